@@ -0,0 +1,138 @@
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+
+/// Output device of the sound system. Samples are always interleaved 16-bit
+/// stereo at the backend's sample rate.
+pub trait AudioBackend {
+	fn sample_rate(&self) -> u32;
+
+	/// Number of stereo frames the backend wants to receive right now. The
+	/// sound system asks once per update.
+	fn frames_needed(&mut self) -> usize;
+
+	fn write(&mut self, samples : &[i16]);
+}
+
+/// Headless backend that writes everything it receives into a WAV file. It
+/// asks for a tic of frames on every update, so the output only depends on
+/// how many times the sound system is updated.
+pub struct WavBackend {
+	writer : BufWriter<File>,
+	sample_rate : u32,
+
+	// Frames per update as a fraction. The remainder is carried between
+	// updates, so the output doesn't drift against the tics.
+	frames_numerator : u64,
+	frames_denominator : u64,
+	frames_remainder : u64,
+
+	data_size : u32
+}
+
+impl WavBackend {
+	/// Creates a WAV file that receives one game tic (1/35 s) of sound on
+	/// every update.
+	pub fn new<P : AsRef<Path>>(path : P, sample_rate : u32) -> io::Result<WavBackend> {
+		let mut backend = WavBackend {
+			writer: BufWriter::new(try!(File::create(path))),
+			sample_rate: sample_rate,
+			frames_numerator: sample_rate as u64,
+			frames_denominator: 35,
+			frames_remainder: 0,
+			data_size: 0
+		};
+		try!(backend.write_header());
+		Ok(backend)
+	}
+
+	pub fn set_frames_per_update(&mut self, frames : usize) {
+		self.frames_numerator = frames as u64;
+		self.frames_denominator = 1;
+		self.frames_remainder = 0;
+	}
+
+	/// Updates the header and flushes the file. The file is also finished
+	/// when the backend is dropped, but errors are ignored there.
+	pub fn finish(&mut self) -> io::Result<()> {
+		try!(self.write_header());
+		try!(self.writer.seek(SeekFrom::End(0)));
+		self.writer.flush()
+	}
+
+	fn write_header(&mut self) -> io::Result<()> {
+		let channels = 2u16;
+		let bits = 16u16;
+		let block_align = channels * bits / 8;
+
+		try!(self.writer.seek(SeekFrom::Start(0)));
+		try!(self.writer.write_all(b"RIFF"));
+		try!(write_u32(&mut self.writer, 36 + self.data_size));
+		try!(self.writer.write_all(b"WAVEfmt "));
+		try!(write_u32(&mut self.writer, 16));
+		try!(write_u16(&mut self.writer, 1));
+		try!(write_u16(&mut self.writer, channels));
+		try!(write_u32(&mut self.writer, self.sample_rate));
+		try!(write_u32(&mut self.writer, self.sample_rate * block_align as u32));
+		try!(write_u16(&mut self.writer, block_align));
+		try!(write_u16(&mut self.writer, bits));
+		try!(self.writer.write_all(b"data"));
+		write_u32(&mut self.writer, self.data_size)
+	}
+}
+
+impl AudioBackend for WavBackend {
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn frames_needed(&mut self) -> usize {
+		let frames = self.frames_numerator + self.frames_remainder;
+		self.frames_remainder = frames % self.frames_denominator;
+		(frames / self.frames_denominator) as usize
+	}
+
+	fn write(&mut self, samples : &[i16]) {
+		for &sample in samples {
+			if write_u16(&mut self.writer, sample as u16).is_err() {
+				return;
+			}
+			self.data_size += 2;
+		}
+	}
+}
+
+impl Drop for WavBackend {
+	fn drop(&mut self) {
+		let _ = self.finish();
+	}
+}
+
+fn write_u16<W : Write>(writer : &mut W, value : u16) -> io::Result<()> {
+	writer.write_all(&[value as u8, (value >> 8) as u8])
+}
+
+fn write_u32<W : Write>(writer : &mut W, value : u32) -> io::Result<()> {
+	writer.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env;
+	use std::fs;
+	use super::*;
+
+	#[test]
+	fn wav_output_keeps_up_with_the_tics() {
+		let path = env::temp_dir().join(format!("doom-wav-test-{}.wav", ::std::process::id()));
+		let mut backend = WavBackend::new(&path, 48000).unwrap();
+		let frames : usize = (0..35 * 10).map(|_| backend.frames_needed()).sum();
+		drop(backend);
+		let _ = fs::remove_file(&path);
+		assert_eq!(frames, 48000 * 10);
+	}
+}
@@ -0,0 +1,147 @@
+use std::io::BufReader;
+use std::io::Read;
+use byteorder::ReadBytesExt;
+use byteorder::LittleEndian;
+use audio::AudioResult;
+use audio::AudioError;
+
+const GENMIDI_HEADER : &'static [u8] = b"#OPL_II#";
+const GENMIDI_NUM_INSTRUMENTS : usize = 175;
+const GENMIDI_INSTRUMENT_SIZE : usize = 36;
+const GENMIDI_NAME_SIZE : usize = 32;
+
+/// The instrument ignores the played note and uses `fixed_note` instead.
+pub const GENMIDI_FLAG_FIXED : u16 = 0x0001;
+/// The instrument plays both of its voices at the same time.
+pub const GENMIDI_FLAG_2VOICE : u16 = 0x0004;
+
+/// First and last percussion key that have an instrument in the bank.
+pub const GENMIDI_FIRST_PERCUSSION : u8 = 35;
+pub const GENMIDI_LAST_PERCUSSION : u8 = 81;
+
+macro_rules! try_read {
+	($e : expr) => {
+		match $e {
+			Ok(value) => value,
+			Err(_) => return Err(AudioError::InvalidLump("GENMIDI"))
+		}
+	}
+}
+
+/// Raw OPL register values of a single operator.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct GenMidiOperator {
+	pub tremolo : u8,
+	pub attack : u8,
+	pub sustain : u8,
+	pub waveform : u8,
+	pub scale : u8,
+	pub level : u8
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct GenMidiVoice {
+	pub modulator : GenMidiOperator,
+	pub feedback : u8,
+	pub carrier : GenMidiOperator,
+	pub base_note_offset : i16
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GenMidiInstrument {
+	pub name : String,
+	pub flags : u16,
+	pub fine_tuning : u8,
+	pub fixed_note : u8,
+	pub voices : [GenMidiVoice; 2]
+}
+
+/// The OPL instrument bank stored in the GENMIDI lump. The first 128
+/// instruments are the General MIDI programs, the rest are the percussion
+/// keys from `GENMIDI_FIRST_PERCUSSION` to `GENMIDI_LAST_PERCUSSION`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GenMidi {
+	pub instruments : Vec<GenMidiInstrument>
+}
+
+impl GenMidi {
+	pub fn new(data : &[u8]) -> AudioResult<GenMidi> {
+		let names_pos = GENMIDI_HEADER.len() + GENMIDI_NUM_INSTRUMENTS * GENMIDI_INSTRUMENT_SIZE;
+		if data.len() < names_pos + GENMIDI_NUM_INSTRUMENTS * GENMIDI_NAME_SIZE {
+			return Err(AudioError::InvalidLump("GENMIDI"));
+		}
+
+		if &data[..GENMIDI_HEADER.len()] != GENMIDI_HEADER {
+			return Err(AudioError::InvalidLump("GENMIDI"));
+		}
+
+		let mut reader = BufReader::new(&data[GENMIDI_HEADER.len()..names_pos]);
+		let mut instruments = Vec::<GenMidiInstrument>::with_capacity(GENMIDI_NUM_INSTRUMENTS);
+
+		for i in 0..GENMIDI_NUM_INSTRUMENTS {
+			let flags = try_read!(reader.read_u16::<LittleEndian>());
+			let fine_tuning = try_read!(reader.read_u8());
+			let fixed_note = try_read!(reader.read_u8());
+			let voice0 = try!(read_voice(&mut reader));
+			let voice1 = try!(read_voice(&mut reader));
+
+			let name_data = &data[names_pos + i * GENMIDI_NAME_SIZE..names_pos + (i + 1) * GENMIDI_NAME_SIZE];
+			let mut name = String::new();
+			for c in name_data {
+				if *c == 0 {
+					break;
+				}
+				name.push(*c as char);
+			}
+
+			instruments.push(GenMidiInstrument {
+				name: name,
+				flags: flags,
+				fine_tuning: fine_tuning,
+				fixed_note: fixed_note,
+				voices: [voice0, voice1]
+			});
+		}
+
+		Ok(GenMidi {
+			instruments: instruments
+		})
+	}
+
+	pub fn melodic(&self, program : u8) -> &GenMidiInstrument {
+		&self.instruments[(program & 0x7F) as usize]
+	}
+
+	pub fn percussion(&self, key : u8) -> Option<&GenMidiInstrument> {
+		if key < GENMIDI_FIRST_PERCUSSION || key > GENMIDI_LAST_PERCUSSION {
+			return None;
+		}
+		Some(&self.instruments[128 + (key - GENMIDI_FIRST_PERCUSSION) as usize])
+	}
+}
+
+fn read_operator<R : Read>(reader : &mut R) -> AudioResult<GenMidiOperator> {
+	Ok(GenMidiOperator {
+		tremolo: try_read!(reader.read_u8()),
+		attack: try_read!(reader.read_u8()),
+		sustain: try_read!(reader.read_u8()),
+		waveform: try_read!(reader.read_u8()),
+		scale: try_read!(reader.read_u8()),
+		level: try_read!(reader.read_u8())
+	})
+}
+
+fn read_voice<R : Read>(reader : &mut R) -> AudioResult<GenMidiVoice> {
+	let modulator = try!(read_operator(reader));
+	let feedback = try_read!(reader.read_u8());
+	let carrier = try!(read_operator(reader));
+	let _unused = try_read!(reader.read_u8());
+	let base_note_offset = try_read!(reader.read_i16::<LittleEndian>());
+
+	Ok(GenMidiVoice {
+		modulator: modulator,
+		feedback: feedback,
+		carrier: carrier,
+		base_note_offset: base_note_offset
+	})
+}
@@ -0,0 +1,15 @@
+pub use self::opl::*;
+pub use self::genmidi::*;
+pub use self::score::*;
+pub use self::oplmusic::*;
+pub use self::sound::*;
+pub use self::backend::*;
+pub use self::sdlbackend::*;
+
+mod opl;
+mod genmidi;
+mod score;
+mod oplmusic;
+mod sound;
+mod backend;
+mod sdlbackend;
@@ -0,0 +1,609 @@
+use std::f64;
+
+/// The native sample rate of the YMF262 and YM3812 chips.
+pub const OPL_SAMPLE_RATE : u32 = 49716;
+
+const NUM_CHANNELS : usize = 18;
+const NUM_OPERATORS : usize = 36;
+
+// Operator slot for each register offset. -1 marks the holes in the map.
+const SLOT_MAP : [i8; 32] = [
+	0, 1, 2, 3, 4, 5, -1, -1, 6, 7, 8, 9, 10, 11, -1, -1,
+	12, 13, 14, 15, 16, 17, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1
+];
+
+// Frequency multipliers, doubled so that 0.5 is representable.
+const MULT_TABLE : [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+const KSL_ROM : [i32; 16] = [0, 32, 40, 45, 48, 51, 53, 55, 56, 58, 59, 60, 61, 62, 63, 64];
+const KSL_SHIFT : [u32; 4] = [8, 1, 2, 0];
+
+// Envelope increments for rates below 48, stepped every 2^shift samples.
+const EG_INC_LOW : [[i32; 8]; 4] = [
+	[0, 1, 0, 1, 0, 1, 0, 1],
+	[0, 1, 0, 1, 1, 1, 0, 1],
+	[0, 1, 1, 1, 0, 1, 1, 1],
+	[0, 1, 1, 1, 1, 1, 1, 1]
+];
+
+// Envelope increments for rates 48-59, stepped every sample.
+const EG_INC_HIGH : [[i32; 8]; 4] = [
+	[1, 1, 1, 1, 1, 1, 1, 1],
+	[1, 1, 1, 2, 1, 1, 1, 2],
+	[1, 2, 1, 2, 1, 2, 1, 2],
+	[1, 2, 2, 2, 1, 2, 2, 2]
+];
+
+const ENVELOPE_MAX : i32 = 0x1FF;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OplMode {
+	Opl2,
+	Opl3
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum EnvelopeState {
+	Attack,
+	Decay,
+	Sustain,
+	Release,
+	Off
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Operator {
+	// Register state
+	tremolo : bool,
+	vibrato : bool,
+	sustain : bool,
+	ksr : bool,
+	mult : u8,
+	ksl : u8,
+	level : u8,
+	attack : u8,
+	decay : u8,
+	sustain_level : u8,
+	release : u8,
+	waveform : u8,
+
+	// Generator state
+	phase : u32,
+	envelope : i32,
+	state : EnvelopeState,
+	out : i32,
+	prev_out : i32
+}
+
+impl Operator {
+	fn new() -> Operator {
+		Operator {
+			tremolo: false,
+			vibrato: false,
+			sustain: false,
+			ksr: false,
+			mult: 0,
+			ksl: 0,
+			level: 0,
+			attack: 0,
+			decay: 0,
+			sustain_level: 0,
+			release: 0,
+			waveform: 0,
+			phase: 0,
+			envelope: ENVELOPE_MAX,
+			state: EnvelopeState::Off,
+			out: 0,
+			prev_out: 0
+		}
+	}
+
+	fn key_on(&mut self) {
+		self.phase = 0;
+		self.state = EnvelopeState::Attack;
+	}
+
+	fn key_off(&mut self) {
+		if self.state != EnvelopeState::Off {
+			self.state = EnvelopeState::Release;
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Channel {
+	fnum : u32,
+	block : u32,
+	key : bool,
+	feedback : u32,
+	additive : bool,
+	left : bool,
+	right : bool
+}
+
+impl Channel {
+	fn new() -> Channel {
+		Channel {
+			fnum: 0,
+			block: 0,
+			key: false,
+			feedback: 0,
+			additive: false,
+			left: true,
+			right: true
+		}
+	}
+}
+
+/// Software emulation of the Yamaha OPL2 (YM3812) and OPL3 (YMF262) FM
+/// synthesis chips.
+///
+/// Samples are generated with integer math from log-sine and exponent tables
+/// that are built with floating point math when the chip is created. The same
+/// register writes always produce the same samples, as long as the platform's
+/// `sin`, `log2` and `powf` round the table entries the same way.
+///
+/// Rhythm mode and the four operator modes are not implemented, as the DMX
+/// music driver never used them.
+pub struct Opl {
+	mode : OplMode,
+	opl3 : bool,
+	waveform_select : bool,
+	note_select : bool,
+	deep_tremolo : bool,
+	deep_vibrato : bool,
+
+	operators : [Operator; NUM_OPERATORS],
+	channels : [Channel; NUM_CHANNELS],
+
+	timer : u32,
+	tremolo_pos : u32,
+	vibrato_pos : u32,
+
+	log_sin : [u32; 256],
+	exp : [u32; 256]
+}
+
+impl Opl {
+	pub fn new(mode : OplMode) -> Opl {
+		let mut log_sin = [0u32; 256];
+		let mut exp = [0u32; 256];
+
+		for i in 0..256 {
+			let x = ((i as f64 + 0.5) * f64::consts::PI / 512.0).sin();
+			log_sin[i] = (-x.log2() * 256.0).round() as u32;
+			exp[i] = (2048.0 * 2f64.powf(-(i as f64 + 1.0) / 256.0)).round() as u32;
+		}
+
+		Opl {
+			mode: mode,
+			opl3: false,
+			waveform_select: false,
+			note_select: false,
+			deep_tremolo: false,
+			deep_vibrato: false,
+			operators: [Operator::new(); NUM_OPERATORS],
+			channels: [Channel::new(); NUM_CHANNELS],
+			timer: 0,
+			tremolo_pos: 0,
+			vibrato_pos: 0,
+			log_sin: log_sin,
+			exp: exp
+		}
+	}
+
+	pub fn mode(&self) -> OplMode {
+		self.mode
+	}
+
+	/// Number of two operator channels the chip currently has available.
+	pub fn num_channels(&self) -> usize {
+		if self.opl3 { NUM_CHANNELS } else { NUM_CHANNELS / 2 }
+	}
+
+	/// Writes a register. Bit 8 of the register selects the second register
+	/// array, which only exists on the OPL3.
+	pub fn write(&mut self, reg : u16, value : u8) {
+		let bank = (reg >> 8) as usize & 1;
+		let reg = (reg & 0xFF) as u8;
+
+		if bank == 1 {
+			if self.mode != OplMode::Opl3 {
+				return;
+			}
+			if reg == 0x05 {
+				self.opl3 = value & 0x01 != 0;
+				return;
+			}
+		}
+
+		match reg {
+			0x01 if bank == 0 => self.waveform_select = value & 0x20 != 0,
+			0x08 if bank == 0 => self.note_select = value & 0x40 != 0,
+			0xBD if bank == 0 => {
+				self.deep_tremolo = value & 0x80 != 0;
+				self.deep_vibrato = value & 0x40 != 0;
+			}
+			0x20..=0x35 | 0x40..=0x55 | 0x60..=0x75 | 0x80..=0x95 | 0xE0..=0xF5 => {
+				let slot = SLOT_MAP[(reg & 0x1F) as usize];
+				if slot >= 0 {
+					self.write_operator(reg & 0xE0, bank * 18 + slot as usize, value);
+				}
+			}
+			0xA0..=0xA8 | 0xB0..=0xB8 | 0xC0..=0xC8 => {
+				self.write_channel(reg & 0xF0, bank * 9 + (reg & 0x0F) as usize, value);
+			}
+			_ => ()
+		}
+	}
+
+	fn write_operator(&mut self, reg : u8, slot : usize, value : u8) {
+		let op = &mut self.operators[slot];
+		match reg {
+			0x20 => {
+				op.tremolo = value & 0x80 != 0;
+				op.vibrato = value & 0x40 != 0;
+				op.sustain = value & 0x20 != 0;
+				op.ksr = value & 0x10 != 0;
+				op.mult = value & 0x0F;
+			}
+			0x40 => {
+				op.ksl = value >> 6;
+				op.level = value & 0x3F;
+			}
+			0x60 => {
+				op.attack = value >> 4;
+				op.decay = value & 0x0F;
+			}
+			0x80 => {
+				op.sustain_level = value >> 4;
+				op.release = value & 0x0F;
+			}
+			_ => op.waveform = value & 0x07
+		}
+	}
+
+	fn write_channel(&mut self, reg : u8, index : usize, value : u8) {
+		match reg {
+			0xA0 => {
+				let channel = &mut self.channels[index];
+				channel.fnum = (channel.fnum & 0x300) | value as u32;
+			}
+			0xB0 => {
+				let key = value & 0x20 != 0;
+				let was_key = {
+					let channel = &mut self.channels[index];
+					channel.fnum = (channel.fnum & 0xFF) | ((value as u32 & 0x03) << 8);
+					channel.block = (value as u32 >> 2) & 0x07;
+					let was_key = channel.key;
+					channel.key = key;
+					was_key
+				};
+
+				let (op1, op2) = channel_operators(index);
+				if key && !was_key {
+					self.operators[op1].key_on();
+					self.operators[op2].key_on();
+				} else if !key && was_key {
+					self.operators[op1].key_off();
+					self.operators[op2].key_off();
+				}
+			}
+			_ => {
+				let channel = &mut self.channels[index];
+				channel.feedback = (value as u32 >> 1) & 0x07;
+				channel.additive = value & 0x01 != 0;
+				channel.left = value & 0x10 != 0;
+				channel.right = value & 0x20 != 0;
+			}
+		}
+	}
+
+	/// Generates interleaved stereo samples at `OPL_SAMPLE_RATE`.
+	pub fn generate(&mut self, buffer : &mut [i16]) {
+		for frame in buffer.chunks_mut(2) {
+			let (left, right) = self.generate_sample();
+			frame[0] = left;
+			if frame.len() > 1 {
+				frame[1] = right;
+			}
+		}
+	}
+
+	/// Generates a single stereo sample at `OPL_SAMPLE_RATE`.
+	pub fn generate_sample(&mut self) -> (i16, i16) {
+		// Low frequency oscillators
+		if self.timer & 0x3F == 0x3F {
+			self.tremolo_pos = (self.tremolo_pos + 1) % 210;
+		}
+		if self.timer & 0x3FF == 0x3FF {
+			self.vibrato_pos = (self.vibrato_pos + 1) & 7;
+		}
+
+		let tremolo_value = if self.tremolo_pos < 105 { self.tremolo_pos } else { 210 - self.tremolo_pos };
+		let tremolo = (tremolo_value >> if self.deep_tremolo { 2 } else { 4 }) as i32;
+
+		let mut left = 0i32;
+		let mut right = 0i32;
+
+		for index in 0..self.num_channels() {
+			let channel = self.channels[index];
+			let (op1, op2) = channel_operators(index);
+
+			let feedback = if channel.feedback != 0 {
+				let op = &self.operators[op1];
+				(op.out + op.prev_out) >> (9 - channel.feedback)
+			} else {
+				0
+			};
+
+			let out1 = self.clock_operator(op1, &channel, feedback, tremolo);
+			let out = if channel.additive {
+				out1 + self.clock_operator(op2, &channel, 0, tremolo)
+			} else {
+				self.clock_operator(op2, &channel, out1, tremolo)
+			};
+
+			let (pan_left, pan_right) = if self.opl3 { (channel.left, channel.right) } else { (true, true) };
+			if pan_left {
+				left += out;
+			}
+			if pan_right {
+				right += out;
+			}
+		}
+
+		self.timer = self.timer.wrapping_add(1);
+
+		(clamp_sample(left), clamp_sample(right))
+	}
+
+	fn clock_operator(&mut self, slot : usize, channel : &Channel, modulation : i32, tremolo : i32) -> i32 {
+		let timer = self.timer;
+		let note_select = self.note_select;
+		let waveform_mask = if self.opl3 { 0x07 } else if self.waveform_select { 0x03 } else { 0x00 };
+
+		// Vibrato
+		let mut fnum = channel.fnum as i32;
+		if self.operators[slot].vibrato {
+			let mut range = (fnum >> 7) & 7;
+			if self.vibrato_pos & 3 == 0 {
+				range = 0;
+			} else if self.vibrato_pos & 1 != 0 {
+				range >>= 1;
+			}
+			if !self.deep_vibrato {
+				range >>= 1;
+			}
+			if self.vibrato_pos & 4 != 0 {
+				range = -range;
+			}
+			fnum += range;
+		}
+		let fnum = (fnum & 0x3FF) as u32;
+
+		let key_scale = if note_select {
+			(channel.block << 1) | ((channel.fnum >> 8) & 1)
+		} else {
+			(channel.block << 1) | ((channel.fnum >> 9) & 1)
+		};
+
+		let op = &mut self.operators[slot];
+
+		// Envelope generator
+		let rate_offset = if op.ksr { key_scale } else { key_scale >> 2 };
+		match op.state {
+			EnvelopeState::Attack => {
+				let rate = envelope_rate(op.attack, rate_offset);
+				if rate >= 60 {
+					op.envelope = 0;
+				} else {
+					let inc = envelope_increment(rate, timer);
+					op.envelope += ((!op.envelope) * inc) >> 3;
+				}
+				if op.envelope <= 0 {
+					op.envelope = 0;
+					op.state = EnvelopeState::Decay;
+				}
+			}
+			EnvelopeState::Decay => {
+				op.envelope += envelope_increment(envelope_rate(op.decay, rate_offset), timer);
+				let sustain_level = if op.sustain_level == 15 { 0x1F0 } else { (op.sustain_level as i32) << 4 };
+				if op.envelope >= sustain_level {
+					op.state = EnvelopeState::Sustain;
+				}
+			}
+			EnvelopeState::Sustain => {
+				if !op.sustain {
+					op.envelope += envelope_increment(envelope_rate(op.release, rate_offset), timer);
+				}
+			}
+			EnvelopeState::Release => {
+				op.envelope += envelope_increment(envelope_rate(op.release, rate_offset), timer);
+			}
+			EnvelopeState::Off => ()
+		}
+		if op.envelope >= ENVELOPE_MAX {
+			op.envelope = ENVELOPE_MAX;
+			if op.state == EnvelopeState::Release || op.state == EnvelopeState::Sustain {
+				op.state = EnvelopeState::Off;
+			}
+		}
+
+		let mut ksl = (KSL_ROM[(fnum >> 6) as usize] << 2) - ((8 - channel.block as i32) << 5);
+		if ksl < 0 {
+			ksl = 0;
+		}
+
+		let mut attenuation = op.envelope + ((op.level as i32) << 2) + (ksl >> KSL_SHIFT[op.ksl as usize]);
+		if op.tremolo {
+			attenuation += tremolo;
+		}
+		if attenuation > ENVELOPE_MAX {
+			attenuation = ENVELOPE_MAX;
+		}
+
+		// Phase generator
+		let phase = ((op.phase >> 9) as i32 + modulation) as u32 & 0x3FF;
+		op.phase = op.phase.wrapping_add((((fnum << channel.block) >> 1) * MULT_TABLE[op.mult as usize]) >> 1);
+
+		op.prev_out = op.out;
+		op.out = waveform_output(&self.log_sin, &self.exp, op.waveform & waveform_mask, phase, attenuation as u32);
+		op.out
+	}
+}
+
+fn channel_operators(index : usize) -> (usize, usize) {
+	let bank = index / 9;
+	let index = index % 9;
+	let op1 = bank * 18 + (index % 3) + (index / 3) * 6;
+	(op1, op1 + 3)
+}
+
+fn envelope_rate(rate : u8, offset : u32) -> u32 {
+	if rate == 0 {
+		return 0;
+	}
+	let rate = (rate as u32) * 4 + offset;
+	if rate > 63 { 63 } else { rate }
+}
+
+fn envelope_increment(rate : u32, timer : u32) -> i32 {
+	if rate == 0 {
+		0
+	} else if rate < 48 {
+		let shift = 12 - (rate >> 2);
+		if timer & ((1 << shift) - 1) != 0 {
+			0
+		} else {
+			EG_INC_LOW[(rate & 3) as usize][((timer >> shift) & 7) as usize]
+		}
+	} else if rate < 60 {
+		EG_INC_HIGH[(rate & 3) as usize][(timer & 7) as usize] << ((rate >> 2) - 12)
+	} else {
+		8
+	}
+}
+
+fn waveform_output(log_sin : &[u32; 256], exp : &[u32; 256], waveform : u8, phase : u32, attenuation : u32) -> i32 {
+	let envelope = attenuation << 3;
+	let silence = 0x1000;
+
+	let (level, negative) = match waveform {
+		// Sine
+		0 => (sine_level(log_sin, phase), phase & 0x200 != 0),
+		// Half sine
+		1 => (if phase & 0x200 != 0 { silence } else { sine_level(log_sin, phase) }, false),
+		// Absolute sine
+		2 => (sine_level(log_sin, phase), false),
+		// Quarter sine
+		3 => (if phase & 0x100 != 0 { silence } else { log_sin[(phase & 0xFF) as usize] }, false),
+		// Alternating sine
+		4 => {
+			if phase & 0x200 != 0 {
+				(silence, false)
+			} else {
+				let phase = phase << 1;
+				(sine_level(log_sin, phase), phase & 0x200 != 0)
+			}
+		}
+		// Camel sine
+		5 => (if phase & 0x200 != 0 { silence } else { sine_level(log_sin, phase << 1) }, false),
+		// Square
+		6 => (0, phase & 0x200 != 0),
+		// Derived square
+		_ => {
+			let negative = phase & 0x200 != 0;
+			let phase = if negative { (phase & 0x1FF) ^ 0x1FF } else { phase & 0x1FF };
+			(phase << 3, negative)
+		}
+	};
+
+	let mut level = level + envelope;
+	if level > 0x1FFF {
+		level = 0x1FFF;
+	}
+
+	let out = ((exp[(level & 0xFF) as usize] << 1) >> (level >> 8)) as i32;
+	if negative { -out } else { out }
+}
+
+fn sine_level(log_sin : &[u32; 256], phase : u32) -> u32 {
+	if phase & 0x100 != 0 {
+		log_sin[(!phase & 0xFF) as usize]
+	} else {
+		log_sin[(phase & 0xFF) as usize]
+	}
+}
+
+fn clamp_sample(value : i32) -> i16 {
+	if value > 32767 {
+		32767
+	} else if value < -32768 {
+		-32768
+	} else {
+		value as i16
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A sine on the carrier of channel 0 at 440Hz, with a silent modulator
+	fn play_tone(opl : &mut Opl) {
+		opl.write(0x20, 0x01);
+		opl.write(0x23, 0x21);
+		opl.write(0x40, 0x3F);
+		opl.write(0x43, 0x00);
+		opl.write(0x60, 0x00);
+		opl.write(0x63, 0xF0);
+		opl.write(0x83, 0x0F);
+		opl.write(0xC0, 0x00);
+		opl.write(0xA0, 0x44);
+		opl.write(0xB0, 0x32);
+	}
+
+	#[test]
+	fn same_writes_give_same_samples() {
+		let mut first = Opl::new(OplMode::Opl2);
+		let mut second = Opl::new(OplMode::Opl2);
+		play_tone(&mut first);
+		play_tone(&mut second);
+
+		let mut first_samples = vec![0i16; 8192];
+		let mut second_samples = vec![0i16; 8192];
+		first.generate(&mut first_samples);
+		second.generate(&mut second_samples);
+
+		assert!(first_samples.iter().any(|&sample| sample != 0));
+		assert_eq!(first_samples, second_samples);
+	}
+
+	#[test]
+	fn tone_has_its_frequency() {
+		let mut opl = Opl::new(OplMode::Opl2);
+		play_tone(&mut opl);
+
+		// A second of samples, counting the rising zero crossings of the left
+		// channel.
+		let mut samples = vec![0i16; OPL_SAMPLE_RATE as usize * 2];
+		opl.generate(&mut samples);
+		let left : Vec<i16> = samples.chunks(2).map(|frame| frame[0]).collect();
+		let crossings = left.windows(2).filter(|pair| pair[0] < 0 && pair[1] >= 0).count();
+		assert!(crossings >= 439 && crossings <= 441, "{} crossings", crossings);
+	}
+
+	#[test]
+	fn key_off_releases_to_silence() {
+		let mut opl = Opl::new(OplMode::Opl2);
+		play_tone(&mut opl);
+		let mut samples = vec![0i16; 2048];
+		opl.generate(&mut samples);
+
+		opl.write(0xB0, 0x12);
+		let mut release = vec![0i16; OPL_SAMPLE_RATE as usize];
+		opl.generate(&mut release);
+		assert!(release[release.len() - 2048..].iter().all(|&sample| sample == 0));
+	}
+}
@@ -0,0 +1,657 @@
+use std::f64;
+use audio::Opl;
+use audio::OplMode;
+use audio::OPL_SAMPLE_RATE;
+use audio::GenMidi;
+use audio::GenMidiOperator;
+use audio::GenMidiVoice;
+use audio::GENMIDI_FLAG_FIXED;
+use audio::GENMIDI_FLAG_2VOICE;
+use audio::GENMIDI_FIRST_PERCUSSION;
+use audio::Score;
+use audio::MusicEvent;
+use audio::PERCUSSION_CHANNEL;
+use audio::CONTROLLER_VOLUME;
+use audio::CONTROLLER_PAN;
+use audio::CONTROLLER_RESET;
+use audio::CONTROLLER_ALL_SOUNDS_OFF;
+use audio::CONTROLLER_ALL_NOTES_OFF;
+
+const OPL_REGS_TREMOLO : u16 = 0x20;
+const OPL_REGS_LEVEL : u16 = 0x40;
+const OPL_REGS_ATTACK : u16 = 0x60;
+const OPL_REGS_SUSTAIN : u16 = 0x80;
+const OPL_REGS_WAVEFORM : u16 = 0xE0;
+const OPL_REGS_FREQ_1 : u16 = 0xA0;
+const OPL_REGS_FREQ_2 : u16 = 0xB0;
+const OPL_REGS_FEEDBACK : u16 = 0xC0;
+
+// Operator register offsets of the first operator of each channel.
+const VOICE_OPERATORS : [u16; 9] = [0x00, 0x01, 0x02, 0x08, 0x09, 0x0A, 0x10, 0x11, 0x12];
+
+// Maps MIDI volumes to OPL attenuation like the DMX library does.
+const VOLUME_MAPPING : [u32; 128] = [
+	0, 1, 3, 5, 6, 8, 10, 11,
+	13, 14, 16, 17, 19, 20, 22, 23,
+	25, 26, 27, 29, 30, 32, 33, 34,
+	36, 37, 39, 41, 43, 45, 47, 49,
+	50, 52, 54, 55, 57, 59, 60, 61,
+	63, 64, 66, 67, 68, 69, 71, 72,
+	73, 74, 75, 76, 77, 79, 80, 81,
+	82, 83, 84, 84, 85, 86, 87, 88,
+	89, 90, 91, 92, 92, 93, 94, 95,
+	96, 96, 97, 98, 99, 99, 100, 101,
+	101, 102, 103, 103, 104, 105, 105, 106,
+	107, 107, 108, 109, 109, 110, 110, 111,
+	112, 112, 113, 113, 114, 114, 115, 115,
+	116, 117, 117, 118, 118, 119, 119, 120,
+	120, 121, 121, 122, 122, 123, 123, 123,
+	124, 124, 125, 125, 126, 126, 127, 127
+];
+
+// Frequencies are looked up in 1/32 semitone steps. The first entries are
+// for notes below the lowest octave, the rest cover a single octave that is
+// shifted with the block number.
+const FREQ_LOW_NOTES : usize = 284;
+const FREQ_OCTAVE : usize = 12 * 32;
+
+#[derive(Debug, Copy, Clone)]
+struct Voice {
+	index : u16,
+	array : u16,
+	op1 : u16,
+	op2 : u16,
+
+	channel : usize,
+	key : u8,
+	note : u8,
+	note_volume : u32,
+	instrument : Option<(usize, usize)>,
+	car_volume : u8,
+	mod_volume : u8,
+	reg_pan : u8,
+	freq : u32
+}
+
+#[derive(Debug, Copy, Clone)]
+struct MidiChannel {
+	program : u8,
+	volume : u32,
+	volume_base : u32,
+	pan : u8,
+	bend : i32
+}
+
+/// Plays a `Score` through an emulated OPL chip using the GENMIDI
+/// instruments, allocating voices and mapping volumes like the DMX sound
+/// library of vanilla Doom. The note frequencies are computed rather than
+/// taken from the DMX table, see `build_frequency_curve`.
+///
+/// The output is rendered into interleaved 16-bit stereo buffers at any
+/// sample rate. Rendering is fully deterministic.
+pub struct OplMusic {
+	opl : Opl,
+	genmidi : GenMidi,
+	score : Score,
+	looping : bool,
+
+	sample_rate : u32,
+	music_volume : u32,
+	frequency_curve : Vec<u32>,
+
+	voices : Vec<Voice>,
+	free_voices : Vec<usize>,
+	allocated_voices : Vec<usize>,
+	channels : [MidiChannel; 16],
+
+	// Playback position
+	next_event : usize,
+	loop_start : u64,
+	samples_played : u64,
+	finished : bool,
+
+	// Resampling state, in 1/OPL_SAMPLE_RATE steps of the output rate.
+	resample_pos : u64,
+	prev_sample : (i16, i16),
+	cur_sample : (i16, i16)
+}
+
+impl OplMusic {
+	pub fn new(genmidi : GenMidi, score : Score, sample_rate : u32, mode : OplMode) -> OplMusic {
+		let mut music = OplMusic {
+			opl: Opl::new(mode),
+			genmidi: genmidi,
+			score: score,
+			looping: false,
+			sample_rate: sample_rate,
+			music_volume: 127,
+			frequency_curve: build_frequency_curve(),
+			voices: Vec::<Voice>::new(),
+			free_voices: Vec::<usize>::new(),
+			allocated_voices: Vec::<usize>::new(),
+			channels: [MidiChannel {
+				program: 0,
+				volume: 100,
+				volume_base: 100,
+				pan: 64,
+				bend: 0
+			}; 16],
+			next_event: 0,
+			loop_start: 0,
+			samples_played: 0,
+			finished: false,
+			resample_pos: 0,
+			prev_sample: (0, 0),
+			cur_sample: (0, 0)
+		};
+
+		music.init_registers();
+
+		let num_voices = music.opl.num_channels();
+		for i in 0..num_voices {
+			let array = if i >= 9 { 0x100 } else { 0x000 };
+			let index = (i % 9) as u16;
+			music.voices.push(Voice {
+				index: index,
+				array: array,
+				op1: VOICE_OPERATORS[index as usize] | array,
+				op2: (VOICE_OPERATORS[index as usize] + 3) | array,
+				channel: 0,
+				key: 0,
+				note: 0,
+				note_volume: 0,
+				instrument: None,
+				car_volume: 0,
+				mod_volume: 0,
+				reg_pan: 0x30,
+				freq: 0
+			});
+			music.free_voices.push(i);
+		}
+
+		music
+	}
+
+	pub fn set_looping(&mut self, looping : bool) {
+		self.looping = looping;
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.finished
+	}
+
+	/// Sets the music volume in the range 0-127.
+	pub fn set_volume(&mut self, volume : u32) {
+		self.music_volume = if volume > 127 { 127 } else { volume };
+
+		for channel in 0..16 {
+			let volume_base = self.channels[channel].volume_base;
+			self.set_channel_volume(channel, volume_base);
+		}
+	}
+
+	/// Renders interleaved stereo samples. Silence is written once the song
+	/// has ended and looping is off.
+	pub fn render(&mut self, buffer : &mut [i16]) {
+		for frame in buffer.chunks_mut(2) {
+			self.process_events();
+
+			// Step the chip until it has passed the output sample.
+			while self.resample_pos >= self.sample_rate as u64 {
+				self.resample_pos -= self.sample_rate as u64;
+				self.prev_sample = self.cur_sample;
+				self.cur_sample = self.opl.generate_sample();
+			}
+
+			let t = self.resample_pos as i64;
+			let n = self.sample_rate as i64;
+			let left = (self.prev_sample.0 as i64 * (n - t) + self.cur_sample.0 as i64 * t) / n;
+			let right = (self.prev_sample.1 as i64 * (n - t) + self.cur_sample.1 as i64 * t) / n;
+
+			frame[0] = left as i16;
+			if frame.len() > 1 {
+				frame[1] = right as i16;
+			}
+
+			self.resample_pos += OPL_SAMPLE_RATE as u64;
+			self.samples_played += 1;
+		}
+	}
+
+	fn process_events(&mut self) {
+		if self.finished {
+			return;
+		}
+
+		let time = self.samples_played * 1000000 / self.sample_rate as u64;
+		loop {
+			if self.next_event >= self.score.events.len() {
+				// A song of no length ends after one pass, as looping it
+				// would replay its events forever without time passing.
+				let looping = self.looping && self.score.length > 0;
+				if looping && time >= self.loop_start + self.score.length {
+					self.all_notes_off();
+					self.loop_start += self.score.length;
+					self.next_event = 0;
+					if self.score.events.is_empty() {
+						return;
+					}
+					continue;
+				}
+				if !looping {
+					self.all_notes_off();
+					self.finished = true;
+				}
+				return;
+			}
+
+			let event = self.score.events[self.next_event];
+			if self.loop_start + event.time > time {
+				return;
+			}
+
+			self.next_event += 1;
+			self.handle_event(event.event);
+		}
+	}
+
+	fn handle_event(&mut self, event : MusicEvent) {
+		match event {
+			MusicEvent::NoteOff { channel, key } => self.key_off(channel as usize, key),
+			MusicEvent::NoteOn { channel, key, velocity } => {
+				if velocity == 0 {
+					self.key_off(channel as usize, key);
+				} else {
+					self.key_on(channel as usize, key, velocity as u32);
+				}
+			}
+			MusicEvent::ProgramChange { channel, program } => self.channels[channel as usize].program = program,
+			MusicEvent::PitchBend { channel, value } => {
+				let channel = channel as usize;
+				self.channels[channel].bend = (value >> 7) as i32 - 64;
+				for i in 0..self.allocated_voices.len() {
+					let voice = self.allocated_voices[i];
+					if self.voices[voice].channel == channel {
+						self.update_voice_frequency(voice);
+					}
+				}
+			}
+			MusicEvent::Controller { channel, controller, value } => {
+				let channel = channel as usize;
+				match controller {
+					CONTROLLER_VOLUME => self.set_channel_volume(channel, value as u32),
+					CONTROLLER_PAN => self.set_channel_pan(channel, value),
+					CONTROLLER_RESET => self.channels[channel].bend = 0,
+					CONTROLLER_ALL_SOUNDS_OFF | CONTROLLER_ALL_NOTES_OFF => self.channel_notes_off(channel),
+					_ => ()
+				}
+			}
+		}
+	}
+
+	fn init_registers(&mut self) {
+		if self.opl.mode() == OplMode::Opl3 {
+			self.opl.write(0x105, 0x01);
+		}
+
+		for array in &[0x000u16, 0x100u16] {
+			for r in 0..0x16u16 {
+				self.opl.write((OPL_REGS_LEVEL + r) | array, 0x3F);
+				self.opl.write((OPL_REGS_ATTACK + r) | array, 0x00);
+				self.opl.write((OPL_REGS_SUSTAIN + r) | array, 0x00);
+				self.opl.write((OPL_REGS_TREMOLO + r) | array, 0x00);
+				self.opl.write((OPL_REGS_WAVEFORM + r) | array, 0x00);
+			}
+			for r in 0..9u16 {
+				self.opl.write((OPL_REGS_FREQ_1 + r) | array, 0x00);
+				self.opl.write((OPL_REGS_FREQ_2 + r) | array, 0x00);
+				self.opl.write((OPL_REGS_FEEDBACK + r) | array, 0x30);
+			}
+		}
+
+		// Enable waveform selection and the keyboard split point.
+		self.opl.write(0x01, 0x20);
+		self.opl.write(0x08, 0x40);
+	}
+
+	fn key_on(&mut self, channel : usize, key : u8, velocity : u32) {
+		let instrument = if channel == PERCUSSION_CHANNEL as usize {
+			match self.genmidi.percussion(key) {
+				Some(_) => 128 + (key - GENMIDI_FIRST_PERCUSSION) as usize,
+				None => return
+			}
+		} else {
+			self.channels[channel].program as usize
+		};
+
+		let flags = self.genmidi.instruments[instrument].flags;
+		self.voice_key_on(channel, instrument, 0, key, velocity);
+		if flags & GENMIDI_FLAG_2VOICE != 0 {
+			self.voice_key_on(channel, instrument, 1, key, velocity);
+		}
+	}
+
+	fn key_off(&mut self, channel : usize, key : u8) {
+		let mut i = 0;
+		while i < self.allocated_voices.len() {
+			let voice = self.allocated_voices[i];
+			if self.voices[voice].channel == channel && self.voices[voice].key == key {
+				self.voice_key_off(voice);
+				self.release_voice(i);
+			} else {
+				i += 1;
+			}
+		}
+	}
+
+	fn channel_notes_off(&mut self, channel : usize) {
+		let mut i = 0;
+		while i < self.allocated_voices.len() {
+			let voice = self.allocated_voices[i];
+			if self.voices[voice].channel == channel {
+				self.voice_key_off(voice);
+				self.release_voice(i);
+			} else {
+				i += 1;
+			}
+		}
+	}
+
+	fn all_notes_off(&mut self) {
+		while !self.allocated_voices.is_empty() {
+			let voice = self.allocated_voices[0];
+			self.voice_key_off(voice);
+			self.release_voice(0);
+		}
+	}
+
+	fn release_voice(&mut self, allocated_index : usize) {
+		let voice = self.allocated_voices.remove(allocated_index);
+		self.free_voices.push(voice);
+	}
+
+	fn allocate_voice(&mut self) -> usize {
+		if self.free_voices.is_empty() {
+			// Steal the oldest voice, but prefer the second voices of double
+			// voice instruments, as they matter less.
+			let mut steal = 0;
+			for (i, &voice) in self.allocated_voices.iter().enumerate() {
+				if let Some((_, 1)) = self.voices[voice].instrument {
+					steal = i;
+					break;
+				}
+			}
+			let voice = self.allocated_voices[steal];
+			self.voice_key_off(voice);
+			self.release_voice(steal);
+		}
+
+		let voice = self.free_voices.remove(0);
+		self.allocated_voices.push(voice);
+		voice
+	}
+
+	fn voice_key_on(&mut self, channel : usize, instrument : usize, instrument_voice : usize, key : u8, velocity : u32) {
+		let voice = self.allocate_voice();
+
+		let (flags, fixed_note) = {
+			let instr = &self.genmidi.instruments[instrument];
+			(instr.flags, instr.fixed_note)
+		};
+
+		{
+			let v = &mut self.voices[voice];
+			v.channel = channel;
+			v.key = key;
+			v.note = if flags & GENMIDI_FLAG_FIXED != 0 { fixed_note } else { key };
+			v.reg_pan = pan_register(self.channels[channel].pan);
+		}
+
+		self.set_voice_instrument(voice, instrument, instrument_voice);
+		self.set_voice_volume(voice, velocity);
+
+		self.voices[voice].freq = 0;
+		self.update_voice_frequency(voice);
+	}
+
+	fn voice_key_off(&mut self, voice : usize) {
+		let v = self.voices[voice];
+		self.opl.write((OPL_REGS_FREQ_2 + v.index) | v.array, (v.freq >> 8) as u8);
+	}
+
+	fn set_voice_instrument(&mut self, voice : usize, instrument : usize, instrument_voice : usize) {
+		if self.voices[voice].instrument == Some((instrument, instrument_voice)) {
+			return;
+		}
+
+		let data = self.genmidi.instruments[instrument].voices[instrument_voice];
+		let v = self.voices[voice];
+
+		// Additive instruments need the volume on both operators.
+		let modulating = data.feedback & 0x01 == 0;
+
+		let car_volume = self.load_operator(v.op2, &data.carrier, true);
+		let mod_volume = self.load_operator(v.op1, &data.modulator, !modulating);
+		self.opl.write((OPL_REGS_FEEDBACK + v.index) | v.array, data.feedback | v.reg_pan);
+
+		let v = &mut self.voices[voice];
+		v.car_volume = car_volume;
+		v.mod_volume = mod_volume;
+		v.instrument = Some((instrument, instrument_voice));
+	}
+
+	fn load_operator(&mut self, op : u16, data : &GenMidiOperator, max_level : bool) -> u8 {
+		let level = if max_level { data.scale | 0x3F } else { data.scale | data.level };
+
+		self.opl.write(OPL_REGS_LEVEL + op, level);
+		self.opl.write(OPL_REGS_TREMOLO + op, data.tremolo);
+		self.opl.write(OPL_REGS_ATTACK + op, data.attack);
+		self.opl.write(OPL_REGS_SUSTAIN + op, data.sustain);
+		self.opl.write(OPL_REGS_WAVEFORM + op, data.waveform);
+
+		level
+	}
+
+	fn set_voice_volume(&mut self, voice : usize, volume : u32) {
+		let v = self.voices[voice];
+		let data = match v.instrument {
+			Some((instrument, instrument_voice)) => self.genmidi.instruments[instrument].voices[instrument_voice],
+			None => return
+		};
+
+		self.voices[voice].note_volume = volume;
+
+		let midi_volume = 2 * (VOLUME_MAPPING[self.channels[v.channel].volume as usize] + 1);
+		let full_volume = (VOLUME_MAPPING[volume as usize] * midi_volume) >> 9;
+		let car_volume = (0x3F - full_volume) as u8;
+
+		if car_volume == v.car_volume & 0x3F {
+			return;
+		}
+
+		let car_volume = car_volume | (v.car_volume & 0xC0);
+		self.voices[voice].car_volume = car_volume;
+		self.opl.write(OPL_REGS_LEVEL + v.op2, car_volume);
+
+		// The modulator is only heard directly in additive instruments.
+		if data.feedback & 0x01 != 0 && data.modulator.level != 0x3F {
+			let mut mod_volume = data.modulator.level;
+			if mod_volume < car_volume & 0x3F {
+				mod_volume = car_volume & 0x3F;
+			}
+			let mod_volume = mod_volume | (v.mod_volume & 0xC0);
+			if mod_volume != v.mod_volume {
+				self.voices[voice].mod_volume = mod_volume;
+				self.opl.write(OPL_REGS_LEVEL + v.op1, mod_volume);
+			}
+		}
+	}
+
+	fn set_channel_volume(&mut self, channel : usize, volume : u32) {
+		self.channels[channel].volume_base = volume;
+		self.channels[channel].volume = volume * self.music_volume / 127;
+
+		for i in 0..self.allocated_voices.len() {
+			let voice = self.allocated_voices[i];
+			if self.voices[voice].channel == channel {
+				let note_volume = self.voices[voice].note_volume;
+				self.set_voice_volume(voice, note_volume);
+			}
+		}
+	}
+
+	fn set_channel_pan(&mut self, channel : usize, pan : u8) {
+		if self.channels[channel].pan == pan {
+			return;
+		}
+		self.channels[channel].pan = pan;
+
+		if self.opl.mode() != OplMode::Opl3 {
+			return;
+		}
+
+		let reg_pan = pan_register(pan);
+		for i in 0..self.allocated_voices.len() {
+			let voice = self.allocated_voices[i];
+			let v = self.voices[voice];
+			if v.channel != channel {
+				continue;
+			}
+			if let Some((instrument, instrument_voice)) = v.instrument {
+				let feedback = self.genmidi.instruments[instrument].voices[instrument_voice].feedback;
+				self.opl.write((OPL_REGS_FEEDBACK + v.index) | v.array, feedback | reg_pan);
+				self.voices[voice].reg_pan = reg_pan;
+			}
+		}
+	}
+
+	fn update_voice_frequency(&mut self, voice : usize) {
+		let freq = self.voice_frequency(voice);
+		let v = self.voices[voice];
+		if v.freq != freq {
+			self.opl.write((OPL_REGS_FREQ_1 + v.index) | v.array, (freq & 0xFF) as u8);
+			self.opl.write((OPL_REGS_FREQ_2 + v.index) | v.array, ((freq >> 8) as u8) | 0x20);
+			self.voices[voice].freq = freq;
+		}
+	}
+
+	fn voice_frequency(&self, voice : usize) -> u32 {
+		let v = &self.voices[voice];
+		let (instrument, instrument_voice) = match v.instrument {
+			Some(value) => value,
+			None => return 0
+		};
+		let instr = &self.genmidi.instruments[instrument];
+		let data : &GenMidiVoice = &instr.voices[instrument_voice];
+
+		let mut note = v.note as i32;
+		if instr.flags & GENMIDI_FLAG_FIXED == 0 {
+			note += data.base_note_offset as i32;
+		}
+		while note < 0 {
+			note += 12;
+		}
+		while note > 95 {
+			note -= 12;
+		}
+
+		let mut freq_index = 64 + 32 * note + self.channels[v.channel].bend;
+		if instrument_voice != 0 {
+			freq_index += (instr.fine_tuning as i32 / 2) - 64;
+		}
+		if freq_index < 0 {
+			freq_index = 0;
+		}
+
+		let freq_index = freq_index as usize;
+		if freq_index < FREQ_LOW_NOTES {
+			return self.frequency_curve[freq_index];
+		}
+
+		let sub_index = (freq_index - FREQ_LOW_NOTES) % FREQ_OCTAVE;
+		let mut octave = (freq_index - FREQ_LOW_NOTES) / FREQ_OCTAVE;
+		if octave >= 7 {
+			octave = 7;
+		}
+
+		self.frequency_curve[sub_index + FREQ_LOW_NOTES] | ((octave as u32) << 10)
+	}
+}
+
+fn pan_register(pan : u8) -> u8 {
+	if pan >= 96 {
+		0x20
+	} else if pan <= 48 {
+		0x10
+	} else {
+		0x30
+	}
+}
+
+/// Builds an F-number table laid out like the one of DMX. Index 64 is note
+/// 0, tuned to C0 (16.35Hz). All entries are for block 0. The entries are
+/// computed for equal temperament instead of copied from DMX, so some notes
+/// can be an F-number step off from vanilla.
+fn build_frequency_curve() -> Vec<u32> {
+	let c0 = 440.0 * 2f64.powf(-57.0 / 12.0);
+	let mut curve = Vec::<u32>::with_capacity(FREQ_LOW_NOTES + FREQ_OCTAVE);
+
+	for i in 0..(FREQ_LOW_NOTES + FREQ_OCTAVE) {
+		let freq = c0 * 2f64.powf((i as f64 - 64.0) / FREQ_OCTAVE as f64);
+		let fnum = (freq * (1 << 20) as f64 / OPL_SAMPLE_RATE as f64).round() as u32;
+		curve.push(if fnum > 0x3FF { 0x3FF } else { fnum });
+	}
+
+	curve
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use audio::ScoreEvent;
+
+	// A bank of silent instruments
+	fn genmidi() -> GenMidi {
+		let mut data = b"#OPL_II#".to_vec();
+		data.resize(8 + 175 * (36 + 32), 0);
+		GenMidi::new(&data).unwrap()
+	}
+
+	fn note(time : u64, on : bool) -> ScoreEvent {
+		ScoreEvent {
+			time: time,
+			event: if on { MusicEvent::NoteOn { channel: 0, key: 60, velocity: 100 } } else { MusicEvent::NoteOff { channel: 0, key: 60 } }
+		}
+	}
+
+	#[test]
+	fn song_finishes_after_its_last_event() {
+		let score = Score { events: vec![note(0, true), note(5000, false)], length: 10000 };
+		let mut music = OplMusic::new(genmidi(), score, 1000, OplMode::Opl2);
+		let mut buffer = [0i16; 2 * 5];
+		music.render(&mut buffer);
+		assert!(!music.is_finished());
+		music.render(&mut buffer);
+		assert!(music.is_finished());
+	}
+
+	#[test]
+	fn looping_song_does_not_finish() {
+		let score = Score { events: vec![note(0, true), note(5000, false)], length: 10000 };
+		let mut music = OplMusic::new(genmidi(), score, 1000, OplMode::Opl2);
+		music.set_looping(true);
+		let mut buffer = [0i16; 2 * 100];
+		music.render(&mut buffer);
+		assert!(!music.is_finished());
+	}
+
+	#[test]
+	fn looping_song_of_no_length_finishes() {
+		let score = Score { events: vec![note(0, true), note(0, false)], length: 0 };
+		let mut music = OplMusic::new(genmidi(), score, 1000, OplMode::Opl2);
+		music.set_looping(true);
+		let mut buffer = [0i16; 2 * 10];
+		music.render(&mut buffer);
+		assert!(music.is_finished());
+	}
+}
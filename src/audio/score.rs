@@ -0,0 +1,503 @@
+pub type AudioResult<T> = Result<T, AudioError>;
+
+#[derive(Debug)]
+pub enum AudioError {
+	UnknownFormat,
+	InvalidLump(&'static str),
+	InvalidScore(&'static str)
+}
+
+/// MIDI channel used for percussion.
+pub const PERCUSSION_CHANNEL : u8 = 9;
+
+// MIDI controller numbers used by the players.
+pub const CONTROLLER_BANK : u8 = 0;
+pub const CONTROLLER_MODULATION : u8 = 1;
+pub const CONTROLLER_VOLUME : u8 = 7;
+pub const CONTROLLER_PAN : u8 = 10;
+pub const CONTROLLER_EXPRESSION : u8 = 11;
+pub const CONTROLLER_SUSTAIN : u8 = 64;
+pub const CONTROLLER_SOFT : u8 = 67;
+pub const CONTROLLER_REVERB : u8 = 91;
+pub const CONTROLLER_CHORUS : u8 = 93;
+pub const CONTROLLER_ALL_SOUNDS_OFF : u8 = 120;
+pub const CONTROLLER_RESET : u8 = 121;
+pub const CONTROLLER_ALL_NOTES_OFF : u8 = 123;
+pub const CONTROLLER_MONO : u8 = 126;
+pub const CONTROLLER_POLY : u8 = 127;
+
+// MUS controller numbers mapped to MIDI. Controller 0 is the program change.
+const MUS_CONTROLLERS : [u8; 15] = [
+	0, CONTROLLER_BANK, CONTROLLER_MODULATION, CONTROLLER_VOLUME, CONTROLLER_PAN,
+	CONTROLLER_EXPRESSION, CONTROLLER_REVERB, CONTROLLER_CHORUS, CONTROLLER_SUSTAIN, CONTROLLER_SOFT,
+	CONTROLLER_ALL_SOUNDS_OFF, CONTROLLER_ALL_NOTES_OFF, CONTROLLER_MONO, CONTROLLER_POLY, CONTROLLER_RESET
+];
+
+const MUS_TICKS_PER_SECOND : u64 = 140;
+const MIDI_DEFAULT_TEMPO : u64 = 500000;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MusicEvent {
+	NoteOff { channel : u8, key : u8 },
+	NoteOn { channel : u8, key : u8, velocity : u8 },
+	Controller { channel : u8, controller : u8, value : u8 },
+	ProgramChange { channel : u8, program : u8 },
+	/// 14-bit pitch bend value, 0x2000 is the center.
+	PitchBend { channel : u8, value : u16 }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ScoreEvent {
+	/// Time since the start of the song in microseconds.
+	pub time : u64,
+	pub event : MusicEvent
+}
+
+/// A song as a flat list of MIDI channel events. Both MUS and MIDI lumps are
+/// converted into this, so the players don't need to care about the format.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Score {
+	pub events : Vec<ScoreEvent>,
+	/// Length of the song in microseconds.
+	pub length : u64
+}
+
+impl Score {
+	/// Loads a MUS or a standard MIDI file, depending on the signature.
+	pub fn new(data : &[u8]) -> AudioResult<Score> {
+		if data.starts_with(b"MUS\x1A") {
+			Score::from_mus(data)
+		} else if data.starts_with(b"MThd") {
+			Score::from_midi(data)
+		} else {
+			Err(AudioError::UnknownFormat)
+		}
+	}
+
+	pub fn from_mus(data : &[u8]) -> AudioResult<Score> {
+		let mut header = ByteReader::new(data);
+		if header.bytes(4) != Some(b"MUS\x1A") {
+			return Err(AudioError::InvalidScore("MUS signature"));
+		}
+		let score_len = try!(header.u16_le()) as usize;
+		let score_start = try!(header.u16_le()) as usize;
+		if score_start > data.len() {
+			return Err(AudioError::InvalidScore("MUS header"));
+		}
+
+		let score_end = if score_start + score_len > data.len() { data.len() } else { score_start + score_len };
+		let mut reader = ByteReader::new(&data[score_start..score_end]);
+
+		let mut events = Vec::<ScoreEvent>::new();
+		let mut volumes = [127u8; 16];
+		let mut ticks = 0u64;
+
+		loop {
+			let descriptor = match reader.u8() {
+				Ok(value) => value,
+				// Some PWADs have a truncated score without the end event.
+				Err(_) => break
+			};
+
+			let last = descriptor & 0x80 != 0;
+			let mus_channel = descriptor & 0x0F;
+			let channel = mus_channel_to_midi(mus_channel);
+			let time = ticks * 1000000 / MUS_TICKS_PER_SECOND;
+
+			let event = match (descriptor >> 4) & 0x07 {
+				0 => {
+					let key = try!(reader.u8()) & 0x7F;
+					Some(MusicEvent::NoteOff { channel: channel, key: key })
+				}
+				1 => {
+					let key = try!(reader.u8());
+					if key & 0x80 != 0 {
+						volumes[mus_channel as usize] = try!(reader.u8()) & 0x7F;
+					}
+					Some(MusicEvent::NoteOn { channel: channel, key: key & 0x7F, velocity: volumes[mus_channel as usize] })
+				}
+				2 => {
+					let bend = try!(reader.u8()) as u16;
+					Some(MusicEvent::PitchBend { channel: channel, value: bend << 6 })
+				}
+				3 => {
+					let controller = try!(reader.u8());
+					if controller < 10 || controller as usize >= MUS_CONTROLLERS.len() {
+						None
+					} else {
+						Some(MusicEvent::Controller { channel: channel, controller: MUS_CONTROLLERS[controller as usize], value: 0 })
+					}
+				}
+				4 => {
+					let controller = try!(reader.u8());
+					let value = try!(reader.u8()) & 0x7F;
+					if controller == 0 {
+						Some(MusicEvent::ProgramChange { channel: channel, program: value })
+					} else if controller < 10 {
+						Some(MusicEvent::Controller { channel: channel, controller: MUS_CONTROLLERS[controller as usize], value: value })
+					} else {
+						None
+					}
+				}
+				5 => None,
+				6 => break,
+				_ => return Err(AudioError::InvalidScore("MUS event"))
+			};
+
+			if let Some(event) = event {
+				events.push(ScoreEvent { time: time, event: event });
+			}
+
+			if last {
+				let mut delay = 0u64;
+				loop {
+					let byte = try!(reader.u8());
+					delay = (delay << 7) | (byte & 0x7F) as u64;
+					if byte & 0x80 == 0 {
+						break;
+					}
+				}
+				ticks += delay;
+			}
+		}
+
+		Ok(Score {
+			events: events,
+			length: ticks * 1000000 / MUS_TICKS_PER_SECOND
+		})
+	}
+
+	pub fn from_midi(data : &[u8]) -> AudioResult<Score> {
+		let mut reader = ByteReader::new(data);
+
+		if reader.bytes(4) != Some(b"MThd") {
+			return Err(AudioError::InvalidScore("MIDI signature"));
+		}
+		let header_len = try!(reader.u32_be()) as usize;
+		let header_data = match reader.bytes(header_len) {
+			Some(value) => value,
+			None => return Err(AudioError::InvalidScore("MIDI header"))
+		};
+		let mut header = ByteReader::new(header_data);
+		let format = try!(header.u16_be());
+		let num_tracks = try!(header.u16_be());
+		let division = try!(header.u16_be());
+
+		if format > 1 {
+			return Err(AudioError::InvalidScore("MIDI format"));
+		}
+		try!(midi_tick_length(division, MIDI_DEFAULT_TEMPO));
+
+		// Read all the tracks with their times in ticks.
+		let mut tick_events = Vec::<(u64, usize, TrackEvent)>::new();
+		let mut sequence = 0usize;
+		for _ in 0..num_tracks {
+			let chunk_id = reader.bytes(4);
+			let chunk_len = try!(reader.u32_be()) as usize;
+			let chunk = match reader.bytes(chunk_len) {
+				Some(value) => value,
+				None => return Err(AudioError::InvalidScore("MIDI track"))
+			};
+			if chunk_id != Some(b"MTrk") {
+				continue;
+			}
+
+			let mut track = ByteReader::new(chunk);
+			let mut ticks = 0u64;
+			let mut running_status = 0u8;
+
+			while !track.is_empty() {
+				ticks += try!(track.varlen());
+
+				let mut status = try!(track.u8());
+				if status & 0x80 == 0 {
+					if running_status == 0 {
+						return Err(AudioError::InvalidScore("MIDI running status"));
+					}
+					track.unread();
+					status = running_status;
+				}
+
+				let event = match status {
+					0xFF => {
+						running_status = 0;
+						let meta_type = try!(track.u8());
+						let len = try!(track.varlen()) as usize;
+						let meta = match track.bytes(len) {
+							Some(value) => value,
+							None => return Err(AudioError::InvalidScore("MIDI meta event"))
+						};
+						match meta_type {
+							0x2F => break,
+							0x51 if len == 3 => Some(TrackEvent::Tempo(((meta[0] as u64) << 16) | ((meta[1] as u64) << 8) | meta[2] as u64)),
+							_ => None
+						}
+					}
+					0xF0 | 0xF7 => {
+						running_status = 0;
+						let len = try!(track.varlen()) as usize;
+						if track.bytes(len).is_none() {
+							return Err(AudioError::InvalidScore("MIDI sysex event"));
+						}
+						None
+					}
+					_ => {
+						running_status = status;
+						let channel = status & 0x0F;
+						match status & 0xF0 {
+							0x80 => {
+								let key = try!(track.u8()) & 0x7F;
+								let _velocity = try!(track.u8());
+								Some(TrackEvent::Channel(MusicEvent::NoteOff { channel: channel, key: key }))
+							}
+							0x90 => {
+								let key = try!(track.u8()) & 0x7F;
+								let velocity = try!(track.u8()) & 0x7F;
+								if velocity == 0 {
+									Some(TrackEvent::Channel(MusicEvent::NoteOff { channel: channel, key: key }))
+								} else {
+									Some(TrackEvent::Channel(MusicEvent::NoteOn { channel: channel, key: key, velocity: velocity }))
+								}
+							}
+							0xA0 => {
+								try!(track.u8());
+								try!(track.u8());
+								None
+							}
+							0xB0 => {
+								let controller = try!(track.u8()) & 0x7F;
+								let value = try!(track.u8()) & 0x7F;
+								Some(TrackEvent::Channel(MusicEvent::Controller { channel: channel, controller: controller, value: value }))
+							}
+							0xC0 => {
+								let program = try!(track.u8()) & 0x7F;
+								Some(TrackEvent::Channel(MusicEvent::ProgramChange { channel: channel, program: program }))
+							}
+							0xD0 => {
+								try!(track.u8());
+								None
+							}
+							0xE0 => {
+								let lsb = try!(track.u8()) as u16 & 0x7F;
+								let msb = try!(track.u8()) as u16 & 0x7F;
+								Some(TrackEvent::Channel(MusicEvent::PitchBend { channel: channel, value: (msb << 7) | lsb }))
+							}
+							_ => return Err(AudioError::InvalidScore("MIDI event"))
+						}
+					}
+				};
+
+				if let Some(event) = event {
+					tick_events.push((ticks, sequence, event));
+					sequence += 1;
+				}
+			}
+		}
+
+		// Merge the tracks and convert the ticks to real time.
+		tick_events.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+		let mut events = Vec::<ScoreEvent>::with_capacity(tick_events.len());
+		let mut tempo = MIDI_DEFAULT_TEMPO;
+		let mut last_tick = 0u64;
+		let mut time = 0u64;
+		let mut remainder = 0u64;
+
+		for &(tick, _, event) in &tick_events {
+			// Time is accumulated as a fraction to keep long songs in sync.
+			let (numerator, denominator) = try!(midi_tick_length(division, tempo));
+			let elapsed = (tick - last_tick) * numerator + remainder;
+			time += elapsed / denominator;
+			remainder = elapsed % denominator;
+			last_tick = tick;
+
+			match event {
+				TrackEvent::Tempo(value) => tempo = value,
+				TrackEvent::Channel(event) => events.push(ScoreEvent { time: time, event: event })
+			}
+		}
+
+		Ok(Score {
+			events: events,
+			length: time
+		})
+	}
+}
+
+#[derive(Debug, Copy, Clone)]
+enum TrackEvent {
+	Tempo(u64),
+	Channel(MusicEvent)
+}
+
+fn mus_channel_to_midi(channel : u8) -> u8 {
+	if channel == 15 {
+		PERCUSSION_CHANNEL
+	} else if channel >= PERCUSSION_CHANNEL {
+		channel + 1
+	} else {
+		channel
+	}
+}
+
+/// Length of a single MIDI tick in microseconds, as a fraction.
+fn midi_tick_length(division : u16, tempo : u64) -> AudioResult<(u64, u64)> {
+	if division & 0x8000 != 0 {
+		// SMPTE time, the high byte is minus the frames per second.
+		let fps = -((division >> 8) as u8 as i8 as i32) as u64;
+		let ticks_per_frame = (division & 0xFF) as u64;
+		if ticks_per_frame == 0 {
+			return Err(AudioError::InvalidScore("MIDI division"));
+		}
+		Ok((1000000, fps * ticks_per_frame))
+	} else {
+		let ticks_per_beat = if division == 0 { 1 } else { division as u64 };
+		Ok((tempo, ticks_per_beat))
+	}
+}
+
+struct ByteReader<'a> {
+	data : &'a [u8],
+	pos : usize
+}
+
+impl<'a> ByteReader<'a> {
+	fn new(data : &'a [u8]) -> ByteReader<'a> {
+		ByteReader {
+			data: data,
+			pos: 0
+		}
+	}
+
+	fn is_empty(&self) -> bool {
+		self.pos >= self.data.len()
+	}
+
+	fn unread(&mut self) {
+		self.pos -= 1;
+	}
+
+	fn bytes(&mut self, len : usize) -> Option<&'a [u8]> {
+		if self.pos + len > self.data.len() {
+			return None;
+		}
+		let bytes = &self.data[self.pos..self.pos + len];
+		self.pos += len;
+		Some(bytes)
+	}
+
+	fn u8(&mut self) -> AudioResult<u8> {
+		match self.bytes(1) {
+			Some(value) => Ok(value[0]),
+			None => Err(AudioError::InvalidScore("unexpected end of data"))
+		}
+	}
+
+	fn u16_le(&mut self) -> AudioResult<u16> {
+		let lo = try!(self.u8()) as u16;
+		let hi = try!(self.u8()) as u16;
+		Ok(lo | (hi << 8))
+	}
+
+	fn u16_be(&mut self) -> AudioResult<u16> {
+		let hi = try!(self.u8()) as u16;
+		let lo = try!(self.u8()) as u16;
+		Ok(lo | (hi << 8))
+	}
+
+	fn u32_be(&mut self) -> AudioResult<u32> {
+		let hi = try!(self.u16_be()) as u32;
+		let lo = try!(self.u16_be()) as u32;
+		Ok(lo | (hi << 16))
+	}
+
+	fn varlen(&mut self) -> AudioResult<u64> {
+		let mut value = 0u64;
+		for _ in 0..4 {
+			let byte = try!(self.u8());
+			value = (value << 7) | (byte & 0x7F) as u64;
+			if byte & 0x80 == 0 {
+				return Ok(value);
+			}
+		}
+		Err(AudioError::InvalidScore("variable length value"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn midi(division : u16, track : &[u8]) -> Vec<u8> {
+		let mut data = Vec::<u8>::new();
+		data.extend_from_slice(b"MThd\0\0\0\x06\0\0\0\x01");
+		data.push((division >> 8) as u8);
+		data.push(division as u8);
+		data.extend_from_slice(b"MTrk\0\0");
+		data.push((track.len() >> 8) as u8);
+		data.push(track.len() as u8);
+		data.extend_from_slice(track);
+		data
+	}
+
+	#[test]
+	fn mus_events_are_timed_in_140ths_of_a_second() {
+		let mut data = Vec::<u8>::new();
+		data.extend_from_slice(b"MUS\x1A\x0B\0\x10\0");
+		data.extend_from_slice(&[0; 8]);
+		data.extend_from_slice(&[
+			// Note on with volume on channel 0, then 140 ticks
+			0x90, 0x80 | 60, 100, 0x81, 0x0C,
+			// Note off on channel 15, then 70 ticks
+			0x8F, 35, 0x46,
+			// Score end
+			0x60
+		]);
+
+		let score = Score::new(&data).unwrap();
+		assert_eq!(score.events, vec![
+			ScoreEvent { time: 0, event: MusicEvent::NoteOn { channel: 0, key: 60, velocity: 100 } },
+			ScoreEvent { time: 1000000, event: MusicEvent::NoteOff { channel: PERCUSSION_CHANNEL, key: 35 } }
+		]);
+		assert_eq!(score.length, 1500000);
+	}
+
+	#[test]
+	fn midi_events_follow_tempo_changes() {
+		let data = midi(96, &[
+			0x00, 0x90, 60, 100,
+			// Halve the length of a beat after one beat of 500000us
+			0x60, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90,
+			0x60, 0x80, 60, 0,
+			0x00, 0xFF, 0x2F, 0x00
+		]);
+
+		let score = Score::new(&data).unwrap();
+		assert_eq!(score.events, vec![
+			ScoreEvent { time: 0, event: MusicEvent::NoteOn { channel: 0, key: 60, velocity: 100 } },
+			ScoreEvent { time: 750000, event: MusicEvent::NoteOff { channel: 0, key: 60 } }
+		]);
+		assert_eq!(score.length, 750000);
+	}
+
+	#[test]
+	fn midi_smpte_division() {
+		// 25 frames per second of 40 ticks, so a tick is a millisecond.
+		let data = midi(0xE728, &[
+			0x00, 0x91, 60, 100,
+			0x87, 0x68, 0x91, 60, 0,
+			0x00, 0xFF, 0x2F, 0x00
+		]);
+
+		let score = Score::new(&data).unwrap();
+		assert_eq!(score.events[1], ScoreEvent { time: 1000000, event: MusicEvent::NoteOff { channel: 1, key: 60 } });
+	}
+
+	#[test]
+	fn invalid_smpte_divisions_are_errors() {
+		let track = [0x00, 0x90, 60, 100, 0x00, 0xFF, 0x2F, 0x00];
+		assert!(Score::new(&midi(0x8000, &track)).is_err());
+		assert!(Score::new(&midi(0xE700, &track)).is_err());
+		assert!(Score::new(&midi(0xE700, &[])).is_err());
+	}
+}
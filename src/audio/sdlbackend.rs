@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use sdl2;
+use sdl2::SdlResult;
+use sdl2::audio::AudioCallback;
+use sdl2::audio::AudioDevice;
+use sdl2::audio::AudioSpecDesired;
+use audio::AudioBackend;
+
+struct SdlAudioCallback {
+	queue : Arc<Mutex<VecDeque<i16>>>,
+	channels : usize
+}
+
+impl AudioCallback for SdlAudioCallback {
+	type Channel = i16;
+
+	fn callback(&mut self, out : &mut [i16]) {
+		let mut queue = self.queue.lock().unwrap();
+
+		for frame in out.chunks_mut(self.channels) {
+			let left = queue.pop_front().unwrap_or(0);
+			let right = queue.pop_front().unwrap_or(0);
+
+			if frame.len() == 1 {
+				frame[0] = ((left as i32 + right as i32) / 2) as i16;
+			} else {
+				frame[0] = left;
+				frame[1] = right;
+				for sample in frame[2..].iter_mut() {
+					*sample = 0;
+				}
+			}
+		}
+	}
+}
+
+/// Plays the sound through the SDL audio device. Mixed samples are queued and
+/// the device callback pulls them from the queue.
+pub struct SdlAudioBackend {
+	device : AudioDevice<SdlAudioCallback>,
+	queue : Arc<Mutex<VecDeque<i16>>>,
+	sample_rate : u32,
+	latency : usize
+}
+
+impl SdlAudioBackend {
+	pub fn new(audio_ctx : &sdl2::AudioSubsystem, sample_rate : u32) -> SdlResult<SdlAudioBackend> {
+		let desired = AudioSpecDesired {
+			freq: Some(sample_rate as i32),
+			channels: Some(2),
+			samples: Some(512)
+		};
+
+		let queue = Arc::new(Mutex::new(VecDeque::<i16>::new()));
+		let mut obtained_rate = sample_rate;
+		let mut buffer_size = 512usize;
+
+		let device = try!(audio_ctx.open_playback(None, &desired, |spec| {
+			obtained_rate = spec.freq as u32;
+			buffer_size = spec.samples as usize;
+			SdlAudioCallback {
+				queue: queue.clone(),
+				channels: spec.channels as usize
+			}
+		}));
+		device.resume();
+
+		Ok(SdlAudioBackend {
+			device: device,
+			queue: queue,
+			sample_rate: obtained_rate,
+			// Keep two device buffers queued to survive slow frames.
+			latency: buffer_size * 2
+		})
+	}
+}
+
+impl AudioBackend for SdlAudioBackend {
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn frames_needed(&mut self) -> usize {
+		let queued = self.queue.lock().unwrap().len() / 2;
+		if queued >= self.latency { 0 } else { self.latency - queued }
+	}
+
+	fn write(&mut self, samples : &[i16]) {
+		self.queue.lock().unwrap().extend(samples.iter().cloned());
+	}
+}
+
+impl Drop for SdlAudioBackend {
+	fn drop(&mut self) {
+		self.device.pause();
+	}
+}
@@ -0,0 +1,40 @@
+use audio::AudioResult;
+use audio::AudioError;
+
+// DMX pads the sample data with 16 bytes on both ends.
+const DMX_PADDING : usize = 16;
+
+/// A digitized sound effect from a DS* lump, as unsigned 8-bit mono samples.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Sound {
+	pub sample_rate : u32,
+	pub samples : Vec<u8>
+}
+
+impl Sound {
+	pub fn new(data : &[u8]) -> AudioResult<Sound> {
+		if data.len() < 8 {
+			return Err(AudioError::InvalidLump("DMX sound"));
+		}
+
+		let format = data[0] as u16 | (data[1] as u16) << 8;
+		let sample_rate = data[2] as u32 | (data[3] as u32) << 8;
+		let length = data[4] as usize | (data[5] as usize) << 8 | (data[6] as usize) << 16 | (data[7] as usize) << 24;
+
+		if format != 3 || sample_rate == 0 || length > data.len() - 8 {
+			return Err(AudioError::InvalidLump("DMX sound"));
+		}
+
+		let samples = &data[8..8 + length];
+		let samples = if samples.len() > DMX_PADDING * 2 {
+			&samples[DMX_PADDING..samples.len() - DMX_PADDING]
+		} else {
+			samples
+		};
+
+		Ok(Sound {
+			sample_rate: sample_rate,
+			samples: samples.to_vec()
+		})
+	}
+}
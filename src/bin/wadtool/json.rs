@@ -0,0 +1,130 @@
+use std::fmt;
+
+/// Minimal JSON value, enough for dumping the resource structures.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+	Int(i64),
+	Float(f64),
+	Str(String),
+	Array(Vec<Json>),
+	Object(Vec<(&'static str, Json)>)
+}
+
+impl Json {
+	/// A 16.16 fixed point value in map units.
+	pub fn fixed(value : i32) -> Json {
+		if value & 0xFFFF == 0 {
+			Json::Int((value >> 16) as i64)
+		} else {
+			Json::Float(value as f64 / 65536.0)
+		}
+	}
+
+	fn write(&self, f : &mut fmt::Formatter, indent : usize) -> fmt::Result {
+		match *self {
+			Json::Int(value) => write!(f, "{}", value),
+			Json::Float(value) => write!(f, "{}", value),
+			Json::Str(ref value) => write_string(f, value),
+			Json::Array(ref values) => {
+				if values.is_empty() {
+					return write!(f, "[]");
+				}
+
+				// Arrays of plain values are kept on one line.
+				if values.iter().all(|value| !value.is_container()) {
+					try!(write!(f, "["));
+					for (i, value) in values.iter().enumerate() {
+						if i > 0 {
+							try!(write!(f, ", "));
+						}
+						try!(value.write(f, indent));
+					}
+					return write!(f, "]");
+				}
+
+				try!(write!(f, "[\n"));
+				for (i, value) in values.iter().enumerate() {
+					try!(write_indent(f, indent + 1));
+					try!(value.write(f, indent + 1));
+					try!(write!(f, "{}\n", if i + 1 < values.len() { "," } else { "" }));
+				}
+				try!(write_indent(f, indent));
+				write!(f, "]")
+			}
+			Json::Object(ref fields) => {
+				if fields.is_empty() {
+					return write!(f, "{{}}");
+				}
+
+				// Small objects of plain values are kept on one line.
+				if fields.iter().all(|&(_, ref value)| !value.is_nested_container()) {
+					try!(write!(f, "{{"));
+					for (i, &(name, ref value)) in fields.iter().enumerate() {
+						if i > 0 {
+							try!(write!(f, ", "));
+						}
+						try!(write_string(f, name));
+						try!(write!(f, ": "));
+						try!(value.write(f, indent));
+					}
+					return write!(f, "}}");
+				}
+
+				try!(write!(f, "{{\n"));
+				for (i, &(name, ref value)) in fields.iter().enumerate() {
+					try!(write_indent(f, indent + 1));
+					try!(write_string(f, name));
+					try!(write!(f, ": "));
+					try!(value.write(f, indent + 1));
+					try!(write!(f, "{}\n", if i + 1 < fields.len() { "," } else { "" }));
+				}
+				try!(write_indent(f, indent));
+				write!(f, "}}")
+			}
+		}
+	}
+
+	fn is_container(&self) -> bool {
+		match *self {
+			Json::Array(_) | Json::Object(_) => true,
+			_ => false
+		}
+	}
+
+	fn is_nested_container(&self) -> bool {
+		match *self {
+			Json::Array(ref values) => values.iter().any(|value| value.is_container()),
+			Json::Object(_) => true,
+			_ => false
+		}
+	}
+}
+
+impl fmt::Display for Json {
+	fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+		self.write(f, 0)
+	}
+}
+
+fn write_indent(f : &mut fmt::Formatter, indent : usize) -> fmt::Result {
+	for _ in 0..indent {
+		try!(write!(f, "  "));
+	}
+	Ok(())
+}
+
+fn write_string(f : &mut fmt::Formatter, value : &str) -> fmt::Result {
+	try!(write!(f, "\""));
+	for c in value.chars() {
+		match c {
+			'"' => try!(write!(f, "\\\"")),
+			'\\' => try!(write!(f, "\\\\")),
+			'\n' => try!(write!(f, "\\n")),
+			'\r' => try!(write!(f, "\\r")),
+			'\t' => try!(write!(f, "\\t")),
+			c if (c as u32) < 0x20 => try!(write!(f, "\\u{:04x}", c as u32)),
+			c => try!(write!(f, "{}", c))
+		}
+	}
+	write!(f, "\"")
+}
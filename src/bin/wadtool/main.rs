@@ -0,0 +1,418 @@
+extern crate doom;
+
+mod json;
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+use doom::render::Canvas;
+use doom::render::RenderStats;
+use doom::render::Renderer;
+use doom::render::View;
+use doom::resources::ColorMap;
+use doom::resources::ResourceManager;
+use doom::resources::WadPackage;
+use doom::resources::Namespace;
+use doom::resources::WadError;
+use doom::resources::bsp;
+use doom::mapexport;
+use doom::mapexport::MapStyle;
+use doom::maplint;
+use doom::maplint::Severity;
+use doom::maplint::TextureNames;
+use json::Json;
+
+const USAGE : &'static str = "Usage: wadtool <command> <wad> [arguments]
+
+Commands:
+  list <wad>                     List the lump directory
+  extract <wad> <lump> [file]    Extract a single lump (to <lump>.lmp by default)
+  extract-all <wad> <directory>  Extract every lump into a directory
+  stats <wad> [map]              Print map statistics
+  json <wad> <map>               Dump a map as JSON, in map units
+  render <wad> <map> <file> [size]
+                                 Draw a map from above to an .svg or .png file
+                                 (1024 pixels by default)
+  lint <wad> [map] [--iwad <iwad>]
+                                 Check maps for errors. Texture names are checked
+                                 against the WAD and the IWAD, if given
+  bench <wad> <map>              Time the 3D renderer from every thing of a map,
+                                 with and without BSP node culling";
+
+const DEFAULT_RENDER_SIZE : i32 = 1024;
+
+// The benchmark renders each view this many times, at this size.
+const BENCH_ROUNDS : usize = 4;
+const BENCH_WIDTH : usize = 640;
+const BENCH_HEIGHT : usize = 400;
+
+fn main() {
+	let args : Vec<String> = env::args().skip(1).collect();
+	if args.len() < 2 {
+		fail(USAGE);
+	}
+
+	let package = load_package(&args[1]);
+	let rest = &args[2..];
+
+	let result = match (&args[0][..], rest.len()) {
+		("list", 0) => list(&package),
+		("extract", 1) => extract(&package, &rest[0], &format!("{}.lmp", rest[0])),
+		("extract", 2) => extract(&package, &rest[0], &rest[1]),
+		("extract-all", 1) => extract_all(&package, &rest[0]),
+		("stats", 0) => stats(&package, None),
+		("stats", 1) => stats(&package, Some(&rest[0])),
+		("json", 1) => dump_json(&package, &rest[0]),
+		("render", 2) => render(&package, &rest[0], &rest[1], DEFAULT_RENDER_SIZE),
+		("lint", _) => lint(&package, rest),
+		("bench", 1) => bench(&args[1], &rest[0]),
+		("render", 3) => match rest[2].parse::<i32>() {
+			Ok(size) if size > 0 => render(&package, &rest[0], &rest[1], size),
+			_ => fail(&format!("Invalid size {}", rest[2]))
+		},
+		_ => fail(USAGE)
+	};
+
+	if let Err(err) = result {
+		fail(&format!("{}", err));
+	}
+}
+
+fn fail(message : &str) -> ! {
+	let _ = writeln!(io::stderr(), "{}", message);
+	process::exit(1);
+}
+
+fn load_package(path : &str) -> WadPackage {
+	let file = match File::open(path) {
+		Ok(value) => value,
+		Err(err) => fail(&format!("Cannot open {}: {}", path, err))
+	};
+
+	match WadPackage::new(file) {
+		Ok(value) => value,
+		Err(err) => fail(&format!("Cannot read {}: {:?}", path, err))
+	}
+}
+
+fn find_map(package : &WadPackage, name : &str) -> bsp::Map {
+	match package.read_map(name) {
+		Ok(Some(value)) => value,
+		Ok(None) => fail(&format!("No map {} in the package", name)),
+		Err(err) => fail(&format!("Cannot read map {}: {:?}", name, err))
+	}
+}
+
+/// The named map, or all maps of the package.
+fn find_maps(package : &WadPackage, name : Option<&str>) -> Vec<bsp::Map> {
+	match name {
+		Some(name) => vec![find_map(package, name)],
+		None => package.get_map_names().iter().map(|name| find_map(package, name)).collect()
+	}
+}
+
+fn read_lump(package : &WadPackage, index : usize) -> Vec<u8> {
+	match package.read_lump(index) {
+		Ok(value) => value,
+		Err(err) => fail(&format!("Cannot read lump {}: {:?}", package.get_lumps()[index].name, err))
+	}
+}
+
+fn namespace_name(namespace : Namespace) -> &'static str {
+	match namespace {
+		Namespace::Global => "global",
+		Namespace::Map => "map",
+		Namespace::Sprites => "sprites",
+		Namespace::Flats => "flats",
+		Namespace::Patches => "patches"
+	}
+}
+
+fn list(package : &WadPackage) -> io::Result<()> {
+	let stdout = io::stdout();
+	let mut out = stdout.lock();
+
+	try!(writeln!(out, "{:>5}  {:<8}  {:>10}  {:>8}  {}", "#", "name", "offset", "size", "namespace"));
+	for (i, lump) in package.get_lumps().iter().enumerate() {
+		try!(writeln!(out, "{:>5}  {:<8}  {:>10}  {:>8}  {}", i, lump.name, lump.pos, lump.size, namespace_name(lump.namespace)));
+	}
+	Ok(())
+}
+
+fn extract(package : &WadPackage, name : &str, path : &str) -> io::Result<()> {
+	// Like the game, the last lump with the name wins.
+	let index = match package.find_lump(name) {
+		Some(value) => value,
+		None => fail(&format!("No lump {} in the package", name))
+	};
+
+	let mut file = try!(File::create(path));
+	file.write_all(&read_lump(package, index))
+}
+
+fn extract_all(package : &WadPackage, directory : &str) -> io::Result<()> {
+	try!(fs::create_dir_all(directory));
+
+	// Lump names repeat (every map has THINGS), so number the duplicates.
+	let mut used_names = Vec::<String>::new();
+	for (i, lump) in package.get_lumps().iter().enumerate() {
+		let base : String = lump.name.chars()
+			.map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+			.collect();
+
+		let mut file_name = format!("{}.lmp", base);
+		let mut n = 1;
+		while used_names.contains(&file_name.to_ascii_uppercase()) {
+			n += 1;
+			file_name = format!("{}.{}.lmp", base, n);
+		}
+		used_names.push(file_name.to_ascii_uppercase());
+
+		let mut file = try!(File::create(Path::new(directory).join(&file_name)));
+		try!(file.write_all(&read_lump(package, i)));
+	}
+
+	println!("Extracted {} lumps", package.get_lumps().len());
+	Ok(())
+}
+
+fn stats(package : &WadPackage, name : Option<&str>) -> io::Result<()> {
+	let stdout = io::stdout();
+	let mut out = stdout.lock();
+
+	let maps = find_maps(package, name);
+
+	try!(writeln!(out, "{:<8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}",
+		"map", "things", "vertices", "lines", "sides", "sectors", "segs", "ssectors", "nodes"));
+	for map in maps.iter() {
+		try!(writeln!(out, "{:<8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}",
+			map.name, map.things.len(), map.vertices.len(), map.lines.len(), map.sides.len(), map.sectors.len(),
+			map.segs.len(), map.subsectors.len(), map.nodes.len()));
+	}
+	Ok(())
+}
+
+fn dump_json(package : &WadPackage, name : &str) -> io::Result<()> {
+	let map = find_map(package, name);
+	let stdout = io::stdout();
+	let mut out = stdout.lock();
+	writeln!(out, "{}", map_to_json(&map))
+}
+
+fn render(package : &WadPackage, name : &str, path : &str, size : i32) -> io::Result<()> {
+	let map = find_map(package, name);
+	let style = MapStyle::default();
+	let mut file = try!(File::create(path));
+
+	let lower_path = path.to_ascii_lowercase();
+	if lower_path.ends_with(".svg") {
+		file.write_all(mapexport::map_to_svg(&map, &style, size).as_bytes())
+	} else if lower_path.ends_with(".png") {
+		mapexport::render_map(&map, &style, size).write_png(&mut file)
+	} else {
+		fail("The output file must end in .svg or .png")
+	}
+}
+
+fn lint(package : &WadPackage, args : &[String]) -> io::Result<()> {
+	let mut map_name = None;
+	let mut iwad = None;
+
+	let mut i = 0;
+	while i < args.len() {
+		if args[i] == "--iwad" && i + 1 < args.len() {
+			iwad = Some(load_package(&args[i + 1]));
+			i += 2;
+		} else if map_name.is_none() {
+			map_name = Some(&args[i][..]);
+			i += 1;
+		} else {
+			fail(USAGE);
+		}
+	}
+
+	// The textures of the WAD override the IWAD ones, but for checking
+	// names only the union matters.
+	let mut textures = TextureNames::new();
+	if let Some(ref iwad) = iwad {
+		try!(add_textures(&mut textures, iwad));
+		textures.mark_complete();
+	}
+	try!(add_textures(&mut textures, package));
+
+	let maps = find_maps(package, map_name);
+
+	let stdout = io::stdout();
+	let mut out = stdout.lock();
+
+	if textures.is_empty() {
+		try!(writeln!(out, "No textures or flats found, texture names are not checked"));
+	} else if !textures.any_textures() {
+		try!(writeln!(out, "No textures found, wall texture names are not checked"));
+	} else if !textures.any_flats() {
+		try!(writeln!(out, "No flats found, flat names are not checked"));
+	}
+
+	let mut errors = 0;
+	let mut warnings = 0;
+	for map in maps.iter() {
+		let diagnostics = maplint::lint_map(map, if textures.is_empty() { None } else { Some(&textures) });
+		for diagnostic in diagnostics.iter() {
+			try!(writeln!(out, "{}: {}", map.name, diagnostic));
+			match diagnostic.severity {
+				Severity::Error => errors += 1,
+				Severity::Warning => warnings += 1
+			}
+		}
+	}
+
+	try!(writeln!(out, "{} errors, {} warnings", errors, warnings));
+	if errors > 0 {
+		process::exit(1);
+	}
+	Ok(())
+}
+
+fn bench(path : &str, name : &str) -> io::Result<()> {
+	let resources = ResourceManager::new();
+	if let Err(err) = resources.load_package(path) {
+		fail(&format!("Cannot load {}: {:?}", path, err));
+	}
+
+	let map = match resources.find_map(name) {
+		Some(value) => value,
+		None => fail(&format!("No map {} in the package", name))
+	};
+	let colormap = match resources.find_lump("COLORMAP").map(|data| ColorMap::read(&data)) {
+		Some(Ok(value)) => value,
+		_ => fail("No valid COLORMAP in the package")
+	};
+
+	// Eight directions from every thing, which covers the places players
+	// actually look from.
+	let views : Vec<View> = map.things.iter()
+		.flat_map(|thing| (0..8).map(move |i| (thing.x, thing.y, (i * 0x2000) as u16)))
+		.map(|(x, y, angle)| View::on_floor(&map, x, y, angle))
+		.collect();
+	if views.is_empty() {
+		fail("The map has no things to render from");
+	}
+
+	let mut pixels = vec![0u8; BENCH_WIDTH * BENCH_HEIGHT];
+	let stdout = io::stdout();
+	let mut out = stdout.lock();
+
+	try!(writeln!(out, "{} views at {}x{}, {} rounds", views.len(), BENCH_WIDTH, BENCH_HEIGHT, BENCH_ROUNDS));
+	try!(writeln!(out, "{:<12}  {:>10}  {:>8}  {:>11}  {:>6}", "culling", "ms/frame", "nodes", "subsectors", "segs"));
+
+	for &culling in [false, true].iter() {
+		let mut renderer = Renderer::new(&map, &resources, &colormap, Canvas::new(&mut pixels, BENCH_WIDTH, BENCH_HEIGHT, BENCH_WIDTH));
+		renderer.set_node_culling(culling);
+
+		// The first frames read and compose the textures and flats they
+		// show, which would count against whichever pass comes first.
+		for view in views.iter() {
+			renderer.render_view(view);
+		}
+
+		let mut total = RenderStats::default();
+		let start = Instant::now();
+		for _ in 0..BENCH_ROUNDS {
+			for view in views.iter() {
+				renderer.render_view(view);
+				let stats = renderer.stats();
+				total.nodes += stats.nodes;
+				total.subsectors += stats.subsectors;
+				total.segs += stats.segs;
+			}
+		}
+		let elapsed = start.elapsed();
+
+		let frames = views.len() * BENCH_ROUNDS;
+		let ms = (elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1000000.0) / frames as f64;
+		try!(writeln!(out, "{:<12}  {:>10.3}  {:>8}  {:>11}  {:>6}", if culling { "bounding box" } else { "none" },
+			ms, total.nodes / frames, total.subsectors / frames, total.segs / frames));
+	}
+
+	Ok(())
+}
+
+fn add_textures(textures : &mut TextureNames, package : &WadPackage) -> io::Result<()> {
+	match textures.add_package(package) {
+		Ok(()) => Ok(()),
+		Err(WadError::IoFailure(err)) => Err(err),
+		Err(err) => fail(&format!("Cannot read textures: {:?}", err))
+	}
+}
+
+fn map_to_json(map : &bsp::Map) -> Json {
+	let uint = |value : u32| Json::Int(value as i64);
+
+	Json::Object(vec![
+		("name", Json::Str(map.name.clone())),
+		("things", Json::Array(map.things.iter().map(|thing| Json::Object(vec![
+			("x", Json::fixed(thing.x)),
+			("y", Json::fixed(thing.y)),
+			("angle", Json::Int((thing.angle as i64 * 360) / 0x10000)),
+			("type", uint(thing.thing_type)),
+			("flags", uint(thing.flags))
+		])).collect())),
+		("vertices", Json::Array(map.vertices.iter().map(|v| Json::Object(vec![
+			("x", Json::fixed(v.x)),
+			("y", Json::fixed(v.y))
+		])).collect())),
+		("lines", Json::Array(map.lines.iter().map(|line| Json::Object(vec![
+			("v", Json::Array(vec![uint(line.v[0]), uint(line.v[1])])),
+			("flags", uint(line.flags)),
+			("special_type", uint(line.special_type)),
+			("sector_tag", uint(line.sector_tag)),
+			("side", Json::Array(vec![uint(line.side[0]), uint(line.side[1])]))
+		])).collect())),
+		("sides", Json::Array(map.sides.iter().map(|side| Json::Object(vec![
+			("x_offset", Json::fixed(side.x_offset)),
+			("y_offset", Json::fixed(side.y_offset)),
+			("upper_texture", Json::Str(side.upper_texture.clone())),
+			("lower_texture", Json::Str(side.lower_texture.clone())),
+			("middle_texture", Json::Str(side.middle_texture.clone())),
+			("sector", uint(side.sector))
+		])).collect())),
+		("sectors", Json::Array(map.sectors.iter().map(|sector| Json::Object(vec![
+			("floor_height", Json::fixed(sector.floor_height)),
+			("ceiling_height", Json::fixed(sector.ceiling_height)),
+			("floor_texture", Json::Str(sector.floor_texture.clone())),
+			("ceiling_texture", Json::Str(sector.ceiling_texture.clone())),
+			("light_level", uint(sector.light_level >> 16)),
+			("sector_type", uint(sector.sector_type)),
+			("tag", uint(sector.tag))
+		])).collect())),
+		("subsectors", Json::Array(map.subsectors.iter().map(|subsector| Json::Object(vec![
+			("num_segs", uint(subsector.num_segs)),
+			("first_seg", uint(subsector.first_seg))
+		])).collect())),
+		("segs", Json::Array(map.segs.iter().map(|seg| Json::Object(vec![
+			("v", Json::Array(vec![uint(seg.v[0]), uint(seg.v[1])])),
+			("angle", uint(seg.angle as u32)),
+			("side", uint(seg.side as u32)),
+			("line", uint(seg.line)),
+			("offset", Json::fixed(seg.offset))
+		])).collect())),
+		("nodes", Json::Array(map.nodes.iter().map(|node| Json::Object(vec![
+			("x", Json::fixed(node.x)),
+			("y", Json::fixed(node.y)),
+			("dx", Json::fixed(node.dx)),
+			("dy", Json::fixed(node.dy)),
+			("bounds", Json::Array(node.bounds.iter().map(|bounds| Json::Object(vec![
+				("left", Json::fixed(bounds.left)),
+				("top", Json::fixed(bounds.top)),
+				("right", Json::fixed(bounds.right)),
+				("bottom", Json::fixed(bounds.bottom))
+			])).collect())),
+			("child", Json::Array(vec![uint(node.child[0]), uint(node.child[1])]))
+		])).collect()))
+	])
+}
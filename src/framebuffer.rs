@@ -1,59 +1,323 @@
 use sdl2;
 use sdl2::SdlResult;
+use resources::Patch;
+use resources::Palette;
+use resources::Color;
+use resources::gamma_correct;
+use resources::GAMMA_LEVELS;
+use scaling;
+use scaling::ScaleOptions;
+use render::Canvas;
 
+/// The screen, as palette indices like the screens of vanilla. The indices
+/// only become colors through the active palette and gamma level when the
+/// frame is presented, so palette flashes and gamma changes cost nothing
+/// per pixel. The screen has its own size, and is scaled to the window.
+/// Headless framebuffers have no window, and the presented colors can be
+/// read back instead.
 pub struct Framebuffer<'a> {
-	window : sdl2::video::Window,
-	surface : sdl2::surface::Surface<'a>,
+	window : Option<WindowOutput<'a>>,
+	pixels : Vec<u8>,
+	width : usize,
+	height : usize,
+
+	// The screen in colors, as of the last present
+	converted : Vec<[u8; 4]>,
+	scale_options : ScaleOptions,
+
+	palettes : Vec<Palette>,
+	palette : usize,
+	gamma : usize,
+
+	// The BGRX pixel of each palette index, with the palette and the gamma
+	// applied
+	colors : [[u8; 4]; 256]
+}
+
+// The window, and the screen scaled to its size
+enum WindowOutput<'a> {
+	// Blitted to the window's surface, and shown as soon as it is ready
+	Surface {
+		window : sdl2::video::Window,
+		surface : sdl2::surface::Surface<'a>
+	},
+
+	Vsync(VsyncOutput)
+}
+
+// Streamed to a texture that the renderer shows at the next refresh of the
+// display
+struct VsyncOutput {
+	renderer : sdl2::render::Renderer<'static>,
+	texture : sdl2::render::Texture,
+	texture_size : (u32, u32),
+	scaled : Vec<u8>
 }
 
 impl<'a> Framebuffer<'a> {
-	pub fn new(video_ctx : &sdl2::VideoSubsystem, width : u32, height : u32) -> SdlResult<Framebuffer<'a>> {
-		let mut window = try!(video_ctx.window("Doom", width, height).position_centered().build());
+	/// Opens a window of `window_width` by `window_height` pixels, showing a
+	/// screen of `width` by `height` pixels. The window can be resized. With
+	/// `vsync`, presenting waits for the display to refresh, so frames are
+	/// drawn at its rate and don't tear.
+	pub fn new(video_ctx : &sdl2::VideoSubsystem, window_width : u32, window_height : u32, width : u32, height : u32, vsync : bool)
+		-> SdlResult<Framebuffer<'a>> {
+
+		let mut window = try!(video_ctx.window("Doom", window_width, window_height).position_centered().resizable().build());
 		window.show();
 
-		Ok(Framebuffer {
-			window: window,
-			surface: try!(sdl2::surface::Surface::new(width, height, sdl2::pixels::PixelFormatEnum::BGRX8888)),
-		})
+		let output = if vsync {
+			let renderer = try!(window.renderer().present_vsync().build());
+			let texture = try!(renderer.create_texture_streaming(sdl2::pixels::PixelFormatEnum::BGRX8888, (window_width, window_height)));
+			WindowOutput::Vsync(VsyncOutput {
+				renderer: renderer,
+				texture: texture,
+				texture_size: (window_width, window_height),
+				scaled: vec![0u8; window_width as usize * window_height as usize * 4]
+			})
+		} else {
+			WindowOutput::Surface {
+				window: window,
+				surface: try!(sdl2::surface::Surface::new(window_width, window_height, sdl2::pixels::PixelFormatEnum::BGRX8888))
+			}
+		};
+
+		let mut framebuffer = Framebuffer::headless(width, height);
+		framebuffer.window = Some(output);
+		Ok(framebuffer)
+	}
+
+	/// A screen of `width` by `height` pixels without a window.
+	pub fn headless(width : u32, height : u32) -> Framebuffer<'a> {
+		// Grays until the palettes are loaded
+		let mut gray = Palette {
+			colors: [Color { r: 0, g: 0, b: 0 }; 256]
+		};
+		for (i, color) in gray.colors.iter_mut().enumerate() {
+			*color = Color { r: i as u8, g: i as u8, b: i as u8 };
+		}
+
+		let mut framebuffer = Framebuffer {
+			window: None,
+			pixels: vec![0u8; width as usize * height as usize],
+			width: width as usize,
+			height: height as usize,
+			converted: vec![[0u8; 4]; width as usize * height as usize],
+			scale_options: ScaleOptions::default(),
+			palettes: vec![gray],
+			palette: 0,
+			gamma: 0,
+			colors: [[0u8; 4]; 256]
+		};
+		framebuffer.update_colors();
+		framebuffer
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
 	}
 
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	/// The palette indices of the screen, row by row.
+	pub fn pixels(&self) -> &[u8] {
+		&self.pixels
+	}
+
+	/// The screen as it was last presented, as RGB bytes row by row, with
+	/// the palette and gamma applied.
+	pub fn presented_rgb(&self) -> Vec<u8> {
+		let mut rgb = Vec::<u8>::with_capacity(self.converted.len() * 3);
+		for pixel in self.converted.iter() {
+			rgb.extend_from_slice(&pixel[1..]);
+		}
+		rgb
+	}
+
+	/// The palette the screen is shown through, with the gamma applied.
+	pub fn shown_palette(&self) -> Palette {
+		let mut palette = Palette {
+			colors: [Color { r: 0, g: 0, b: 0 }; 256]
+		};
+		for (color, pixel) in palette.colors.iter_mut().zip(self.colors.iter()) {
+			*color = Color { r: pixel[1], g: pixel[2], b: pixel[3] };
+		}
+		palette
+	}
+
+	/// The palette indices of the screen, with the width, height and pitch.
 	pub fn get(&mut self) -> (&mut [u8], usize, usize, usize) {
-		let width = self.surface.width() as usize;
-		let height = self.surface.height() as usize;
-		let pitch = self.surface.pitch() as usize;
+		(&mut self.pixels, self.width, self.height, self.width)
+	}
 
-		// If this panics, fix the surface creation code.
-		let buffer = self.surface.without_lock_mut().unwrap();
+	/// The screen as a canvas for the renderers.
+	pub fn canvas(&mut self) -> Canvas {
+		let (pixels, width, height, pitch) = self.get();
+		Canvas::new(pixels, width, height, pitch)
+	}
+
+	pub fn clear(&mut self, index : u8) {
+		for pixel in self.pixels.iter_mut() {
+			*pixel = index;
+		}
+	}
+
+	/// Sets how the screen is scaled to the window.
+	pub fn set_scale_options(&mut self, options : ScaleOptions) {
+		self.scale_options = options;
+	}
+
+	/// Sets the palettes of PLAYPAL, and shows the first one.
+	pub fn set_palettes(&mut self, palettes : Vec<Palette>) {
+		if palettes.is_empty() {
+			return;
+		}
+
+		self.palettes = palettes;
+		self.palette = 0;
+		self.update_colors();
+	}
+
+	/// Shows the screen through another palette, like the red of damage.
+	/// See `resources::palette_effect`.
+	pub fn set_palette(&mut self, index : usize) {
+		let index = index.min(self.palettes.len() - 1);
+		if index != self.palette {
+			self.palette = index;
+			self.update_colors();
+		}
+	}
+
+	/// Sets the gamma correction level, from 0 for none to 4.
+	pub fn set_gamma(&mut self, level : usize) {
+		let level = level.min(GAMMA_LEVELS - 1);
+		if level != self.gamma {
+			self.gamma = level;
+			self.update_colors();
+		}
+	}
+
+	pub fn gamma(&self) -> usize {
+		self.gamma
+	}
 
-		(buffer, width, height, pitch)
+	fn update_colors(&mut self) {
+		let palette = &self.palettes[self.palette];
+		for (pixel, color) in self.colors.iter_mut().zip(palette.colors.iter()) {
+			*pixel = [0x00u8, gamma_correct(color.r, self.gamma), gamma_correct(color.g, self.gamma), gamma_correct(color.b, self.gamma)];
+		}
 	}
 
-	pub fn clear(&mut self, r : u8, g : u8, b : u8) {
+	/// Draws a patch with its offsets applied, like V_DrawPatch does. Parts
+	/// outside the framebuffer are clipped.
+	pub fn draw_patch(&mut self, patch : &Patch, x : i32, y : i32) {
 		let (screen, width, height, pitch) = self.get();
+		let x = x - patch.left_offset;
+		let y = y - patch.top_offset;
+
+		for (column_x, posts) in patch.columns.iter().enumerate() {
+			let sx = x + column_x as i32;
+			if sx < 0 || sx >= width as i32 {
+				continue;
+			}
+
+			for post in posts {
+				for (i, &index) in post.pixels.iter().enumerate() {
+					let sy = y + post.top + i as i32;
+					if sy < 0 || sy >= height as i32 {
+						continue;
+					}
+
+					screen[sy as usize * pitch + sx as usize] = index;
+				}
+			}
+		}
+	}
+
+	/// Turns the screen into colors, and shows it in the window if there is
+	/// one.
+	pub fn present(&mut self, event_pump : Option<&sdl2::EventPump>) -> bool {
+		for (pixel, &index) in self.converted.iter_mut().zip(self.pixels.iter()) {
+			*pixel = self.colors[index as usize];
+		}
+
+		let (output, event_pump) = match (self.window.as_mut(), event_pump) {
+			(Some(output), Some(event_pump)) => (output, event_pump),
+			_ => return true
+		};
 
-		for y in 0..height {
-			for x in 0..width {
-				screen[y * pitch + x * 4 + 0] = 0x00u8;
-				screen[y * pitch + x * 4 + 1] = r;
-				screen[y * pitch + x * 4 + 2] = g;
-				screen[y * pitch + x * 4 + 3] = b;
+		match *output {
+			WindowOutput::Surface { ref window, ref mut surface } => {
+				present_surface(window, surface, event_pump, &self.converted, self.width, self.height, &self.scale_options)
 			}
+			WindowOutput::Vsync(ref mut output) => output.present(&self.converted, self.width, self.height, &self.scale_options)
 		}
 	}
+}
+
+fn present_surface(window : &sdl2::video::Window, output : &mut sdl2::surface::Surface, event_pump : &sdl2::EventPump,
+	converted : &[[u8; 4]], width : usize, height : usize, scale_options : &ScaleOptions) -> bool {
+
+	let surface = match window.surface(event_pump) {
+		Ok(value) => value,
+		Err(_) => return false
+	};
+
+	// The window may have been resized.
+	let (window_width, window_height) = surface.size();
+	if output.size() != (window_width, window_height) {
+		*output = match sdl2::surface::Surface::new(window_width, window_height, sdl2::pixels::PixelFormatEnum::BGRX8888) {
+			Ok(value) => value,
+			Err(_) => return false
+		};
+	}
+
+	{
+		let rect = scaling::scaled_rect(width, height, window_width as usize, window_height as usize, scale_options);
+		let pitch = output.pitch() as usize;
+
+		// If this panics, fix the surface creation code.
+		let buffer = output.without_lock_mut().unwrap();
+		scaling::scale(converted, width, height, buffer, pitch, rect, scale_options.filter);
+	}
 
-	pub fn present(&self, event_pump : &sdl2::EventPump) -> bool {
-		let surface = match self.window.surface(event_pump) {
+	// Stupid SDL wrapper isn't implementing AsMut properly, so we need to do
+	// unsafe hack.
+	unsafe {
+		let _ = output.blit(None, sdl2::surface::Surface::from_ll(surface.raw()), None);
+	}
+
+	window.update_surface().unwrap();
+	true
+}
+
+impl VsyncOutput {
+	fn present(&mut self, converted : &[[u8; 4]], width : usize, height : usize, scale_options : &ScaleOptions) -> bool {
+		// The window may have been resized.
+		let (window_width, window_height) = match self.renderer.output_size() {
 			Ok(value) => value,
 			Err(_) => return false
 		};
+		if self.texture_size != (window_width, window_height) {
+			self.texture = match self.renderer.create_texture_streaming(sdl2::pixels::PixelFormatEnum::BGRX8888, (window_width, window_height)) {
+				Ok(value) => value,
+				Err(_) => return false
+			};
+			self.texture_size = (window_width, window_height);
+			self.scaled.resize(window_width as usize * window_height as usize * 4, 0);
+		}
 
-		// Stupid SDL wrapper isn't implementing AsMut properly, so we need to do
-		// unsafe hack.
-		unsafe {
-			let _ = self.surface.blit(None, sdl2::surface::Surface::from_ll(surface.raw()), None);
+		let rect = scaling::scaled_rect(width, height, window_width as usize, window_height as usize, scale_options);
+		let pitch = window_width as usize * 4;
+		scaling::scale(converted, width, height, &mut self.scaled, pitch, rect, scale_options.filter);
+		if self.texture.update(None, &self.scaled, pitch).is_err() {
+			return false;
 		}
 
-		self.window.update_surface().unwrap();
+		// Waits for the refresh.
+		self.renderer.copy(&self.texture, None, None);
+		self.renderer.present();
 		true
 	}
 }
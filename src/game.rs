@@ -0,0 +1,544 @@
+use std::f32;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use system::System;
+use system::Keycode;
+use system::KeyEvent;
+use framebuffer::Framebuffer;
+use hud::Font;
+use screenshot::ScreenshotFormat;
+use screenshot::save_screenshot;
+use resources::ResourceManager;
+use resources::bsp;
+use resources::LoadHandle;
+use resources::PackageLoadError;
+use resources::WadError;
+use resources::ColorMap;
+use resources::Palette;
+use resources::GAMMA_LEVELS;
+use render::DebugView;
+use render::RenderStats;
+use render::SoftwareRenderer;
+use render::ViewRenderer;
+use render::WireframeRenderer;
+use render::View;
+use render::VANILLA_MAX_VISPLANES;
+use audio::GenMidi;
+use audio::OplMode;
+use audio::OplMusic;
+use audio::Score;
+use soundsystem::SoundSystem;
+use soundsystem::DEFAULT_SOUND_CHANNELS;
+use timing::FrameLimiter;
+use timing::TicClock;
+use interpolation::LevelInterpolation;
+use interpolation::lerp;
+
+/// The level the game shows.
+pub const LEVEL : &str = "E1M1";
+
+// How far the camera turns and moves in a tic, like the normal turn speed
+// and about the walking speed of vanilla.
+const CAMERA_TURN : u16 = 640;
+const CAMERA_SPEED : i32 = 8;
+
+// How often the loaded packages are checked for changes.
+const RELOAD_INTERVAL_MS : u64 = 500;
+
+/// What the game loads and how it draws, from the command line.
+pub struct GameOptions {
+	/// The packages to load, the IWAD first and the PWADs over it.
+	pub paths : Vec<String>,
+	pub threads : usize,
+	pub visplane_limit : Option<usize>,
+	pub fps_cap : Option<u32>,
+	pub screenshot_dir : PathBuf,
+	pub screenshot_format : ScreenshotFormat
+}
+
+impl Default for GameOptions {
+	fn default() -> GameOptions {
+		GameOptions {
+			paths: vec!["./doom1.wad".to_string()],
+			threads: 1,
+			visplane_limit: None,
+			fps_cap: None,
+			screenshot_dir: PathBuf::from("."),
+			screenshot_format: ScreenshotFormat::Png
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum GameError {
+	/// A package failed to load, with its path.
+	Package(String, PackageLoadError),
+	/// No package has the level.
+	MissingLevel,
+	Level(PackageLoadError),
+	MissingLump(&'static str),
+	InvalidLump(&'static str, WadError)
+}
+
+impl fmt::Display for GameError {
+	fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			GameError::Package(ref path, ref err) => write!(f, "Cannot load {}: {:?}", path, err),
+			GameError::MissingLevel => write!(f, "Cannot find level {}", LEVEL),
+			GameError::Level(ref err) => write!(f, "Cannot load level {}: {:?}", LEVEL, err),
+			GameError::MissingLump(name) => write!(f, "Cannot find {}", name),
+			GameError::InvalidLump(name, ref err) => write!(f, "Cannot read {}: {:?}", name, err)
+		}
+	}
+}
+
+pub type GameResult<T> = Result<T, GameError>;
+
+struct Camera {
+	pos : (i32, i32),
+	angle : u16,
+	// The height of the eyes, on the floor under the camera
+	z : i32,
+
+	// Where the camera was before the last tic, to draw the frames between
+	// tics
+	previous_pos : (i32, i32),
+	previous_angle : u16,
+	previous_z : i32,
+
+	// Hacky movement system
+	forward_movement : i32,
+	side_movement : i32
+}
+
+impl Camera {
+	fn new(level : &bsp::Map, pos : (i32, i32), angle : u16) -> Camera {
+		let z = View::on_floor(level, pos.0, pos.1, angle).z;
+		Camera {
+			pos: pos,
+			angle: angle,
+			z: z,
+			previous_pos: pos,
+			previous_angle: angle,
+			previous_z: z,
+			forward_movement: 0i32,
+			side_movement: 0i32,
+		}
+	}
+
+	fn process_input(&mut self, system : &System) {
+		for &event in system.key_events() {
+			match event {
+				KeyEvent::Down(code) => {
+					match code {
+						Keycode::Left => self.side_movement -= 1,
+						Keycode::Right => self.side_movement += 1,
+						Keycode::Down => self.forward_movement -= 1,
+						Keycode::Up => self.forward_movement += 1,
+						_ => ()
+					}
+				}
+				KeyEvent::Up(code) => {
+					match code {
+						Keycode::Left => self.side_movement += 1,
+						Keycode::Right => self.side_movement -= 1,
+						Keycode::Down => self.forward_movement += 1,
+						Keycode::Up => self.forward_movement -= 1,
+						_ => ()
+					}
+				}
+			}
+		}
+	}
+
+	/// Moves the camera by a tic, over the level as of that tic.
+	fn tic(&mut self, level : &bsp::Map) {
+		self.previous_pos = self.pos;
+		self.previous_angle = self.angle;
+		self.previous_z = self.z;
+
+		// Turn
+		if self.side_movement > 0 {
+			self.angle = self.angle.wrapping_add(CAMERA_TURN);
+		} else if self.side_movement < 0 {
+			self.angle = self.angle.wrapping_sub(CAMERA_TURN);
+		}
+
+		// Move forward
+		let angle = (self.angle as f32) / (0x10000 as f32) * f32::consts::PI * 2f32;
+		let dir_x = (angle.cos() * (0x10000 as f32)) as i32;
+		let dir_y = (angle.sin() * (0x10000 as f32)) as i32;
+		self.pos.0 += dir_x * CAMERA_SPEED * self.forward_movement;
+		self.pos.1 += dir_y * CAMERA_SPEED * self.forward_movement;
+		self.z = View::on_floor(level, self.pos.0, self.pos.1, self.angle).z;
+	}
+
+	/// The view `fraction` of the way through the last tic. The height moves
+	/// smoothly too, over steps and with moving floors.
+	fn view(&self, fraction : f32) -> View {
+		let turn = self.angle.wrapping_sub(self.previous_angle) as i16;
+		View {
+			x: lerp(self.previous_pos.0, self.pos.0, fraction),
+			y: lerp(self.previous_pos.1, self.pos.1, fraction),
+			z: lerp(self.previous_z, self.z, fraction),
+			angle: self.previous_angle.wrapping_add((turn as f32 * fraction) as i16 as u16),
+			pitch: 0,
+			extra_light: 0
+		}
+	}
+}
+
+/// Draws a progress bar while the loader thread works. The moving block
+/// shows that the game hasn't hung on a big load. The palettes aren't loaded
+/// yet, so the framebuffer shows the indices as grays.
+fn draw_loading_screen(fb : &mut Framebuffer, done : usize, total : usize, frame : u32) {
+	fb.clear(0);
+
+	let (screen, width, height, pitch) = fb.get();
+	let bar_width = width / 2;
+	let bar_height = 16;
+	let left = (width - bar_width) / 2;
+	let top = (height - bar_height) / 2;
+	let filled = if total == 0 { bar_width } else { bar_width * done / total };
+	let block = (frame as usize * 8) % bar_width;
+
+	for y in top..top + bar_height {
+		for x in left..left + bar_width {
+			let border = y == top || y == top + bar_height - 1 || x == left || x == left + bar_width - 1;
+			let bx = x - left;
+			let value = if border || bx < filled {
+				0xC0u8
+			} else if bx >= block && bx < block + 16 {
+				0x60u8
+			} else {
+				continue;
+			};
+			screen[y * pitch + x] = value;
+		}
+	}
+}
+
+/// Draws the counts of the frame in the top left corner.
+fn draw_stats_overlay(fb : &mut Framebuffer, font : &Font, stats : &RenderStats) {
+	let lines = [
+		format!("SEGS {}", stats.segs),
+		format!("VISPLANES {}", stats.visplanes),
+		format!("DRAWSEGS {}", stats.drawsegs),
+		format!("SPRITES {}", stats.sprites)
+	];
+	for (i, line) in lines.iter().enumerate() {
+		font.draw_text(fb, line, 2, 2 + i as i32 * font.line_height());
+	}
+}
+
+/// Loads the packages while showing the loading screen. Returns false if
+/// the system quits first.
+fn load(system : &mut System, framebuffer : &mut Framebuffer, resource_manager : &Arc<ResourceManager>, paths : &[String]) -> GameResult<bool> {
+	let mut package_loads : Vec<(String, LoadHandle<()>)> = paths.iter()
+		.map(|path| (path.clone(), resource_manager.load_package_async(path)))
+		.collect();
+	let mut map_load = resource_manager.load_map_async(LEVEL);
+	let total = package_loads.len() + 1;
+
+	// Headless runs wait instead, so the script starts with the first frame
+	// of the game however long the loads take.
+	if system.is_headless() {
+		for (path, handle) in package_loads {
+			if let Err(err) = handle.wait() {
+				return Err(GameError::Package(path, err));
+			}
+		}
+		return match map_load.wait() {
+			Ok(Some(_)) => Ok(true),
+			Ok(None) => Err(GameError::MissingLevel),
+			Err(err) => Err(GameError::Level(err))
+		};
+	}
+
+	let mut frame = 0;
+	loop {
+		if !system.handle_events() {
+			return Ok(false);
+		}
+
+		let mut done = 0;
+		for &mut (ref path, ref mut handle) in package_loads.iter_mut() {
+			if let Some(Err(err)) = handle.poll() {
+				return Err(GameError::Package(path.clone(), err));
+			}
+			if handle.is_done() {
+				done += 1;
+			}
+		}
+
+		if map_load.is_done() {
+			return match map_load.poll() {
+				Some(Ok(Some(_))) | None => Ok(true),
+				Some(Ok(None)) => Err(GameError::MissingLevel),
+				Some(Err(err)) => Err(GameError::Level(err))
+			};
+		}
+
+		draw_loading_screen(framebuffer, done, total, frame);
+		system.present(framebuffer);
+		frame += 1;
+	}
+}
+
+/// Loads the packages and runs the game on `framebuffer` until the system
+/// quits. Headless systems take one tic a frame, so a script of key events
+/// always leaves the same pixels.
+pub fn run(system : &mut System, framebuffer : &mut Framebuffer, options : &GameOptions) -> GameResult<()> {
+	let resource_manager = Arc::new(ResourceManager::new());
+
+	// They load on the loader thread while the window stays responsive.
+	if !try!(load(system, framebuffer, &resource_manager, &options.paths)) {
+		return Ok(());
+	}
+
+	let mut sound_system = match system.create_audio_backend(44100) {
+		Ok(backend) => Some(SoundSystem::new(Box::new(backend), DEFAULT_SOUND_CHANNELS)),
+		Err(err) => {
+			println!("Sound disabled: {}", err);
+			None
+		}
+	};
+
+	if let Some(ref mut sound_system) = sound_system {
+		if let (Some(genmidi), Some(music)) = (resource_manager.find_lump("GENMIDI"), resource_manager.find_lump(&format!("D_{}", LEVEL))) {
+			match (GenMidi::new(&genmidi), Score::new(&music)) {
+				(Ok(genmidi), Ok(score)) => {
+					let music = OplMusic::new(genmidi, score, sound_system.sample_rate(), OplMode::Opl3);
+					sound_system.play_music(music, true);
+				}
+				(Err(err), _) | (_, Err(err)) => println!("Cannot play music: {:?}", err)
+			}
+		}
+	}
+
+	match resource_manager.find_lump("PLAYPAL").map(|data| Palette::read_all(&data)) {
+		Some(Ok(palettes)) => framebuffer.set_palettes(palettes),
+		Some(Err(err)) => return Err(GameError::InvalidLump("PLAYPAL", err)),
+		None => return Err(GameError::MissingLump("PLAYPAL"))
+	}
+	let colormap = match resource_manager.find_lump("COLORMAP").map(|data| ColorMap::read(&data)) {
+		Some(Ok(value)) => value,
+		Some(Err(err)) => return Err(GameError::InvalidLump("COLORMAP", err)),
+		None => return Err(GameError::MissingLump("COLORMAP"))
+	};
+
+	let mut software_renderer = SoftwareRenderer::new(&resource_manager, &colormap);
+	software_renderer.set_threads(options.threads);
+	software_renderer.set_visplane_limit(options.visplane_limit);
+	let mut renderers : Vec<Box<dyn ViewRenderer>> = vec![Box::new(software_renderer), Box::new(WireframeRenderer::new())];
+	let mut renderer = 0;
+	let mut debug_view = DebugView::None;
+	let mut stats_overlay = false;
+	let font = Font::small(&resource_manager);
+
+	let mut level = match resource_manager.find_map(LEVEL) {
+		Some(value) => value,
+		None => return Err(GameError::MissingLevel)
+	};
+	let mut interpolation = LevelInterpolation::new(&level);
+	let mut camera = Camera::new(&level, (0, 0), 0);
+	let mut last_reload_check = Instant::now();
+	let mut visplane_overflow = false;
+
+	// The camera and the level move in tics, and the frames are drawn as
+	// often as they can be, between the last two tics.
+	let mut clock = if system.is_headless() { TicClock::fixed() } else { TicClock::new() };
+	let mut frame_limiter = FrameLimiter::new(options.fps_cap);
+
+	while system.handle_events() {
+		camera.process_input(system);
+		for _ in 0..clock.tics_due() {
+			camera.tic(&level);
+			interpolation.tic(&level);
+		}
+
+		// F11 steps through the gamma levels, like vanilla.
+		if system.key_events().any(|&event| event == KeyEvent::Down(Keycode::F11)) {
+			let gamma = (framebuffer.gamma() + 1) % GAMMA_LEVELS;
+			framebuffer.set_gamma(gamma);
+			println!("Gamma correction level {}", gamma);
+		}
+
+		// F5 switches between the renderers.
+		if system.key_events().any(|&event| event == KeyEvent::Down(Keycode::F5)) {
+			renderer = (renderer + 1) % renderers.len();
+			println!("Renderer: {}", renderers[renderer].name());
+		}
+
+		// F6 steps through the debug views, and F7 shows the counts of each
+		// frame.
+		if system.key_events().any(|&event| event == KeyEvent::Down(Keycode::F6)) {
+			debug_view = debug_view.next();
+			for renderer in renderers.iter_mut() {
+				renderer.set_debug_view(debug_view);
+			}
+			if debug_view != DebugView::None && options.threads > 1 {
+				println!("Debug view: {}, drawn on one thread", debug_view.name());
+			} else {
+				println!("Debug view: {}", debug_view.name());
+			}
+		}
+		if system.key_events().any(|&event| event == KeyEvent::Down(Keycode::F7)) {
+			stats_overlay = !stats_overlay;
+		}
+
+		// Pick up maps saved by an editor. The level is rebuilt from the new
+		// data while the camera stays put.
+		if last_reload_check.elapsed() >= Duration::from_millis(RELOAD_INTERVAL_MS) {
+			last_reload_check = Instant::now();
+			match resource_manager.reload_changed_packages() {
+				Ok(ref maps) if maps.iter().any(|name| name == LEVEL) => {
+					match resource_manager.find_map(LEVEL) {
+						Some(value) => {
+							level = value;
+							interpolation.reset(&level);
+							println!("Reloaded {}", LEVEL);
+						}
+						None => println!("Cannot reload level {}", LEVEL)
+					}
+				}
+				Ok(_) => (),
+				Err(err) => println!("Cannot reload package: {:?}", err)
+			}
+		}
+
+		if let Some(ref mut sound_system) = sound_system {
+			sound_system.set_listener(camera.pos, camera.angle);
+			sound_system.update();
+		}
+
+		{
+			let fraction = clock.fraction();
+			let view = camera.view(fraction);
+			let renderer = &mut renderers[renderer];
+			renderer.render_view(interpolation.interpolated(fraction), &view, framebuffer.canvas());
+
+			// Report when the view starts or stops overflowing, not every frame.
+			let stats = renderer.stats();
+			if stats.visplane_overflow != visplane_overflow {
+				visplane_overflow = stats.visplane_overflow;
+				if visplane_overflow {
+					println!("Visplane overflow: more than {} visplanes", VANILLA_MAX_VISPLANES);
+				} else {
+					println!("No visplane overflow");
+				}
+			}
+
+			if let Some(ref font) = font {
+				if stats_overlay {
+					draw_stats_overlay(framebuffer, font, &stats);
+				}
+			}
+		}
+
+		// Print Screen saves the frame just drawn.
+		if system.key_events().any(|&event| event == KeyEvent::Down(Keycode::PrintScreen)) {
+			match save_screenshot(framebuffer, &options.screenshot_dir, options.screenshot_format) {
+				Ok(path) => println!("Saved screenshot {}", path.display()),
+				Err(err) => println!("Cannot save screenshot: {}", err)
+			}
+		}
+
+		system.present(framebuffer);
+		frame_limiter.wait();
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use resources::testwad::*;
+	use render::VIEW_HEIGHT;
+	use super::*;
+
+	// Runs the game on a room of gray 40 walls, a gray 20 floor and a gray
+	// 60 ceiling, and returns the last frame.
+	fn run_script(file_name : &str, script : Vec<Vec<KeyEvent>>) -> (Vec<u8>, usize) {
+		let path = temp_path(file_name);
+		let mut lumps = room_map(LEVEL, &[]);
+		lumps.push(("PLAYPAL", playpal()));
+		lumps.push(("COLORMAP", colormap()));
+		lumps.push(("PNAMES", pnames(&["WALLPAT"])));
+		lumps.push(("TEXTURE1", texture1(&["WALL"])));
+		lumps.push(("WALLPAT", patch(40)));
+		lumps.push(("FLOOR", flat(20)));
+		lumps.push(("CEIL", flat(60)));
+		write_wad(&path, &lumps);
+
+		let mut system = System::headless(script);
+		let mut framebuffer = system.create_framebuffer(0, 0, 64, 40, false).unwrap();
+		let options = GameOptions {
+			paths: vec![path.to_string_lossy().into_owned()],
+			..GameOptions::default()
+		};
+		let result = run(&mut system, &mut framebuffer, &options);
+		let _ = fs::remove_file(&path);
+		result.unwrap();
+		(framebuffer.pixels().to_vec(), framebuffer.gamma())
+	}
+
+	// The colors down the middle column of a 64x40 frame.
+	fn middle_column(pixels : &[u8]) -> Vec<u8> {
+		(0..40).map(|y| pixels[y * 64 + 32]).collect()
+	}
+
+	fn count(column : &[u8], color : u8) -> usize {
+		column.iter().filter(|&&value| value == color).count()
+	}
+
+	#[test]
+	fn game_draws_the_room() {
+		let (pixels, _) = run_script("game-room", vec![Vec::<KeyEvent>::new(); 3]);
+		let column = middle_column(&pixels);
+		assert_eq!(column[0], 60);
+		assert_eq!(column[20], 40);
+		assert_eq!(column[39], 20);
+		assert_eq!(count(&column, 60) + count(&column, 40) + count(&column, 20), 40);
+	}
+
+	#[test]
+	fn keys_move_the_camera() {
+		let (still, _) = run_script("game-still", vec![Vec::<KeyEvent>::new(); 11]);
+		let mut script = vec![vec![KeyEvent::Down(Keycode::Up)]];
+		script.extend(vec![Vec::<KeyEvent>::new(); 10]);
+		let (moved, _) = run_script("game-moved", script.clone());
+		let (again, _) = run_script("game-again", script);
+
+		// Walking up to the far wall makes it taller.
+		assert!(count(&middle_column(&moved), 40) > count(&middle_column(&still), 40));
+		assert_eq!(moved, again);
+	}
+
+	#[test]
+	fn f11_steps_the_gamma() {
+		let (_, gamma) = run_script("game-gamma", vec![vec![KeyEvent::Down(Keycode::F11)], Vec::<KeyEvent>::new()]);
+		assert_eq!(gamma, 1);
+	}
+
+	#[test]
+	fn view_height_moves_between_tics() {
+		let path = temp_path("game-height");
+		write_wad(&path, &room_map(LEVEL, &[]));
+		let resources = ResourceManager::new();
+		resources.load_package(&path).unwrap();
+		let _ = fs::remove_file(&path);
+		let level = resources.find_map(LEVEL).unwrap();
+		let mut raised = (*level).clone();
+		raised.sectors[0].floor_height = 16 << 16;
+
+		let mut camera = Camera::new(&level, (0, 0), 0);
+		camera.tic(&raised);
+		assert_eq!(camera.view(0.5).z, (8 << 16) + VIEW_HEIGHT);
+		assert_eq!(camera.view(1.0).z, (16 << 16) + VIEW_HEIGHT);
+	}
+}
@@ -0,0 +1,259 @@
+use std::sync::Arc;
+use resources::ResourceManager;
+use resources::Patch;
+use framebuffer::Framebuffer;
+
+// Characters in the small HUD font, from '!' to '_'.
+const HU_FONTSTART : u8 = b'!';
+const HU_FONTEND : u8 = b'_';
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TextAlign {
+	Left,
+	Center,
+	Right
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TextLine {
+	pub text : String,
+	pub width : i32
+}
+
+/// Text broken into lines for a font. The lines are aligned inside a box that
+/// is `width` pixels wide.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TextLayout {
+	pub lines : Vec<TextLine>,
+	pub align : TextAlign,
+	pub width : i32,
+	pub line_height : i32
+}
+
+impl TextLayout {
+	pub fn height(&self) -> i32 {
+		self.lines.len() as i32 * self.line_height
+	}
+
+	/// Horizontal position of a line inside the layout box.
+	pub fn line_x(&self, line : usize) -> i32 {
+		match self.align {
+			TextAlign::Left => 0,
+			TextAlign::Center => (self.width - self.lines[line].width) / 2,
+			TextAlign::Right => self.width - self.lines[line].width
+		}
+	}
+}
+
+/// A set of patches used to draw text. Characters without a glyph are drawn
+/// as spaces.
+pub struct Font {
+	glyphs : Vec<Option<Arc<Patch>>>,
+	space_width : i32,
+	line_height : i32,
+	uppercase : bool
+}
+
+impl Font {
+	/// Creates a font from glyph patches. `lump_name` gives the lump for each
+	/// character, or None if the font doesn't have it.
+	pub fn from_lumps<F>(resources : &ResourceManager, space_width : i32, line_spacing : i32, uppercase : bool, lump_name : F) -> Option<Font>
+		where F : Fn(u8) -> Option<String> {
+
+		let mut glyphs = Vec::<Option<Arc<Patch>>>::with_capacity(128);
+		let mut height = 0;
+
+		for c in 0..128u8 {
+			let patch = lump_name(c).and_then(|name| resources.find_patch(&name));
+
+			if let Some(ref patch) = patch {
+				if patch.height > height {
+					height = patch.height;
+				}
+			}
+			glyphs.push(patch);
+		}
+
+		if height == 0 {
+			return None;
+		}
+
+		Some(Font {
+			glyphs: glyphs,
+			space_width: space_width,
+			line_height: height + line_spacing,
+			uppercase: uppercase
+		})
+	}
+
+	/// The small font used by HUD messages, the menus and the intermission
+	/// and finale text (STCFN033 to STCFN095).
+	pub fn small(resources : &ResourceManager) -> Option<Font> {
+		Font::from_lumps(resources, 4, 1, true, |c| {
+			if c >= HU_FONTSTART && c <= HU_FONTEND {
+				Some(format!("STCFN{:03}", c))
+			} else {
+				None
+			}
+		})
+	}
+
+	/// The big menu font. The IWADs don't have one, but many PWADs ship the
+	/// FONTBxx patches, numbered from '!' as 01.
+	pub fn big(resources : &ResourceManager) -> Option<Font> {
+		Font::from_lumps(resources, 8, 2, true, |c| {
+			if c >= HU_FONTSTART && c <= HU_FONTEND {
+				Some(format!("FONTB{:02}", c - b' '))
+			} else {
+				None
+			}
+		})
+	}
+
+	/// The tall red numbers of the status bar (STTNUM0 to STTNUM9, with
+	/// STTMINUS and STTPRCNT).
+	pub fn status_bar_large(resources : &ResourceManager) -> Option<Font> {
+		Font::from_lumps(resources, 14, 0, false, |c| match c {
+			b'0'..=b'9' => Some(format!("STTNUM{}", c - b'0')),
+			b'-' => Some("STTMINUS".to_string()),
+			b'%' => Some("STTPRCNT".to_string()),
+			_ => None
+		})
+	}
+
+	/// The small yellow (STYSNUM) or grey (STGNUM) status bar numbers.
+	pub fn status_bar_small(resources : &ResourceManager, yellow : bool) -> Option<Font> {
+		let prefix = if yellow { "STYSNUM" } else { "STGNUM" };
+		Font::from_lumps(resources, 4, 0, false, |c| match c {
+			b'0'..=b'9' => Some(format!("{}{}", prefix, c - b'0')),
+			_ => None
+		})
+	}
+
+	pub fn line_height(&self) -> i32 {
+		self.line_height
+	}
+
+	pub fn glyph(&self, c : char) -> Option<&Patch> {
+		let c = if self.uppercase { c.to_ascii_uppercase() } else { c };
+		if (c as u32) < 128 {
+			self.glyphs[c as usize].as_ref().map(|patch| &**patch)
+		} else {
+			None
+		}
+	}
+
+	pub fn char_width(&self, c : char) -> i32 {
+		match self.glyph(c) {
+			Some(patch) => patch.width,
+			None => self.space_width
+		}
+	}
+
+	/// Width of a single line of text.
+	pub fn text_width(&self, text : &str) -> i32 {
+		text.chars().map(|c| self.char_width(c)).sum()
+	}
+
+	/// Size of the text, with lines separated by '\n'.
+	pub fn measure(&self, text : &str) -> (i32, i32) {
+		let layout = self.layout(text, None, TextAlign::Left);
+		(layout.width, layout.height())
+	}
+
+	/// Breaks the text into lines. With `max_width` the lines are wrapped at
+	/// spaces, and words that don't fit on a line of their own are split.
+	pub fn layout(&self, text : &str, max_width : Option<i32>, align : TextAlign) -> TextLayout {
+		let mut lines = Vec::<TextLine>::new();
+
+		for paragraph in text.split('\n') {
+			let max_width = match max_width {
+				Some(value) => value,
+				None => {
+					lines.push(TextLine {
+						text: paragraph.to_string(),
+						width: self.text_width(paragraph)
+					});
+					continue;
+				}
+			};
+
+			let mut line = String::new();
+			let mut line_width = 0;
+
+			for word in paragraph.split(' ') {
+				let word_width = self.text_width(word);
+				let space_width = if line.is_empty() { 0 } else { self.char_width(' ') };
+
+				if !line.is_empty() && line_width + space_width + word_width > max_width {
+					lines.push(TextLine {
+						text: line,
+						width: line_width
+					});
+					line = String::new();
+					line_width = 0;
+				} else if !line.is_empty() {
+					line.push(' ');
+					line_width += space_width;
+				}
+
+				if word_width <= max_width {
+					line.push_str(word);
+					line_width += word_width;
+					continue;
+				}
+
+				// The word is too long for any line, so split it.
+				for c in word.chars() {
+					let c_width = self.char_width(c);
+					if !line.is_empty() && line_width + c_width > max_width {
+						lines.push(TextLine {
+							text: line,
+							width: line_width
+						});
+						line = String::new();
+						line_width = 0;
+					}
+					line.push(c);
+					line_width += c_width;
+				}
+			}
+
+			lines.push(TextLine {
+				text: line,
+				width: line_width
+			});
+		}
+
+		let widest = lines.iter().map(|line| line.width).max().unwrap_or(0);
+
+		TextLayout {
+			lines: lines,
+			align: align,
+			width: match max_width {
+				Some(value) => value,
+				None => widest
+			},
+			line_height: self.line_height
+		}
+	}
+
+	/// Draws a single line of text with its top-left corner at (x, y).
+	pub fn draw_text(&self, fb : &mut Framebuffer, text : &str, x : i32, y : i32) {
+		let mut cx = x;
+		for c in text.chars() {
+			if let Some(patch) = self.glyph(c) {
+				fb.draw_patch(patch, cx, y);
+			}
+			cx += self.char_width(c);
+		}
+	}
+
+	/// Draws laid out text with the top-left corner of the layout box at
+	/// (x, y).
+	pub fn draw_layout(&self, fb : &mut Framebuffer, layout : &TextLayout, x : i32, y : i32) {
+		for (i, line) in layout.lines.iter().enumerate() {
+			self.draw_text(fb, &line.text, x + layout.line_x(i), y + i as i32 * layout.line_height);
+		}
+	}
+}
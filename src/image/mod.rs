@@ -0,0 +1,6 @@
+pub use self::png::write_png_rgb;
+pub use self::png::write_png_indexed;
+pub use self::pcx::write_pcx;
+
+mod png;
+mod pcx;
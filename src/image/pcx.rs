@@ -0,0 +1,132 @@
+use std::io;
+use std::io::Write;
+use resources::Palette;
+
+// The longest run a single PCX count byte can hold
+const MAX_RUN : usize = 63;
+
+/// Writes palette indices as a 256 color PCX file, like WritePCXfile of
+/// vanilla. The rows are run length encoded, and the palette follows them.
+pub fn write_pcx<W : Write>(writer : &mut W, width : u32, height : u32, pixels : &[u8], palette : &Palette) -> io::Result<()> {
+	if width == 0 || height == 0 || width > 0xFFFF || height > 0xFFFF {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "PCX images are 1 to 65535 pixels wide and high"));
+	}
+	if pixels.len() != width as usize * height as usize {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "the pixels don't fit the image size"));
+	}
+
+	let mut header = Vec::<u8>::with_capacity(128);
+	header.extend_from_slice(&[
+		0x0a, // manufacturer
+		5,    // version
+		1,    // run length encoding
+		8     // bits per pixel
+	]);
+	push_u16(&mut header, 0);
+	push_u16(&mut header, 0);
+	push_u16(&mut header, width as u16 - 1);
+	push_u16(&mut header, height as u16 - 1);
+	push_u16(&mut header, width as u16);
+	push_u16(&mut header, height as u16);
+	header.extend_from_slice(&[0u8; 48]);
+	header.push(0); // reserved
+	header.push(1); // color planes
+	push_u16(&mut header, width as u16);
+	push_u16(&mut header, 2); // palette type, as vanilla writes it
+	header.resize(128, 0);
+	try!(writer.write_all(&header));
+
+	let mut data = Vec::<u8>::with_capacity(pixels.len());
+	for row in pixels.chunks(width as usize) {
+		let mut x = 0;
+		while x < row.len() {
+			let value = row[x];
+			let run = row[x..].iter().take(MAX_RUN).take_while(|&&other| other == value).count();
+
+			// Values with the top bits set would read as counts.
+			if run > 1 || value & 0xc0 == 0xc0 {
+				data.push(0xc0 | run as u8);
+			}
+			data.push(value);
+			x += run;
+		}
+	}
+	try!(writer.write_all(&data));
+
+	let mut colors = Vec::<u8>::with_capacity(769);
+	colors.push(0x0c);
+	for color in palette.colors.iter() {
+		colors.extend_from_slice(&[color.r, color.g, color.b]);
+	}
+	writer.write_all(&colors)
+}
+
+fn push_u16(buffer : &mut Vec<u8>, value : u16) {
+	buffer.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+}
+
+#[cfg(test)]
+mod tests {
+	use resources::Color;
+	use super::*;
+
+	fn test_palette() -> Palette {
+		let mut palette = Palette {
+			colors: [Color { r: 0, g: 0, b: 0 }; 256]
+		};
+		for (i, color) in palette.colors.iter_mut().enumerate() {
+			*color = Color { r: i as u8, g: 255 - i as u8, b: (i * 7) as u8 };
+		}
+		palette
+	}
+
+	// Reads the size, pixels and palette back from a PCX file.
+	fn decode(data : &[u8]) -> (u32, u32, Vec<u8>, Vec<u8>) {
+		let width = (data[8] as u32 | (data[9] as u32) << 8) + 1;
+		let height = (data[10] as u32 | (data[11] as u32) << 8) + 1;
+		let mut pixels = Vec::<u8>::new();
+		let mut pos = 128;
+		while pixels.len() < (width * height) as usize {
+			let byte = data[pos];
+			pos += 1;
+			if byte & 0xc0 == 0xc0 {
+				for _ in 0..byte & 0x3f {
+					pixels.push(data[pos]);
+				}
+				pos += 1;
+			} else {
+				pixels.push(byte);
+			}
+		}
+		assert_eq!(data[pos], 0x0c);
+		(width, height, pixels, data[pos + 1..].to_vec())
+	}
+
+	#[test]
+	fn pcx_round_trip() {
+		// Runs longer than a count byte holds, single pixels and values that
+		// need a count byte
+		let (width, height) = (100, 3);
+		let mut pixels = vec![7u8; 100];
+		pixels.extend((0..100).map(|x| x as u8));
+		pixels.extend((0..100).map(|x| 0xc0 + (x / 10) as u8));
+
+		let mut data = Vec::<u8>::new();
+		write_pcx(&mut data, width, height, &pixels, &test_palette()).unwrap();
+		let (decoded_width, decoded_height, decoded, palette) = decode(&data);
+
+		assert_eq!((decoded_width, decoded_height), (width, height));
+		assert_eq!(decoded, pixels);
+		assert_eq!(palette.len(), 768);
+		assert_eq!(&palette[3 * 10..3 * 11], &[10, 245, 70]);
+	}
+
+	#[test]
+	fn empty_pcx_is_an_error() {
+		let mut data = Vec::<u8>::new();
+		assert!(write_pcx(&mut data, 0, 10, &[], &test_palette()).is_err());
+		assert_eq!(write_pcx(&mut data, 70000, 1, &[0; 70000], &test_palette()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+		assert_eq!(write_pcx(&mut data, 4, 4, &[0; 15], &test_palette()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+		assert!(data.is_empty());
+	}
+}
@@ -0,0 +1,418 @@
+use std::io;
+use std::io::Write;
+use resources::Palette;
+
+const PNG_SIGNATURE : [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Deflate length and distance code tables
+const LENGTH_BASE : [u32; 29] = [
+	3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+	35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+const LENGTH_EXTRA : [u32; 29] = [
+	0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+	3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+const DIST_BASE : [u32; 30] = [
+	1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+	257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+const DIST_EXTRA : [u32; 30] = [
+	0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+	7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+];
+
+const WINDOW_SIZE : usize = 32768;
+const MIN_MATCH : usize = 3;
+const MAX_MATCH : usize = 258;
+const MAX_CHAIN : usize = 64;
+const HASH_SIZE : usize = 1 << 15;
+
+/// Writes 8-bit RGB pixels as a PNG file.
+pub fn write_png_rgb<W : Write>(writer : &mut W, width : u32, height : u32, pixels : &[u8]) -> io::Result<()> {
+	assert_eq!(pixels.len(), (width * height * 3) as usize);
+	write_png(writer, width, height, 2, None, pixels, width as usize * 3)
+}
+
+/// Writes palette indices as a PNG file with the palette.
+pub fn write_png_indexed<W : Write>(writer : &mut W, width : u32, height : u32, pixels : &[u8], palette : &Palette) -> io::Result<()> {
+	assert_eq!(pixels.len(), (width * height) as usize);
+	write_png(writer, width, height, 3, Some(palette), pixels, width as usize)
+}
+
+fn write_png<W : Write>(writer : &mut W, width : u32, height : u32, color_type : u8, palette : Option<&Palette>,
+	pixels : &[u8], row_size : usize) -> io::Result<()> {
+
+	try!(writer.write_all(&PNG_SIGNATURE));
+
+	let mut header = Vec::<u8>::new();
+	push_u32(&mut header, width);
+	push_u32(&mut header, height);
+	header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+	try!(write_chunk(writer, b"IHDR", &header));
+
+	if let Some(palette) = palette {
+		let mut colors = Vec::<u8>::with_capacity(768);
+		for color in palette.colors.iter() {
+			colors.extend_from_slice(&[color.r, color.g, color.b]);
+		}
+		try!(write_chunk(writer, b"PLTE", &colors));
+	}
+
+	// Every row starts with the filter type, which is always none.
+	let mut raw = Vec::<u8>::with_capacity((row_size + 1) * height as usize);
+	for row in pixels.chunks(row_size) {
+		raw.push(0);
+		raw.extend_from_slice(row);
+	}
+
+	try!(write_chunk(writer, b"IDAT", &zlib_compress(&raw)));
+	write_chunk(writer, b"IEND", &[])
+}
+
+fn write_chunk<W : Write>(writer : &mut W, chunk_type : &[u8; 4], data : &[u8]) -> io::Result<()> {
+	let mut length = Vec::<u8>::new();
+	push_u32(&mut length, data.len() as u32);
+	try!(writer.write_all(&length));
+	try!(writer.write_all(chunk_type));
+	try!(writer.write_all(data));
+
+	let crc = crc32(crc32(0xFFFFFFFF, chunk_type), data) ^ 0xFFFFFFFF;
+	let mut crc_bytes = Vec::<u8>::new();
+	push_u32(&mut crc_bytes, crc);
+	writer.write_all(&crc_bytes)
+}
+
+fn push_u32(buffer : &mut Vec<u8>, value : u32) {
+	buffer.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+fn crc32(mut crc : u32, data : &[u8]) -> u32 {
+	for &byte in data {
+		crc ^= byte as u32;
+		for _ in 0..8 {
+			crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+		}
+	}
+	crc
+}
+
+fn adler32(data : &[u8]) -> u32 {
+	let mut a = 1u32;
+	let mut b = 0u32;
+	for chunk in data.chunks(5552) {
+		for &byte in chunk {
+			a += byte as u32;
+			b += a;
+		}
+		a %= 65521;
+		b %= 65521;
+	}
+	(b << 16) | a
+}
+
+struct BitWriter {
+	bytes : Vec<u8>,
+	bits : u32,
+	num_bits : u32
+}
+
+impl BitWriter {
+	fn write(&mut self, value : u32, num_bits : u32) {
+		self.bits |= value << self.num_bits;
+		self.num_bits += num_bits;
+		while self.num_bits >= 8 {
+			self.bytes.push(self.bits as u8);
+			self.bits >>= 8;
+			self.num_bits -= 8;
+		}
+	}
+
+	/// Writes a Huffman code, which is stored starting from the most
+	/// significant bit.
+	fn write_code(&mut self, code : u32, num_bits : u32) {
+		let mut reversed = 0;
+		for i in 0..num_bits {
+			reversed |= ((code >> i) & 1) << (num_bits - 1 - i);
+		}
+		self.write(reversed, num_bits);
+	}
+
+	fn write_literal(&mut self, symbol : u32) {
+		match symbol {
+			0..=143 => self.write_code(0x30 + symbol, 8),
+			144..=255 => self.write_code(0x190 + symbol - 144, 9),
+			256..=279 => self.write_code(symbol - 256, 7),
+			_ => self.write_code(0xC0 + symbol - 280, 8)
+		}
+	}
+
+	fn write_match(&mut self, length : usize, distance : usize) {
+		let length = length as u32;
+		let mut code = 0;
+		while code + 1 < LENGTH_BASE.len() && LENGTH_BASE[code + 1] <= length {
+			code += 1;
+		}
+		self.write_literal(257 + code as u32);
+		self.write(length - LENGTH_BASE[code], LENGTH_EXTRA[code]);
+
+		let distance = distance as u32;
+		let mut code = 0;
+		while code + 1 < DIST_BASE.len() && DIST_BASE[code + 1] <= distance {
+			code += 1;
+		}
+		self.write_code(code as u32, 5);
+		self.write(distance - DIST_BASE[code], DIST_EXTRA[code]);
+	}
+
+	fn finish(mut self) -> Vec<u8> {
+		if self.num_bits > 0 {
+			self.bytes.push(self.bits as u8);
+		}
+		self.bytes
+	}
+}
+
+/// Compresses the data into a zlib stream with a single fixed Huffman block.
+fn zlib_compress(data : &[u8]) -> Vec<u8> {
+	let mut writer = BitWriter {
+		bytes: vec![0x78, 0x01],
+		bits: 0,
+		num_bits: 0
+	};
+
+	// Final block, fixed Huffman codes
+	writer.write(1, 1);
+	writer.write(1, 2);
+
+	// Greedy LZ77 with hash chains over the last 32K
+	let mut head = vec![usize::max_value(); HASH_SIZE];
+	let mut prev = vec![usize::max_value(); WINDOW_SIZE];
+	let hash = |pos : usize| -> usize {
+		((data[pos] as usize) << 10 ^ (data[pos + 1] as usize) << 5 ^ data[pos + 2] as usize) & (HASH_SIZE - 1)
+	};
+
+	let mut pos = 0;
+	while pos < data.len() {
+		let mut best_length = 0;
+		let mut best_distance = 0;
+
+		if pos + MIN_MATCH <= data.len() {
+			let h = hash(pos);
+			let mut candidate = head[h];
+			let max_length = if data.len() - pos < MAX_MATCH { data.len() - pos } else { MAX_MATCH };
+
+			let mut chain = 0;
+			while candidate != usize::max_value() && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+				let mut length = 0;
+				while length < max_length && data[candidate + length] == data[pos + length] {
+					length += 1;
+				}
+				if length > best_length {
+					best_length = length;
+					best_distance = pos - candidate;
+					if length == max_length {
+						break;
+					}
+				}
+				candidate = prev[candidate % WINDOW_SIZE];
+				chain += 1;
+			}
+		}
+
+		let advance = if best_length >= MIN_MATCH {
+			writer.write_match(best_length, best_distance);
+			best_length
+		} else {
+			writer.write_literal(data[pos] as u32);
+			1
+		};
+
+		for p in pos..pos + advance {
+			if p + MIN_MATCH <= data.len() {
+				let h = hash(p);
+				prev[p % WINDOW_SIZE] = head[h];
+				head[h] = p;
+			}
+		}
+		pos += advance;
+	}
+
+	writer.write_literal(256);
+
+	let mut bytes = writer.finish();
+	push_u32(&mut bytes, adler32(data));
+	bytes
+}
+
+#[cfg(test)]
+mod tests {
+	use resources::Color;
+	use super::*;
+
+	// Reads the bits of a deflate stream, from the least significant bit of
+	// each byte.
+	struct BitReader<'a> {
+		data : &'a [u8],
+		pos : usize
+	}
+
+	impl<'a> BitReader<'a> {
+		fn bits(&mut self, num_bits : u32) -> u32 {
+			let mut value = 0;
+			for i in 0..num_bits {
+				let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+				value |= (bit as u32) << i;
+				self.pos += 1;
+			}
+			value
+		}
+
+		// Reads a fixed Huffman literal or length symbol.
+		fn symbol(&mut self) -> u32 {
+			let mut code = 0;
+			for _ in 0..7 {
+				code = (code << 1) | self.bits(1);
+			}
+			if code <= 0x17 {
+				return 256 + code;
+			}
+			code = (code << 1) | self.bits(1);
+			if code >= 0x30 && code <= 0xBF {
+				return code - 0x30;
+			}
+			if code >= 0xC0 && code <= 0xC7 {
+				return 280 + code - 0xC0;
+			}
+			code = (code << 1) | self.bits(1);
+			144 + code - 0x190
+		}
+
+		fn reversed(&mut self, num_bits : u32) -> u32 {
+			let mut code = 0;
+			for _ in 0..num_bits {
+				code = (code << 1) | self.bits(1);
+			}
+			code
+		}
+	}
+
+	// Inflates a zlib stream of fixed Huffman blocks, like the ones written
+	// by `zlib_compress`.
+	fn inflate(data : &[u8]) -> Vec<u8> {
+		assert_eq!(&data[..2], &[0x78, 0x01]);
+		let mut reader = BitReader { data: &data[2..], pos: 0 };
+		let mut out = Vec::<u8>::new();
+		loop {
+			let last = reader.bits(1);
+			assert_eq!(reader.bits(2), 1);
+			loop {
+				let symbol = reader.symbol();
+				if symbol < 256 {
+					out.push(symbol as u8);
+					continue;
+				}
+				if symbol == 256 {
+					break;
+				}
+				let code = (symbol - 257) as usize;
+				let length = LENGTH_BASE[code] + reader.bits(LENGTH_EXTRA[code]);
+				let code = reader.reversed(5) as usize;
+				let distance = DIST_BASE[code] + reader.bits(DIST_EXTRA[code]);
+				for _ in 0..length {
+					let byte = out[out.len() - distance as usize];
+					out.push(byte);
+				}
+			}
+			if last == 1 {
+				break;
+			}
+		}
+
+		let end = 2 + (reader.pos + 7) / 8;
+		let adler = &data[end..end + 4];
+		assert_eq!(((adler[0] as u32) << 24) | ((adler[1] as u32) << 16) | ((adler[2] as u32) << 8) | adler[3] as u32, adler32(&out));
+		out
+	}
+
+	fn read_u32(data : &[u8]) -> u32 {
+		((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | data[3] as u32
+	}
+
+	// Reads the chunks of a PNG file, checking their CRCs.
+	fn chunks(data : &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+		assert_eq!(&data[..8], &PNG_SIGNATURE);
+		let mut chunks = Vec::<([u8; 4], Vec<u8>)>::new();
+		let mut pos = 8;
+		while pos < data.len() {
+			let length = read_u32(&data[pos..]) as usize;
+			let chunk_type = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+			let chunk_data = &data[pos + 8..pos + 8 + length];
+			let crc = crc32(crc32(0xFFFFFFFF, &chunk_type), chunk_data) ^ 0xFFFFFFFF;
+			assert_eq!(read_u32(&data[pos + 8 + length..]), crc);
+			chunks.push((chunk_type, chunk_data.to_vec()));
+			pos += 12 + length;
+		}
+		chunks
+	}
+
+	// Reads the size, color type and rows back from a PNG file.
+	fn decode(data : &[u8]) -> (u32, u32, u8, Vec<u8>, Option<Vec<u8>>) {
+		let chunks = chunks(data);
+		assert_eq!(&chunks[0].0, b"IHDR");
+		assert_eq!(&chunks[chunks.len() - 1].0, b"IEND");
+		let header = &chunks[0].1;
+		let (width, height, color_type) = (read_u32(&header[0..]), read_u32(&header[4..]), header[9]);
+		let palette = chunks.iter().find(|chunk| &chunk.0 == b"PLTE").map(|chunk| chunk.1.clone());
+		let idat = &chunks.iter().find(|chunk| &chunk.0 == b"IDAT").unwrap().1;
+
+		let raw = inflate(idat);
+		let row_size = raw.len() / height as usize;
+		let mut pixels = Vec::<u8>::new();
+		for row in raw.chunks(row_size) {
+			assert_eq!(row[0], 0);
+			pixels.extend_from_slice(&row[1..]);
+		}
+		(width, height, color_type, pixels, palette)
+	}
+
+	#[test]
+	fn rgb_png_round_trip() {
+		// Gradients and long repeats, so both literals and matches are written
+		let (width, height) = (70, 40);
+		let mut pixels = Vec::<u8>::new();
+		for y in 0..height {
+			for x in 0..width {
+				if y < 20 {
+					pixels.extend_from_slice(&[x as u8 * 3, y as u8 * 5, (x * y) as u8]);
+				} else {
+					pixels.extend_from_slice(&[200, 10, 10]);
+				}
+			}
+		}
+
+		let mut data = Vec::<u8>::new();
+		write_png_rgb(&mut data, width, height, &pixels).unwrap();
+		assert_eq!(decode(&data), (width, height, 2, pixels, None));
+	}
+
+	#[test]
+	fn indexed_png_round_trip() {
+		let mut palette = Palette {
+			colors: [Color { r: 0, g: 0, b: 0 }; 256]
+		};
+		for (i, color) in palette.colors.iter_mut().enumerate() {
+			*color = Color { r: i as u8, g: 0, b: 255 - i as u8 };
+		}
+		let pixels : Vec<u8> = (0..320 * 20).map(|i| (i % 320 / 16) as u8).collect();
+
+		let mut data = Vec::<u8>::new();
+		write_png_indexed(&mut data, 320, 20, &pixels, &palette).unwrap();
+		let (width, height, color_type, decoded, colors) = decode(&data);
+		assert_eq!((width, height, color_type), (320, 20, 3));
+		assert_eq!(decoded, pixels);
+		let colors = colors.unwrap();
+		assert_eq!(colors.len(), 768);
+		assert_eq!(&colors[3 * 7..3 * 8], &[7, 0, 248]);
+	}
+}
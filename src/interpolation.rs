@@ -0,0 +1,166 @@
+use std::mem;
+use resources::bsp;
+
+// Things move at most 30 units a tic, like MAXMOVE, so those that moved
+// further teleported or respawned, and are shown where they are now.
+const SNAP_DISTANCE : i64 = 64 << 16;
+
+/// `fraction` of the way from `from` to `to`, for fixed point values.
+pub fn lerp(from : i32, to : i32, fraction : f32) -> i32 {
+	from + ((to as i64 - from as i64) as f32 * fraction) as i32
+}
+
+// The parts of a level that move from tic to tic: the floor and ceiling
+// heights of the sectors, and the positions of the things.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+struct TicState {
+	heights : Vec<(i32, i32)>,
+	positions : Vec<(i32, i32)>
+}
+
+impl TicState {
+	fn record(&mut self, level : &bsp::Map) {
+		self.heights.clear();
+		self.heights.extend(level.sectors.iter().map(|sector| (sector.floor_height, sector.ceiling_height)));
+		self.positions.clear();
+		self.positions.extend(level.things.iter().map(|thing| (thing.x, thing.y)));
+	}
+}
+
+/// Draws a level between its last two tics, like the camera. The game
+/// changes the level once a tic, and the frames are drawn from a copy where
+/// the sectors and things are part of the way between where they were
+/// before and after the last tic. Things that jumped are not moved between.
+pub struct LevelInterpolation {
+	previous : TicState,
+	current : TicState,
+
+	// The copy of the level the frames are drawn from
+	frame : bsp::Map
+}
+
+impl LevelInterpolation {
+	pub fn new(level : &bsp::Map) -> LevelInterpolation {
+		let mut current = TicState::default();
+		current.record(level);
+		LevelInterpolation {
+			previous: current.clone(),
+			current: current,
+			frame: level.clone()
+		}
+	}
+
+	/// Records the level after a tic. The BSP tree has to be the same as in
+	/// the last tic, see `reset`.
+	pub fn tic(&mut self, level : &bsp::Map) {
+		mem::swap(&mut self.previous, &mut self.current);
+		self.current.record(level);
+
+		// Without the same sectors and things, there is nothing to move
+		// between.
+		if self.previous.heights.len() != self.current.heights.len() || self.previous.positions.len() != self.current.positions.len() {
+			self.previous.clone_from(&self.current);
+		}
+
+		// The textures, lights and the rest are shown as of the last tic.
+		self.frame.things.clone_from(&level.things);
+		self.frame.lines.clone_from(&level.lines);
+		self.frame.sides.clone_from(&level.sides);
+		self.frame.sectors.clone_from(&level.sectors);
+	}
+
+	/// Starts over from another level, or the same one reloaded from its
+	/// package, without moving from the old one.
+	pub fn reset(&mut self, level : &bsp::Map) {
+		*self = LevelInterpolation::new(level);
+	}
+
+	/// The level `fraction` of the way through the last tic.
+	pub fn interpolated(&mut self, fraction : f32) -> &bsp::Map {
+		let heights = self.previous.heights.iter().zip(self.current.heights.iter());
+		for (sector, (&(floor0, ceiling0), &(floor1, ceiling1))) in self.frame.sectors.iter_mut().zip(heights) {
+			sector.floor_height = lerp(floor0, floor1, fraction);
+			sector.ceiling_height = lerp(ceiling0, ceiling1, fraction);
+		}
+
+		let positions = self.previous.positions.iter().zip(self.current.positions.iter());
+		for (thing, (&(x0, y0), &(x1, y1))) in self.frame.things.iter_mut().zip(positions) {
+			if (x1 as i64 - x0 as i64).abs() > SNAP_DISTANCE || (y1 as i64 - y0 as i64).abs() > SNAP_DISTANCE {
+				thing.x = x1;
+				thing.y = y1;
+			} else {
+				thing.x = lerp(x0, x1, fraction);
+				thing.y = lerp(y0, y1, fraction);
+			}
+		}
+
+		&self.frame
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn level(floor : i32, ceiling : i32, thing_x : i32) -> bsp::Map {
+		bsp::Map {
+			name: "E1M1".to_string(),
+			things: vec![bsp::Thing { x: thing_x, y: 0, angle: 0, thing_type: 2035, flags: 7 }],
+			lines: Vec::<bsp::LineDef>::new(),
+			sides: Vec::<bsp::SideDef>::new(),
+			sectors: vec![bsp::Sector {
+				floor_height: floor,
+				ceiling_height: ceiling,
+				floor_texture: "FLOOR".to_string(),
+				ceiling_texture: "CEIL".to_string(),
+				light_level: 160,
+				sector_type: 0,
+				tag: 0
+			}],
+			subsectors: Vec::<bsp::Subsector>::new(),
+			segs: Vec::<bsp::LineSegment>::new(),
+			nodes: Vec::<bsp::Node>::new(),
+			vertices: Vec::<bsp::Vertex>::new()
+		}
+	}
+
+	#[test]
+	fn frames_are_between_the_last_two_tics() {
+		let mut interpolation = LevelInterpolation::new(&level(0, 128 << 16, 0));
+		interpolation.tic(&level(16 << 16, 96 << 16, 64 << 16));
+
+		let frame = interpolation.interpolated(0.25);
+		assert_eq!(frame.sectors[0].floor_height, 4 << 16);
+		assert_eq!(frame.sectors[0].ceiling_height, 120 << 16);
+		assert_eq!(frame.things[0].x, 16 << 16);
+
+		let frame = interpolation.interpolated(1.0);
+		assert_eq!(frame.sectors[0].floor_height, 16 << 16);
+		assert_eq!(frame.things[0].x, 64 << 16);
+	}
+
+	#[test]
+	fn a_reset_level_does_not_move() {
+		let mut interpolation = LevelInterpolation::new(&level(0, 128 << 16, 0));
+		let reloaded = level(32 << 16, 128 << 16, 64 << 16);
+		interpolation.reset(&reloaded);
+		assert_eq!(*interpolation.interpolated(0.5), reloaded);
+
+		interpolation.tic(&reloaded);
+		assert_eq!(*interpolation.interpolated(0.5), reloaded);
+	}
+
+	#[test]
+	fn teleported_things_do_not_slide() {
+		let mut interpolation = LevelInterpolation::new(&level(0, 128 << 16, 0));
+		interpolation.tic(&level(0, 128 << 16, 1024 << 16));
+		assert_eq!(interpolation.interpolated(0.25).things[0].x, 1024 << 16);
+
+		interpolation.tic(&level(0, 128 << 16, -1024 << 16));
+		assert_eq!(interpolation.interpolated(0.5).things[0].x, -1024 << 16);
+
+		// A fast move is still smooth.
+		interpolation.tic(&level(0, 128 << 16, -992 << 16));
+		assert_eq!(interpolation.interpolated(0.5).things[0].x, -1008 << 16);
+	}
+}
@@ -0,0 +1,17 @@
+extern crate sdl2;
+extern crate byteorder;
+pub mod system;
+pub mod framebuffer;
+pub mod scaling;
+pub mod screenshot;
+pub mod timing;
+pub mod interpolation;
+pub mod resources;
+pub mod audio;
+pub mod soundsystem;
+pub mod hud;
+pub mod image;
+pub mod mapexport;
+pub mod maplint;
+pub mod render;
+pub mod game;
@@ -0,0 +1,158 @@
+pub use self::svg::map_to_svg;
+pub use self::raster::MapImage;
+pub use self::raster::render_map;
+
+mod svg;
+mod raster;
+
+use resources::bsp;
+
+/// How a line is drawn on the map.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LineClass {
+	OneSided,
+	TwoSided,
+	Special,
+	Secret
+}
+
+impl LineClass {
+	/// Classifies a line like the automap does: secrets first, then lines
+	/// with a special, then the rest by the number of sides.
+	pub fn of(line : &bsp::LineDef) -> LineClass {
+		if line.flags & bsp::ML_SECRET != 0 {
+			LineClass::Secret
+		} else if line.special_type != 0 {
+			LineClass::Special
+		} else if line.flags & bsp::ML_TWOSIDED != 0 || line.side[1] != bsp::NO_SIDE {
+			LineClass::TwoSided
+		} else {
+			LineClass::OneSided
+		}
+	}
+}
+
+/// Order the line classes are drawn in, so the important ones end up on top.
+const DRAW_ORDER : [LineClass; 4] = [LineClass::TwoSided, LineClass::OneSided, LineClass::Special, LineClass::Secret];
+
+// Player start thing types: players 1-4 and deathmatch.
+const PLAYER_STARTS : [u32; 5] = [1, 2, 3, 4, 11];
+
+pub type Rgb = (u8, u8, u8);
+
+/// Colors and sizes used to draw a map.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MapStyle {
+	pub background : Rgb,
+	pub one_sided : Rgb,
+	pub two_sided : Rgb,
+	pub special : Rgb,
+	pub secret : Rgb,
+	pub thing : Rgb,
+	pub player_start : Rgb,
+
+	/// Empty space around the map, in pixels.
+	pub margin : i32,
+	/// Radius of the thing markers, in pixels.
+	pub thing_radius : i32,
+	pub draw_things : bool
+}
+
+impl Default for MapStyle {
+	/// Colors close to the vanilla automap.
+	fn default() -> MapStyle {
+		MapStyle {
+			background: (0, 0, 0),
+			one_sided: (252, 0, 0),
+			two_sided: (188, 120, 72),
+			special: (252, 252, 0),
+			secret: (252, 0, 252),
+			thing: (0, 160, 0),
+			player_start: (255, 255, 255),
+			margin: 16,
+			thing_radius: 2,
+			draw_things: true
+		}
+	}
+}
+
+impl MapStyle {
+	pub fn line_color(&self, class : LineClass) -> Rgb {
+		match class {
+			LineClass::OneSided => self.one_sided,
+			LineClass::TwoSided => self.two_sided,
+			LineClass::Special => self.special,
+			LineClass::Secret => self.secret
+		}
+	}
+}
+
+/// Maps world coordinates to image pixels. The map is scaled to fit the
+/// largest side into `size` pixels, with y pointing down.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MapView {
+	pub width : i32,
+	pub height : i32,
+	min_x : f64,
+	max_y : f64,
+	scale : f64,
+	margin : f64
+}
+
+impl MapView {
+	pub fn new(map : &bsp::Map, size : i32, margin : i32) -> MapView {
+		let mut min = (i32::max_value(), i32::max_value());
+		let mut max = (i32::min_value(), i32::min_value());
+		for v in map.vertices.iter() {
+			min = (min.0.min(v.x), min.1.min(v.y));
+			max = (max.0.max(v.x), max.1.max(v.y));
+		}
+		if map.vertices.is_empty() {
+			min = (0, 0);
+			max = (0, 0);
+		}
+
+		let map_width = (max.0 as f64 - min.0 as f64) / 65536.0;
+		let map_height = (max.1 as f64 - min.1 as f64) / 65536.0;
+		let inner = (size - margin * 2).max(1) as f64;
+		let largest = map_width.max(map_height).max(1.0);
+		let scale = inner / largest;
+
+		MapView {
+			width: (map_width * scale).ceil() as i32 + margin * 2 + 1,
+			height: (map_height * scale).ceil() as i32 + margin * 2 + 1,
+			min_x: min.0 as f64 / 65536.0,
+			max_y: max.1 as f64 / 65536.0,
+			scale: scale,
+			margin: margin as f64
+		}
+	}
+
+	/// Converts a 16.16 fixed point world position to pixel coordinates.
+	pub fn to_image(&self, x : i32, y : i32) -> (f64, f64) {
+		(
+			(x as f64 / 65536.0 - self.min_x) * self.scale + self.margin,
+			(self.max_y - y as f64 / 65536.0) * self.scale + self.margin
+		)
+	}
+}
+
+/// Lines of the map in drawing order, with their class. Lines flagged to be
+/// hidden on the automap are still drawn.
+fn sorted_lines(map : &bsp::Map) -> Vec<(&bsp::LineDef, LineClass)> {
+	let mut lines = Vec::<(&bsp::LineDef, LineClass)>::with_capacity(map.lines.len());
+	for class in DRAW_ORDER.iter() {
+		lines.extend(map.lines.iter().filter(|line| LineClass::of(line) == *class).map(|line| (line, *class)));
+	}
+	lines
+}
+
+fn is_player_start(thing : &bsp::Thing) -> bool {
+	PLAYER_STARTS.contains(&thing.thing_type)
+}
+
+/// Direction of a thing in image space. The image y axis points down.
+fn thing_direction(thing : &bsp::Thing) -> (f64, f64) {
+	let angle = thing.angle as f64 / 65536.0 * 2.0 * ::std::f64::consts::PI;
+	(angle.cos(), -angle.sin())
+}
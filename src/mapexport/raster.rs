@@ -0,0 +1,120 @@
+use std::io;
+use std::io::Write;
+use resources::bsp;
+use image;
+use super::MapStyle;
+use super::MapView;
+use super::Rgb;
+
+/// An RGB image of a map.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MapImage {
+	pub width : i32,
+	pub height : i32,
+	pub pixels : Vec<u8>
+}
+
+impl MapImage {
+	fn new(width : i32, height : i32, background : Rgb) -> MapImage {
+		let mut pixels = Vec::<u8>::with_capacity((width * height * 3) as usize);
+		for _ in 0..width * height {
+			pixels.extend_from_slice(&[background.0, background.1, background.2]);
+		}
+
+		MapImage {
+			width: width,
+			height: height,
+			pixels: pixels
+		}
+	}
+
+	pub fn get_pixel(&self, x : i32, y : i32) -> Rgb {
+		let i = ((y * self.width + x) * 3) as usize;
+		(self.pixels[i], self.pixels[i + 1], self.pixels[i + 2])
+	}
+
+	pub fn put_pixel(&mut self, x : i32, y : i32, color : Rgb) {
+		if x < 0 || y < 0 || x >= self.width || y >= self.height {
+			return;
+		}
+		let i = ((y * self.width + x) * 3) as usize;
+		self.pixels[i] = color.0;
+		self.pixels[i + 1] = color.1;
+		self.pixels[i + 2] = color.2;
+	}
+
+	/// Draws a line with Bresenham's algorithm.
+	pub fn draw_line(&mut self, x0 : i32, y0 : i32, x1 : i32, y1 : i32, color : Rgb) {
+		let dx = (x1 - x0).abs();
+		let dy = -(y1 - y0).abs();
+		let sx = if x0 < x1 { 1 } else { -1 };
+		let sy = if y0 < y1 { 1 } else { -1 };
+		let mut err = dx + dy;
+		let (mut x, mut y) = (x0, y0);
+
+		loop {
+			self.put_pixel(x, y, color);
+			if x == x1 && y == y1 {
+				break;
+			}
+			let e2 = err * 2;
+			if e2 >= dy {
+				err += dy;
+				x += sx;
+			}
+			if e2 <= dx {
+				err += dx;
+				y += sy;
+			}
+		}
+	}
+
+	pub fn fill_circle(&mut self, cx : i32, cy : i32, radius : i32, color : Rgb) {
+		for y in -radius..radius + 1 {
+			for x in -radius..radius + 1 {
+				if x * x + y * y <= radius * radius {
+					self.put_pixel(cx + x, cy + y, color);
+				}
+			}
+		}
+	}
+
+	pub fn write_png<W : Write>(&self, writer : &mut W) -> io::Result<()> {
+		image::write_png_rgb(writer, self.width as u32, self.height as u32, &self.pixels)
+	}
+}
+
+/// Draws the map into an image that fits in `size` pixels.
+pub fn render_map(map : &bsp::Map, style : &MapStyle, size : i32) -> MapImage {
+	let view = MapView::new(map, size, style.margin);
+	let mut img = MapImage::new(view.width, view.height, style.background);
+
+	for (line, class) in super::sorted_lines(map) {
+		let (v0, v1) = match (map.vertices.get(line.v[0] as usize), map.vertices.get(line.v[1] as usize)) {
+			(Some(v0), Some(v1)) => (v0, v1),
+			_ => continue
+		};
+		let p0 = view.to_image(v0.x, v0.y);
+		let p1 = view.to_image(v1.x, v1.y);
+		img.draw_line(p0.0.round() as i32, p0.1.round() as i32, p1.0.round() as i32, p1.1.round() as i32,
+			style.line_color(class));
+	}
+
+	if style.draw_things {
+		let radius = style.thing_radius;
+		for thing in map.things.iter() {
+			let (x, y) = view.to_image(thing.x, thing.y);
+			let (x, y) = (x.round() as i32, y.round() as i32);
+			if super::is_player_start(thing) {
+				let (dx, dy) = super::thing_direction(thing);
+				let length = (radius * 3) as f64;
+				img.fill_circle(x, y, radius, style.player_start);
+				img.draw_line(x, y, x + (dx * length).round() as i32, y + (dy * length).round() as i32, style.player_start);
+			} else {
+				img.fill_circle(x, y, radius, style.thing);
+			}
+		}
+	}
+
+	img
+}
@@ -0,0 +1,121 @@
+use std::fmt::Write;
+use resources::bsp;
+use super::MapStyle;
+use super::MapView;
+use super::Rgb;
+
+fn color(rgb : Rgb) -> String {
+	format!("#{:02x}{:02x}{:02x}", rgb.0, rgb.1, rgb.2)
+}
+
+// Escapes the characters XML reads as markup.
+fn escape(text : &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			_ => escaped.push(c)
+		}
+	}
+	escaped
+}
+
+/// Draws the map as an SVG document that fits in `size` pixels.
+pub fn map_to_svg(map : &bsp::Map, style : &MapStyle, size : i32) -> String {
+	let view = MapView::new(map, size, style.margin);
+	let mut out = String::new();
+
+	// Writing to a String can't fail.
+	let _ = writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+	let _ = writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">",
+		view.width, view.height);
+	let _ = writeln!(out, "<title>{}</title>", escape(&map.name));
+	let _ = writeln!(out, "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>", color(style.background));
+
+	let _ = writeln!(out, "<g stroke-width=\"1\" stroke-linecap=\"round\">");
+	for (line, class) in super::sorted_lines(map) {
+		let (v0, v1) = match (map.vertices.get(line.v[0] as usize), map.vertices.get(line.v[1] as usize)) {
+			(Some(v0), Some(v1)) => (v0, v1),
+			_ => continue
+		};
+		let p0 = view.to_image(v0.x, v0.y);
+		let p1 = view.to_image(v1.x, v1.y);
+		let _ = writeln!(out, "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\"/>",
+			p0.0, p0.1, p1.0, p1.1, color(style.line_color(class)));
+	}
+	let _ = writeln!(out, "</g>");
+
+	if style.draw_things {
+		let radius = style.thing_radius as f64;
+		let _ = writeln!(out, "<g>");
+		for thing in map.things.iter() {
+			let (x, y) = view.to_image(thing.x, thing.y);
+			if super::is_player_start(thing) {
+				// An arrow pointing where the player faces
+				let (dx, dy) = super::thing_direction(thing);
+				let length = radius * 3.0;
+				let tip = (x + dx * length, y + dy * length);
+				let left = (x - dy * radius, y + dx * radius);
+				let right = (x + dy * radius, y - dx * radius);
+				let _ = writeln!(out, "<polygon points=\"{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}\" fill=\"{}\"><title>player start {}</title></polygon>",
+					tip.0, tip.1, left.0, left.1, right.0, right.1, color(style.player_start), thing.thing_type);
+			} else {
+				let _ = writeln!(out, "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{:.1}\" fill=\"{}\"><title>thing {}</title></circle>",
+					x, y, radius, color(style.thing), thing.thing_type);
+			}
+		}
+		let _ = writeln!(out, "</g>");
+	}
+
+	let _ = writeln!(out, "</svg>");
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use resources::bsp;
+	use super::*;
+
+	fn test_map(name : &str) -> bsp::Map {
+		bsp::Map {
+			name: name.to_string(),
+			things: vec![
+				bsp::Thing { x: 32 << 16, y: 32 << 16, angle: 90, thing_type: 1, flags: 7 },
+				bsp::Thing { x: 64 << 16, y: 32 << 16, angle: 0, thing_type: 2001, flags: 7 }
+			],
+			lines: vec![
+				bsp::LineDef { v: [0, 1], flags: 0, special_type: 0, sector_tag: 0, side: [0, bsp::NO_SIDE] },
+				bsp::LineDef { v: [1, 2], flags: 0, special_type: 1, sector_tag: 0, side: [0, bsp::NO_SIDE] }
+			],
+			sides: Vec::<bsp::SideDef>::new(),
+			sectors: Vec::<bsp::Sector>::new(),
+			subsectors: Vec::<bsp::Subsector>::new(),
+			segs: Vec::<bsp::LineSegment>::new(),
+			nodes: Vec::<bsp::Node>::new(),
+			vertices: vec![
+				bsp::Vertex { x: 0, y: 0 },
+				bsp::Vertex { x: 128 << 16, y: 0 },
+				bsp::Vertex { x: 128 << 16, y: 64 << 16 }
+			]
+		}
+	}
+
+	#[test]
+	fn svg_has_the_lines_and_things() {
+		let svg = map_to_svg(&test_map("E1M1"), &MapStyle::default(), 256);
+		assert!(svg.starts_with("<?xml"));
+		assert!(svg.trim_end().ends_with("</svg>"));
+		assert_eq!(svg.matches("<line ").count(), 2);
+		assert_eq!(svg.matches("stroke=\"#fcfc00\"").count(), 1);
+		assert_eq!(svg.matches("<polygon ").count(), 1);
+		assert_eq!(svg.matches("<circle ").count(), 1);
+	}
+
+	#[test]
+	fn svg_title_is_escaped() {
+		let svg = map_to_svg(&test_map("<E1M1 & E1M2>"), &MapStyle::default(), 256);
+		assert!(svg.contains("<title>&lt;E1M1 &amp; E1M2&gt;</title>"));
+	}
+}
@@ -0,0 +1,596 @@
+pub use self::textures::TextureNames;
+
+mod textures;
+
+use std::collections::HashMap;
+use std::fmt;
+use resources::bsp;
+
+// Tagged line specials that open doors or move lifts.
+const TAGGED_DOORS : [u32; 33] = [
+	2, 3, 4, 16, 29, 42, 46, 50, 61, 63, 75, 76, 86, 90, 99, 103, 105, 106,
+	107, 108, 109, 110, 111, 112, 113, 114, 115, 116, 133, 134, 135, 136, 137
+];
+const TAGGED_LIFTS : [u32; 11] = [10, 21, 53, 62, 87, 88, 89, 120, 121, 122, 123];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+	/// Probably intended, but worth a look.
+	Warning,
+	/// Breaks the map in the game or in the renderer.
+	Error
+}
+
+impl fmt::Display for Severity {
+	fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match *self {
+			Severity::Warning => "warning",
+			Severity::Error => "error"
+		})
+	}
+}
+
+/// The kinds of problems the checker looks for.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Check {
+	InvalidReference,
+	UnclosedSector,
+	MissingBackSide,
+	UnexpectedBackSide,
+	MissingTexture,
+	UnknownTexture,
+	ZeroLengthLine,
+	OverlappingVertices,
+	UnusedSector,
+	UnusedSide,
+	UnmatchedTag
+}
+
+impl Check {
+	/// Short name of the check, for filtering and machine readable output.
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Check::InvalidReference => "invalid-reference",
+			Check::UnclosedSector => "unclosed-sector",
+			Check::MissingBackSide => "missing-back-side",
+			Check::UnexpectedBackSide => "unexpected-back-side",
+			Check::MissingTexture => "missing-texture",
+			Check::UnknownTexture => "unknown-texture",
+			Check::ZeroLengthLine => "zero-length-line",
+			Check::OverlappingVertices => "overlapping-vertices",
+			Check::UnusedSector => "unused-sector",
+			Check::UnusedSide => "unused-side",
+			Check::UnmatchedTag => "unmatched-tag"
+		}
+	}
+}
+
+/// The map element a diagnostic is about.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MapObject {
+	Line(usize),
+	Side(usize),
+	Sector(usize),
+	Vertex(usize)
+}
+
+impl fmt::Display for MapObject {
+	fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			MapObject::Line(index) => write!(f, "line {}", index),
+			MapObject::Side(index) => write!(f, "side {}", index),
+			MapObject::Sector(index) => write!(f, "sector {}", index),
+			MapObject::Vertex(index) => write!(f, "vertex {}", index)
+		}
+	}
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+	pub severity : Severity,
+	pub check : Check,
+	pub object : MapObject,
+	pub message : String
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}: {}: {} [{}]", self.severity, self.object, self.message, self.check.name())
+	}
+}
+
+/// Checks a map for problems. Texture names are only checked against the
+/// known textures when `textures` is given, and flat names only when it has
+/// any flats, otherwise only missing textures are reported. Unknown names are
+/// errors when the names are complete and warnings otherwise.
+///
+/// The diagnostics are sorted with the errors first.
+pub fn lint_map(map : &bsp::Map, textures : Option<&TextureNames>) -> Vec<Diagnostic> {
+	let mut linter = Linter {
+		map: map,
+		diagnostics: Vec::<Diagnostic>::new()
+	};
+
+	linter.check_references();
+	linter.check_sides();
+	linter.check_textures(textures);
+	linter.check_geometry();
+	linter.check_sectors_closed();
+	linter.check_unused();
+	linter.check_tags();
+
+	let mut diagnostics = linter.diagnostics;
+	diagnostics.sort_by(|a, b| b.severity.cmp(&a.severity));
+	diagnostics
+}
+
+struct Linter<'a> {
+	map : &'a bsp::Map,
+	diagnostics : Vec<Diagnostic>
+}
+
+impl<'a> Linter<'a> {
+	fn report(&mut self, severity : Severity, check : Check, object : MapObject, message : String) {
+		self.diagnostics.push(Diagnostic {
+			severity: severity,
+			check: check,
+			object: object,
+			message: message
+		});
+	}
+
+	/// The side of a line, if it has one and it exists.
+	fn side(&self, line : &bsp::LineDef, side : usize) -> Option<&'a bsp::SideDef> {
+		let map = self.map;
+		if line.side[side] == bsp::NO_SIDE {
+			None
+		} else {
+			map.sides.get(line.side[side] as usize)
+		}
+	}
+
+	fn sector(&self, side : &bsp::SideDef) -> Option<&'a bsp::Sector> {
+		let map = self.map;
+		map.sectors.get(side.sector as usize)
+	}
+
+	fn check_references(&mut self) {
+		let map = self.map;
+		for (i, line) in map.lines.iter().enumerate() {
+			for &v in line.v.iter() {
+				if v as usize >= map.vertices.len() {
+					self.report(Severity::Error, Check::InvalidReference, MapObject::Line(i),
+						format!("vertex {} does not exist", v));
+				}
+			}
+			for &side in line.side.iter() {
+				if side != bsp::NO_SIDE && side as usize >= map.sides.len() {
+					self.report(Severity::Error, Check::InvalidReference, MapObject::Line(i),
+						format!("side {} does not exist", side));
+				}
+			}
+			if line.side[0] == bsp::NO_SIDE {
+				self.report(Severity::Error, Check::InvalidReference, MapObject::Line(i),
+					"line has no front side".to_string());
+			}
+		}
+
+		for (i, side) in map.sides.iter().enumerate() {
+			if side.sector as usize >= map.sectors.len() {
+				self.report(Severity::Error, Check::InvalidReference, MapObject::Side(i),
+					format!("sector {} does not exist", side.sector));
+			}
+		}
+	}
+
+	fn check_sides(&mut self) {
+		let map = self.map;
+		for (i, line) in map.lines.iter().enumerate() {
+			let two_sided = line.flags & bsp::ML_TWOSIDED != 0;
+			if two_sided && line.side[1] == bsp::NO_SIDE {
+				self.report(Severity::Error, Check::MissingBackSide, MapObject::Line(i),
+					"two-sided line has no back side".to_string());
+			} else if !two_sided && line.side[1] != bsp::NO_SIDE {
+				self.report(Severity::Warning, Check::UnexpectedBackSide, MapObject::Line(i),
+					"one-sided line has a back side".to_string());
+			}
+		}
+	}
+
+	fn check_textures(&mut self, textures : Option<&TextureNames>) {
+		let map = self.map;
+
+		for (i, line) in map.lines.iter().enumerate() {
+			let front = match self.side(line, 0) {
+				Some(value) => value,
+				None => continue
+			};
+			let back = self.side(line, 1);
+
+			match back {
+				None => {
+					if is_missing(&front.middle_texture) {
+						self.report(Severity::Error, Check::MissingTexture, MapObject::Side(line.side[0] as usize),
+							format!("one-sided line {} has no middle texture", i));
+					}
+				}
+				Some(back) => {
+					let (front_sector, back_sector) = match (self.sector(front), self.sector(back)) {
+						(Some(front_sector), Some(back_sector)) => (front_sector, back_sector),
+						_ => continue
+					};
+
+					// Steps show the upper and lower textures of the side
+					// facing them. Between two sky ceilings the upper texture
+					// is not drawn.
+					let sky = is_sky(front_sector) && is_sky(back_sector);
+					let sides = [(line.side[0], front, front_sector, back_sector), (line.side[1], back, back_sector, front_sector)];
+					for &(index, side, sector, other) in sides.iter() {
+						if !sky && sector.ceiling_height > other.ceiling_height && is_missing(&side.upper_texture) {
+							self.report(Severity::Error, Check::MissingTexture, MapObject::Side(index as usize),
+								format!("line {} has no upper texture for its ceiling step", i));
+						}
+						if sector.floor_height < other.floor_height && is_missing(&side.lower_texture) {
+							self.report(Severity::Error, Check::MissingTexture, MapObject::Side(index as usize),
+								format!("line {} has no lower texture for its floor step", i));
+						}
+					}
+				}
+			}
+		}
+
+		let textures = match textures {
+			Some(value) => value,
+			None => return
+		};
+		let severity = if textures.is_complete() { Severity::Error } else { Severity::Warning };
+
+		// A set without textures or without flats comes from lumps that
+		// don't have them, like a PWAD with only new flats.
+		if textures.any_textures() {
+			for (i, side) in map.sides.iter().enumerate() {
+				for name in [&side.upper_texture, &side.lower_texture, &side.middle_texture].iter() {
+					if !is_missing(name) && !textures.has_texture(name) {
+						self.report(severity, Check::UnknownTexture, MapObject::Side(i),
+							format!("unknown texture {}", name));
+					}
+				}
+			}
+		}
+
+		if textures.any_flats() {
+			for (i, sector) in map.sectors.iter().enumerate() {
+				for name in [&sector.floor_texture, &sector.ceiling_texture].iter() {
+					if !textures.has_flat(name) {
+						self.report(severity, Check::UnknownTexture, MapObject::Sector(i),
+							format!("unknown flat {}", name));
+					}
+				}
+			}
+		}
+	}
+
+	fn check_geometry(&mut self) {
+		let map = self.map;
+
+		for (i, line) in map.lines.iter().enumerate() {
+			if let (Some(v0), Some(v1)) = (map.vertices.get(line.v[0] as usize), map.vertices.get(line.v[1] as usize)) {
+				if v0 == v1 {
+					self.report(Severity::Error, Check::ZeroLengthLine, MapObject::Line(i),
+						"line has zero length".to_string());
+				}
+			}
+		}
+
+		// Only the vertices used by lines, the node builder adds its own.
+		let mut used = vec![false; map.vertices.len()];
+		for line in map.lines.iter() {
+			for &v in line.v.iter() {
+				if let Some(value) = used.get_mut(v as usize) {
+					*value = true;
+				}
+			}
+		}
+
+		let mut positions = HashMap::<(i32, i32), usize>::new();
+		for (i, vertex) in map.vertices.iter().enumerate() {
+			if !used[i] {
+				continue;
+			}
+			match positions.get(&(vertex.x, vertex.y)) {
+				Some(&other) => {
+					self.report(Severity::Warning, Check::OverlappingVertices, MapObject::Vertex(i),
+						format!("vertex is at the same position as vertex {} ({}, {})", other, vertex.x >> 16, vertex.y >> 16));
+					continue;
+				}
+				None => ()
+			}
+			positions.insert((vertex.x, vertex.y), i);
+		}
+	}
+
+	/// A sector is closed when its lines form loops: walking along the lines
+	/// with the sector on the right, every vertex is left as often as it is
+	/// entered.
+	fn check_sectors_closed(&mut self) {
+		let map = self.map;
+		let mut balance = HashMap::<(usize, (i32, i32)), (i32, usize)>::new();
+
+		for line in map.lines.iter() {
+			let (v0, v1) = match (map.vertices.get(line.v[0] as usize), map.vertices.get(line.v[1] as usize)) {
+				(Some(v0), Some(v1)) => (v0, v1),
+				_ => continue
+			};
+
+			for side in 0..2 {
+				let sector = match self.side(line, side) {
+					Some(value) if (value.sector as usize) < map.sectors.len() => value.sector as usize,
+					_ => continue
+				};
+
+				// The back side runs the other way.
+				let (from, to) = if side == 0 { (line.v[0], line.v[1]) } else { (line.v[1], line.v[0]) };
+				let (from_pos, to_pos) = if side == 0 { ((v0.x, v0.y), (v1.x, v1.y)) } else { ((v1.x, v1.y), (v0.x, v0.y)) };
+				balance.entry((sector, from_pos)).or_insert((0, from as usize)).0 += 1;
+				balance.entry((sector, to_pos)).or_insert((0, to as usize)).0 -= 1;
+			}
+		}
+
+		let mut open = Vec::<(usize, usize)>::new();
+		for (&(sector, _), &(count, vertex)) in balance.iter() {
+			if count != 0 {
+				open.push((sector, vertex));
+			}
+		}
+		open.sort();
+
+		// One diagnostic per sector, pointing at the first open vertex.
+		let mut last_sector = None;
+		for (sector, vertex) in open {
+			if last_sector == Some(sector) {
+				continue;
+			}
+			last_sector = Some(sector);
+			self.report(Severity::Error, Check::UnclosedSector, MapObject::Sector(sector),
+				format!("sector is not closed at vertex {}", vertex));
+		}
+	}
+
+	fn check_unused(&mut self) {
+		let map = self.map;
+
+		let mut used_sides = vec![false; map.sides.len()];
+		for line in map.lines.iter() {
+			for &side in line.side.iter() {
+				if let Some(value) = used_sides.get_mut(side as usize) {
+					*value = true;
+				}
+			}
+		}
+
+		let mut used_sectors = vec![false; map.sectors.len()];
+		for (i, side) in map.sides.iter().enumerate() {
+			if !used_sides[i] {
+				self.report(Severity::Warning, Check::UnusedSide, MapObject::Side(i),
+					"side is not used by any line".to_string());
+				continue;
+			}
+			if let Some(value) = used_sectors.get_mut(side.sector as usize) {
+				*value = true;
+			}
+		}
+
+		for (i, used) in used_sectors.iter().enumerate() {
+			if !used {
+				self.report(Severity::Warning, Check::UnusedSector, MapObject::Sector(i),
+					"sector has no sides".to_string());
+			}
+		}
+	}
+
+	fn check_tags(&mut self) {
+		let map = self.map;
+
+		for (i, line) in map.lines.iter().enumerate() {
+			let kind = if TAGGED_DOORS.contains(&line.special_type) {
+				"door"
+			} else if TAGGED_LIFTS.contains(&line.special_type) {
+				"lift"
+			} else {
+				continue;
+			};
+
+			if line.sector_tag == 0 {
+				// Vanilla applies these to every untagged sector.
+				self.report(Severity::Warning, Check::UnmatchedTag, MapObject::Line(i),
+					format!("{} special {} has tag 0", kind, line.special_type));
+			} else if !map.sectors.iter().any(|sector| sector.tag == line.sector_tag) {
+				self.report(Severity::Error, Check::UnmatchedTag, MapObject::Line(i),
+					format!("{} special {} has tag {}, which no sector uses", kind, line.special_type, line.sector_tag));
+			}
+		}
+	}
+}
+
+fn is_missing(name : &str) -> bool {
+	name.is_empty() || name == bsp::NO_TEXTURE
+}
+
+fn is_sky(sector : &bsp::Sector) -> bool {
+	sector.ceiling_texture.eq_ignore_ascii_case(bsp::SKY_FLAT)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::fs::File;
+	use resources::WadPackage;
+	use resources::testwad::*;
+	use super::*;
+
+	fn vertex(x : i32, y : i32) -> bsp::Vertex {
+		bsp::Vertex { x: x << 16, y: y << 16 }
+	}
+
+	fn line(v0 : u32, v1 : u32, front : u32, back : u32) -> bsp::LineDef {
+		let flags = if back == bsp::NO_SIDE { 0 } else { bsp::ML_TWOSIDED };
+		bsp::LineDef { v: [v0, v1], flags: flags, special_type: 0, sector_tag: 0, side: [front, back] }
+	}
+
+	fn side(sector : u32) -> bsp::SideDef {
+		bsp::SideDef {
+			x_offset: 0,
+			y_offset: 0,
+			upper_texture: bsp::NO_TEXTURE.to_string(),
+			lower_texture: bsp::NO_TEXTURE.to_string(),
+			middle_texture: "WALL".to_string(),
+			sector: sector
+		}
+	}
+
+	fn sector() -> bsp::Sector {
+		bsp::Sector {
+			floor_height: 0,
+			ceiling_height: 128,
+			floor_texture: "FLOOR".to_string(),
+			ceiling_texture: "CEIL".to_string(),
+			light_level: 160,
+			sector_type: 0,
+			tag: 0
+		}
+	}
+
+	/// A closed 64x64 square sector with one side per line.
+	fn square_map() -> bsp::Map {
+		bsp::Map {
+			name: "MAP01".to_string(),
+			things: Vec::<bsp::Thing>::new(),
+			lines: vec![line(0, 1, 0, bsp::NO_SIDE), line(1, 2, 1, bsp::NO_SIDE), line(2, 3, 2, bsp::NO_SIDE), line(3, 0, 3, bsp::NO_SIDE)],
+			sides: vec![side(0), side(0), side(0), side(0)],
+			sectors: vec![sector()],
+			subsectors: Vec::<bsp::Subsector>::new(),
+			segs: Vec::<bsp::LineSegment>::new(),
+			nodes: Vec::<bsp::Node>::new(),
+			vertices: vec![vertex(0, 0), vertex(0, 64), vertex(64, 64), vertex(64, 0)]
+		}
+	}
+
+	/// The severity and object of every diagnostic of one check.
+	fn found(map : &bsp::Map, textures : Option<&TextureNames>, check : Check) -> Vec<(Severity, MapObject)> {
+		lint_map(map, textures).into_iter()
+			.filter(|diagnostic| diagnostic.check == check)
+			.map(|diagnostic| (diagnostic.severity, diagnostic.object))
+			.collect()
+	}
+
+	fn texture_names(file_name : &str) -> TextureNames {
+		let path = temp_path(file_name);
+		write_wad(&path, &[
+			("PNAMES", pnames(&["WALLPAT"])),
+			("TEXTURE1", texture1(&["WALL"])),
+			("F_START", Vec::<u8>::new()),
+			("FLOOR", flat(1)),
+			("CEIL", flat(2)),
+			("F_END", Vec::<u8>::new())
+		]);
+		let package = WadPackage::new(File::open(&path).unwrap()).unwrap();
+		let _ = fs::remove_file(&path);
+
+		let mut textures = TextureNames::new();
+		textures.add_package(&package).unwrap();
+		textures
+	}
+
+	#[test]
+	fn square_is_clean() {
+		let textures = texture_names("lint-clean");
+		assert_eq!(lint_map(&square_map(), Some(&textures)), Vec::<Diagnostic>::new());
+	}
+
+	#[test]
+	fn unclosed_sectors_are_reported() {
+		let mut map = square_map();
+		map.lines.pop();
+		map.sides.pop();
+		assert_eq!(found(&map, None, Check::UnclosedSector), vec![(Severity::Error, MapObject::Sector(0))]);
+	}
+
+	#[test]
+	fn sides_must_match_the_two_sided_flag() {
+		let mut map = square_map();
+		map.lines[0].flags = bsp::ML_TWOSIDED;
+		map.sides.push(side(0));
+		map.lines[1].side[1] = 4;
+		assert_eq!(found(&map, None, Check::MissingBackSide), vec![(Severity::Error, MapObject::Line(0))]);
+		assert_eq!(found(&map, None, Check::UnexpectedBackSide), vec![(Severity::Warning, MapObject::Line(1))]);
+	}
+
+	#[test]
+	fn zero_length_lines_are_reported() {
+		let mut map = square_map();
+		map.sides.push(side(0));
+		map.lines.push(line(2, 2, 4, bsp::NO_SIDE));
+		assert_eq!(found(&map, None, Check::ZeroLengthLine), vec![(Severity::Error, MapObject::Line(4))]);
+	}
+
+	#[test]
+	fn overlapping_vertices_are_reported() {
+		let mut map = square_map();
+		map.vertices.push(vertex(0, 0));
+		map.lines[3].v[1] = 4;
+		// A vertex no line uses is left alone.
+		map.vertices.push(vertex(64, 64));
+		assert_eq!(found(&map, None, Check::OverlappingVertices), vec![(Severity::Warning, MapObject::Vertex(4))]);
+		assert_eq!(found(&map, None, Check::UnclosedSector), Vec::<(Severity, MapObject)>::new());
+	}
+
+	#[test]
+	fn unused_sectors_and_sides_are_reported() {
+		let mut map = square_map();
+		map.sectors.push(sector());
+		map.sides.push(side(1));
+		assert_eq!(found(&map, None, Check::UnusedSide), vec![(Severity::Warning, MapObject::Side(4))]);
+		assert_eq!(found(&map, None, Check::UnusedSector), vec![(Severity::Warning, MapObject::Sector(1))]);
+	}
+
+	#[test]
+	fn unknown_names_are_errors_only_for_complete_names() {
+		let mut map = square_map();
+		map.sides[2].middle_texture = "NOTHERE".to_string();
+		map.sectors[0].ceiling_texture = "NOFLAT".to_string();
+
+		let mut textures = texture_names("lint-unknown");
+		assert_eq!(found(&map, Some(&textures), Check::UnknownTexture),
+			vec![(Severity::Warning, MapObject::Side(2)), (Severity::Warning, MapObject::Sector(0))]);
+
+		textures.mark_complete();
+		assert_eq!(found(&map, Some(&textures), Check::UnknownTexture),
+			vec![(Severity::Error, MapObject::Side(2)), (Severity::Error, MapObject::Sector(0))]);
+	}
+
+	fn map_with_specials(specials : &[u32]) -> bsp::Map {
+		bsp::Map {
+			name: "MAP01".to_string(),
+			things: Vec::<bsp::Thing>::new(),
+			lines: specials.iter().map(|&special| {
+				bsp::LineDef { v: [0, 1], flags: 0, special_type: special, sector_tag: 0, side: [bsp::NO_SIDE, bsp::NO_SIDE] }
+			}).collect(),
+			sides: Vec::<bsp::SideDef>::new(),
+			sectors: Vec::<bsp::Sector>::new(),
+			subsectors: Vec::<bsp::Subsector>::new(),
+			segs: Vec::<bsp::LineSegment>::new(),
+			nodes: Vec::<bsp::Node>::new(),
+			vertices: vec![bsp::Vertex { x: 0, y: 0 }, bsp::Vertex { x: 64 << 16, y: 0 }]
+		}
+	}
+
+	#[test]
+	fn untagged_doors_are_reported() {
+		let map = map_with_specials(&[1, 46, 99, 62, 117]);
+		let lines : Vec<MapObject> = lint_map(&map, None).into_iter()
+			.filter(|diagnostic| diagnostic.check == Check::UnmatchedTag)
+			.map(|diagnostic| diagnostic.object)
+			.collect();
+		assert_eq!(lines, vec![MapObject::Line(1), MapObject::Line(2), MapObject::Line(3)]);
+	}
+}
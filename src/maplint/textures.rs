@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+use resources::Namespace;
+use resources::WadPackage;
+use resources::WadResult;
+use resources::read_texture_defs;
+
+/// The wall texture and flat names a set of lumps provides.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TextureNames {
+	textures : HashSet<String>,
+	flats : HashSet<String>,
+	complete : bool
+}
+
+impl TextureNames {
+	pub fn new() -> TextureNames {
+		TextureNames {
+			textures: HashSet::<String>::new(),
+			flats: HashSet::<String>::new(),
+			complete: false
+		}
+	}
+
+	/// Adds the textures defined in TEXTURE1 and TEXTURE2 of a package, and
+	/// the flats between its F_START and F_END markers.
+	pub fn add_package(&mut self, package : &WadPackage) -> WadResult<()> {
+		for (i, lump) in package.get_lumps().iter().enumerate() {
+			if lump.namespace == Namespace::Flats {
+				self.flats.insert(lump.name.to_ascii_uppercase());
+			} else if lump.name.eq_ignore_ascii_case("TEXTURE1") || lump.name.eq_ignore_ascii_case("TEXTURE2") {
+				let defs = try!(read_texture_defs(&try!(package.read_lump(i))));
+				self.textures.extend(defs.into_iter().map(|def| def.name));
+			}
+		}
+		Ok(())
+	}
+
+	/// Marks the names as covering everything the map can use, as when an
+	/// IWAD was added. Only then are unknown names errors, a PWAD on its own
+	/// uses the textures of an IWAD that isn't known here.
+	pub fn mark_complete(&mut self) {
+		self.complete = true;
+	}
+
+	pub fn is_complete(&self) -> bool {
+		self.complete
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.textures.is_empty() && self.flats.is_empty()
+	}
+
+	/// True if any wall textures are known, so names can be checked.
+	pub fn any_textures(&self) -> bool {
+		!self.textures.is_empty()
+	}
+
+	/// True if any flats are known, so names can be checked.
+	pub fn any_flats(&self) -> bool {
+		!self.flats.is_empty()
+	}
+
+	pub fn has_texture(&self, name : &str) -> bool {
+		self.textures.contains(&name.to_ascii_uppercase())
+	}
+
+	pub fn has_flat(&self, name : &str) -> bool {
+		self.flats.contains(&name.to_ascii_uppercase())
+	}
+}
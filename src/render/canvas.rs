@@ -0,0 +1,67 @@
+/// The pixels the renderer draws into, as palette indices like the
+/// framebuffer. It can borrow the framebuffer, or any buffer when there is
+/// no window.
+pub struct Canvas<'a> {
+	pixels : &'a mut [u8],
+	width : usize,
+	height : usize,
+	pitch : usize,
+
+	// The columns of the screen the pixels are, when the canvas only holds a
+	// strip of them
+	left : usize,
+	screen_width : usize
+}
+
+impl<'a> Canvas<'a> {
+	pub fn new(pixels : &'a mut [u8], width : usize, height : usize, pitch : usize) -> Canvas<'a> {
+		Canvas::strip(pixels, 0, width, width, height, pitch)
+	}
+
+	/// The `width` columns from column `left` of a screen `screen_width`
+	/// pixels wide. The view is projected to the whole screen, and only the
+	/// columns of the strip are drawn.
+	pub fn strip(pixels : &'a mut [u8], left : usize, width : usize, screen_width : usize, height : usize, pitch : usize) -> Canvas<'a> {
+		assert!(pitch >= width && pixels.len() >= pitch * height, "The pixels don't fit the canvas size");
+		assert!(left + width <= screen_width, "The strip doesn't fit the screen");
+		Canvas {
+			pixels: pixels,
+			width: width,
+			height: height,
+			pitch: pitch,
+			left: left,
+			screen_width: screen_width
+		}
+	}
+
+	/// The pixels of the canvas, with the width, height and pitch. Column x
+	/// of the screen is at x - `left()` in each row.
+	pub fn get(&mut self) -> (&mut [u8], usize, usize, usize) {
+		(self.pixels, self.width, self.height, self.pitch)
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	/// The first column of the screen in the canvas.
+	pub fn left(&self) -> usize {
+		self.left
+	}
+
+	pub fn screen_width(&self) -> usize {
+		self.screen_width
+	}
+
+	pub fn clear(&mut self, index : u8) {
+		for y in 0..self.height {
+			for pixel in self.pixels[y * self.pitch..y * self.pitch + self.width].iter_mut() {
+				*pixel = index;
+			}
+		}
+	}
+}
@@ -0,0 +1,147 @@
+/// A range of screen columns, both ends included.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ClipRange {
+	pub first : i32,
+	pub last : i32
+}
+
+/// The screen columns already covered by solid walls, like the solidsegs list
+/// of vanilla. The ranges are sorted, and ranges that touch are merged, so the
+/// whole screen is covered when one range is left.
+pub struct SolidSegs {
+	ranges : Vec<ClipRange>
+}
+
+impl SolidSegs {
+	pub fn new() -> SolidSegs {
+		SolidSegs {
+			ranges: Vec::<ClipRange>::new()
+		}
+	}
+
+	/// Starts a frame with nothing covered.
+	pub fn reset(&mut self, width : i32) {
+		// Everything left and right of the screen is covered, which keeps the
+		// merging free of special cases.
+		self.ranges.clear();
+		self.ranges.push(ClipRange { first: ::std::i32::MIN / 2, last: -1 });
+		self.ranges.push(ClipRange { first: width, last: ::std::i32::MAX / 2 });
+	}
+
+	/// Returns true when every column of the screen is covered.
+	pub fn is_full(&self) -> bool {
+		self.ranges.len() == 1
+	}
+
+	/// Returns true when all of the columns from `first` to `last` are covered.
+	pub fn is_covered(&self, first : i32, last : i32) -> bool {
+		self.ranges.iter().any(|range| range.first <= first && range.last >= last)
+	}
+
+	/// Collects the parts of the columns from `first` to `last` that are not
+	/// covered yet into `visible`.
+	pub fn clip(&self, first : i32, last : i32, visible : &mut Vec<ClipRange>) {
+		visible.clear();
+
+		let mut start = first;
+		for range in self.ranges.iter() {
+			if range.last < start {
+				continue;
+			}
+			if range.first > last {
+				break;
+			}
+			if range.first > start {
+				visible.push(ClipRange { first: start, last: range.first - 1 });
+			}
+			start = range.last + 1;
+			if start > last {
+				return;
+			}
+		}
+
+		if start <= last {
+			visible.push(ClipRange { first: start, last: last });
+		}
+	}
+
+	/// Marks the columns from `first` to `last` as covered.
+	pub fn add(&mut self, first : i32, last : i32) {
+		let mut merged = ClipRange { first: first, last: last };
+		self.ranges.retain(|range| {
+			if range.last + 1 >= merged.first && range.first <= merged.last + 1 {
+				merged.first = merged.first.min(range.first);
+				merged.last = merged.last.max(range.last);
+				false
+			} else {
+				true
+			}
+		});
+
+		let index = self.ranges.iter().position(|range| range.first > merged.last).unwrap_or(self.ranges.len());
+		self.ranges.insert(index, merged);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn visible(segs : &SolidSegs, first : i32, last : i32) -> Vec<(i32, i32)> {
+		let mut visible = Vec::<ClipRange>::new();
+		segs.clip(first, last, &mut visible);
+		visible.iter().map(|range| (range.first, range.last)).collect()
+	}
+
+	#[test]
+	fn adjacent_ranges_merge() {
+		let mut segs = SolidSegs::new();
+		segs.reset(320);
+		segs.add(10, 19);
+		segs.add(20, 29);
+		segs.add(40, 49);
+		assert!(segs.is_covered(10, 29));
+		assert!(!segs.is_covered(10, 49));
+
+		// Closing the gap leaves one range.
+		segs.add(30, 39);
+		assert!(segs.is_covered(10, 49));
+		assert_eq!(visible(&segs, 0, 60), vec![(0, 9), (50, 60)]);
+	}
+
+	#[test]
+	fn ranges_spanning_gaps_are_split() {
+		let mut segs = SolidSegs::new();
+		segs.reset(320);
+		segs.add(10, 19);
+		segs.add(30, 39);
+		segs.add(50, 59);
+		assert_eq!(visible(&segs, 15, 55), vec![(20, 29), (40, 49)]);
+		assert_eq!(visible(&segs, 0, 100), vec![(0, 9), (20, 29), (40, 49), (60, 100)]);
+		assert_eq!(visible(&segs, 30, 39), Vec::<(i32, i32)>::new());
+
+		// Off the screen is always covered.
+		assert_eq!(visible(&segs, -20, 5), vec![(0, 5)]);
+		assert_eq!(visible(&segs, 300, 400), vec![(300, 319)]);
+
+		segs.add(5, 55);
+		assert_eq!(visible(&segs, 0, 100), vec![(0, 4), (60, 100)]);
+	}
+
+	#[test]
+	fn covering_the_screen_fills_it() {
+		let mut segs = SolidSegs::new();
+		segs.reset(320);
+		assert!(!segs.is_full());
+		segs.add(100, 219);
+		segs.add(0, 99);
+		assert!(!segs.is_full());
+		segs.add(220, 319);
+		assert!(segs.is_full());
+		assert_eq!(visible(&segs, 0, 319), Vec::<(i32, i32)>::new());
+
+		segs.reset(320);
+		assert!(!segs.is_full());
+		assert_eq!(visible(&segs, 0, 319), vec![(0, 319)]);
+	}
+}
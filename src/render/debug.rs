@@ -0,0 +1,291 @@
+use super::Renderer;
+
+/// What the software renderer shows over the frame, to find rendering bugs
+/// in maps.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DebugView {
+	/// The frame as it is
+	None,
+	/// Each wall, and the floor and ceiling drawn up to it, in a color of
+	/// its subsector
+	Subsectors,
+	/// Like `Subsectors`, from white for the first subsector of the BSP walk
+	/// to black for the last
+	BspOrder,
+	/// How often each pixel was drawn, from blue for once to red for five
+	/// times or more. Black pixels were not drawn at all.
+	Overdraw,
+	/// The floors and ceilings in a color of their visplane, so the planes
+	/// split by R_CheckPlane stand out
+	Visplanes,
+	/// The solid walls, from white for the first to black for the last.
+	/// Columns no solid wall covers are red, where vanilla would show the
+	/// hall of mirrors.
+	SolidSegs
+}
+
+impl DebugView {
+	/// The view after this one, to step through them with a key.
+	pub fn next(self) -> DebugView {
+		match self {
+			DebugView::None => DebugView::Subsectors,
+			DebugView::Subsectors => DebugView::BspOrder,
+			DebugView::BspOrder => DebugView::Overdraw,
+			DebugView::Overdraw => DebugView::Visplanes,
+			DebugView::Visplanes => DebugView::SolidSegs,
+			DebugView::SolidSegs => DebugView::None
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			DebugView::None => "none",
+			DebugView::Subsectors => "subsectors",
+			DebugView::BspOrder => "BSP order",
+			DebugView::Overdraw => "overdraw",
+			DebugView::Visplanes => "visplanes",
+			DebugView::SolidSegs => "solid segs"
+		}
+	}
+}
+
+// Palette indices of PLAYPAL: the first of the shades of the color ramps,
+// brightest first, the grays from white to black, and the colors of the
+// overdraw heatmap from no draws to five or more
+const RAMPS : [u8; 6] = [176, 112, 200, 224, 208, 80];
+const RAMP_SHADES : u32 = 8;
+const GRAYS : u32 = 80;
+const GRAY_SHADES : u32 = 32;
+const HEAT : [u8; 6] = [0, 200, 112, 231, 216, 176];
+const HOLE_COLOR : u8 = 176;
+
+// Marks the columns no solid wall covers.
+const HOLE : u32 = ::std::u32::MAX;
+
+/// The debug values of the pixels of a canvas, turned into colors at the
+/// end of the frame. Values of 0 leave the pixel as it is drawn.
+pub struct DebugBuffer {
+	view : DebugView,
+	values : Vec<u32>,
+	left : i32,
+	width : usize,
+
+	// The value of the walls of the current subsector, and the number of
+	// solid walls so far
+	tag : u32,
+	solid_walls : u32
+}
+
+impl DebugBuffer {
+	pub fn new() -> DebugBuffer {
+		DebugBuffer {
+			view: DebugView::None,
+			values: Vec::<u32>::new(),
+			left: 0,
+			width: 0,
+			tag: 0,
+			solid_walls: 0
+		}
+	}
+
+	pub fn view(&self) -> DebugView {
+		self.view
+	}
+
+	pub fn set_view(&mut self, view : DebugView) {
+		self.view = view;
+	}
+
+	/// Starts a frame for a canvas of `width` by `height` pixels, from
+	/// column `left` of the screen.
+	pub fn reset(&mut self, left : i32, width : usize, height : usize) {
+		self.left = left;
+		self.width = width;
+		self.tag = 0;
+		self.solid_walls = 0;
+		self.values.clear();
+		if self.view != DebugView::None {
+			self.values.resize(width * height, 0);
+		}
+	}
+
+	/// Called when the BSP walk reaches a subsector, the `order`th so far.
+	pub fn enter_subsector(&mut self, index : usize, order : usize) {
+		self.tag = match self.view {
+			DebugView::Subsectors => index as u32 + 1,
+			DebugView::BspOrder => order as u32,
+			_ => 0
+		};
+	}
+
+	/// The value of the rows a wall closes, or 0 to leave them.
+	pub fn wall_tag(&mut self, solid : bool) -> u32 {
+		match self.view {
+			DebugView::SolidSegs if solid => {
+				self.solid_walls += 1;
+				self.solid_walls
+			}
+			DebugView::SolidSegs => 0,
+			_ => self.tag
+		}
+	}
+
+	/// Sets the rows from `top` to `bottom` of column x to `value`.
+	pub fn tag_column(&mut self, x : i32, top : i32, bottom : i32, value : u32) {
+		if value == 0 || self.values.is_empty() {
+			return;
+		}
+		let column = (x - self.left) as usize;
+		for y in top.max(0)..bottom + 1 {
+			self.values[y as usize * self.width + column] = value;
+		}
+	}
+
+	/// Marks a column as not covered by any solid wall.
+	pub fn tag_hole(&mut self, x : i32, height : i32) {
+		if self.view == DebugView::SolidSegs {
+			self.tag_column(x, 0, height - 1, HOLE);
+		}
+	}
+
+	/// Counts a draw of the rows from `top` to `bottom` of column x.
+	pub fn count_column(&mut self, x : i32, top : i32, bottom : i32) {
+		if self.view != DebugView::Overdraw {
+			return;
+		}
+		let column = (x - self.left) as usize;
+		for y in top.max(0)..bottom + 1 {
+			self.values[y as usize * self.width + column] += 1;
+		}
+	}
+
+	/// Counts a draw of the columns from `x1` to `x2` of row y.
+	pub fn count_row(&mut self, y : i32, x1 : i32, x2 : i32) {
+		if self.view != DebugView::Overdraw {
+			return;
+		}
+		let row = y as usize * self.width;
+		for x in x1..x2 + 1 {
+			self.values[row + (x - self.left) as usize] += 1;
+		}
+	}
+
+	/// Counts a draw of one pixel.
+	pub fn count(&mut self, x : i32, y : i32) {
+		if self.view == DebugView::Overdraw {
+			self.values[y as usize * self.width + (x - self.left) as usize] += 1;
+		}
+	}
+
+	/// Shows the values in the pixels. `subsectors` is the number of
+	/// subsectors the BSP walk reached.
+	pub fn apply(&self, pixels : &mut [u8], pitch : usize, subsectors : usize) {
+		let last = match self.view {
+			DebugView::None => return,
+			DebugView::BspOrder => subsectors as u32,
+			DebugView::SolidSegs => self.solid_walls,
+			_ => 0
+		};
+
+		for (row, values) in pixels.chunks_mut(pitch).zip(self.values.chunks(self.width)) {
+			for (pixel, &value) in row.iter_mut().zip(values.iter()) {
+				*pixel = match self.view {
+					DebugView::Overdraw => HEAT[(value as usize).min(HEAT.len() - 1)],
+					_ if value == 0 => continue,
+					DebugView::SolidSegs if value == HOLE => HOLE_COLOR,
+					DebugView::Subsectors | DebugView::Visplanes => distinct_color(value),
+					_ => (GRAYS + (value - 1) * (GRAY_SHADES - 1) / (last.max(2) - 1)) as u8
+				};
+			}
+		}
+	}
+}
+
+impl<'a> Renderer<'a> {
+	// Shows the debug values of the frame in its pixels.
+	pub(super) fn draw_debug_view(&mut self) {
+		if self.debug.view() == DebugView::None {
+			return;
+		}
+
+		for x in self.first_column..self.last_column + 1 {
+			if !self.solid_segs.is_covered(x, x) {
+				self.debug.tag_hole(x, self.height);
+			}
+		}
+
+		let (pixels, _, _, pitch) = self.canvas.get();
+		self.debug.apply(pixels, pitch, self.stats.subsectors);
+	}
+}
+
+// One of the bright shades of the color ramps, different for values next to
+// each other.
+fn distinct_color(value : u32) -> u8 {
+	let ramp = value as usize % RAMPS.len();
+	let shade = (value / RAMPS.len() as u32) % RAMP_SHADES;
+	RAMPS[ramp] + shade as u8
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use resources::ColorMap;
+	use resources::ResourceManager;
+	use resources::testwad::*;
+	use render::Canvas;
+	use render::View;
+	use super::*;
+
+	// The test room from the origin, with a barrel in front of the view.
+	fn render(file_name : &str, debug_view : DebugView) -> Vec<u8> {
+		let path = temp_path(file_name);
+		let mut lumps = room_map("E1M1", &[(96, 0, 2035)]);
+		lumps.push(("PNAMES", pnames(&["WALLPAT"])));
+		lumps.push(("TEXTURE1", texture1(&["WALL"])));
+		lumps.push(("WALLPAT", patch(40)));
+		lumps.push(("FLOOR", flat(20)));
+		lumps.push(("CEIL", flat(60)));
+		lumps.push(("S_START", Vec::<u8>::new()));
+		lumps.push(("BAR1A0", sprite_patch(16, 32, 8, 32, 90)));
+		lumps.push(("S_END", Vec::<u8>::new()));
+		write_wad(&path, &lumps);
+
+		let resources = ResourceManager::new();
+		resources.load_package(&path).unwrap();
+		let _ = fs::remove_file(&path);
+		let level = resources.find_map("E1M1").unwrap();
+		let lights = ColorMap::read(&colormap()).unwrap();
+
+		let mut pixels = vec![0u8; 64 * 40];
+		{
+			let mut renderer = Renderer::new(&level, &resources, &lights, Canvas::new(&mut pixels, 64, 40, 64));
+			renderer.set_debug_view(debug_view);
+			renderer.render_view(&View::on_floor(&level, 0, 0, 0));
+		}
+		pixels
+	}
+
+	#[test]
+	fn overdraw_counts_the_sprite_twice() {
+		let frame = render("debug-frame", DebugView::None);
+		let overdraw = render("debug-overdraw", DebugView::Overdraw);
+		assert!(frame.contains(&90), "The barrel isn't in view");
+
+		// The walls, floor and ceiling cover the screen once, and the barrel
+		// is drawn over them.
+		for (&drawn, &count) in frame.iter().zip(overdraw.iter()) {
+			assert_eq!(count, if drawn == 90 { HEAT[2] } else { HEAT[1] });
+		}
+	}
+
+	#[test]
+	fn one_subsector_colors_the_whole_screen() {
+		let subsectors = render("debug-subsectors", DebugView::Subsectors);
+		assert!(subsectors.iter().all(|&pixel| pixel == distinct_color(1)));
+
+		// The four walls close the room, so no column is a hole.
+		let solid_segs = render("debug-solid-segs", DebugView::SolidSegs);
+		assert!(!solid_segs.contains(&HOLE_COLOR));
+	}
+}
@@ -0,0 +1,87 @@
+use resources::NUM_COLORMAPS;
+
+// Like the lighting of r_main.c: sector light levels are grouped into 16
+// steps, and each step starts at a colormap that gets brighter up close.
+const LIGHT_LEVELS : i32 = 16;
+const LIGHT_SEG_SHIFT : u32 = 4;
+const MAX_LIGHT_SCALE : i32 = 48;
+const MAX_LIGHT_Z : i32 = 128;
+const DIST_MAP : i32 = 2;
+
+// The scales and distances of vanilla are for a 320 pixels wide view.
+pub const VANILLA_CENTER_X : f32 = 160.0;
+
+/// The light step of a sector light level, plus `contrast` steps. Walls
+/// along the x axis are a step darker and along the y axis a step brighter,
+/// like the fake contrast of R_StoreWallRange.
+pub fn light_step(light_level : u32, contrast : i32) -> i32 {
+	let step = (light_level >> 16 >> LIGHT_SEG_SHIFT) as i32 + contrast;
+	step.max(0).min(LIGHT_LEVELS - 1)
+}
+
+// The colormap a light step starts at, far away.
+fn start_map(step : i32) -> i32 {
+	(LIGHT_LEVELS - 1 - step) * 2 * NUM_COLORMAPS as i32 / LIGHT_LEVELS
+}
+
+/// The colormap of a wall or sprite column drawn at `scale` pixels per map
+/// unit, like scalelight.
+pub fn scale_light(step : i32, scale : f32, focal : f32) -> usize {
+	// Fixed point scale of vanilla, shifted by LIGHTSCALESHIFT
+	let index = ((scale * VANILLA_CENTER_X / focal * 16.0) as i32).max(0).min(MAX_LIGHT_SCALE - 1);
+	(start_map(step) - index / DIST_MAP).max(0).min(NUM_COLORMAPS as i32 - 1) as usize
+}
+
+/// The colormap of a floor or ceiling row `distance` map units away, like
+/// zlight.
+pub fn z_light(step : i32, distance : f32) -> usize {
+	// Distances are in steps of 16 units, LIGHTZSHIFT in vanilla.
+	let index = ((distance / 16.0) as i32).max(0).min(MAX_LIGHT_Z - 1);
+	let scale = ((VANILLA_CENTER_X as i32) << 12) / (index + 1) >> 12;
+	(start_map(step) - scale / DIST_MAP).max(0).min(NUM_COLORMAPS as i32 - 1) as usize
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn light_steps_match_vanilla() {
+		assert_eq!(light_step(255 << 16, 0), 15);
+		assert_eq!(light_step(128 << 16, 0), 8);
+		assert_eq!(light_step(143 << 16, -1), 7);
+		assert_eq!(light_step(0, -1), 0);
+		assert_eq!(light_step(255 << 16, 1), 15);
+	}
+
+	#[test]
+	fn scale_light_matches_vanilla() {
+		// scalelight[step][scale >> LIGHTSCALESHIFT] of a 320 wide view:
+		// startmap - j / DISTMAP, with startmap 0 at step 15, 28 at step 8
+		// and 60 at step 0.
+		let table = [
+			(15, 0.0, 0), (15, 1.0, 0),
+			(8, 0.0, 28), (8, 0.5, 24), (8, 1.0, 20), (8, 2.0, 12), (8, 3.0, 5), (8, 100.0, 5),
+			(0, 0.0, 31), (0, 1.0, 31), (0, 2.0, 31), (0, 3.0, 31)
+		];
+		for &(step, scale, colormap) in table.iter() {
+			assert_eq!(scale_light(step, scale, VANILLA_CENTER_X), colormap, "step {} at scale {}", step, scale);
+		}
+
+		// A wider view has more pixels per unit at the same distance.
+		assert_eq!(scale_light(8, 2.0, 2.0 * VANILLA_CENTER_X), 20);
+	}
+
+	#[test]
+	fn z_light_matches_vanilla() {
+		// zlight[step][distance >> LIGHTZSHIFT]: startmap - 160 / (j + 1) / DISTMAP.
+		let table = [
+			(15, 0.0, 0), (15, 2048.0, 0),
+			(8, 0.0, 0), (8, 80.0, 15), (8, 160.0, 21), (8, 512.0, 26), (8, 2048.0, 28), (8, 1e6, 28),
+			(0, 0.0, 0), (0, 160.0, 31), (0, 2048.0, 31)
+		];
+		for &(step, distance, colormap) in table.iter() {
+			assert_eq!(z_light(step, distance), colormap, "step {} at distance {}", step, distance);
+		}
+	}
+}
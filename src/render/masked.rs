@@ -0,0 +1,343 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+use resources::bsp;
+use resources::thing_info;
+use resources::Patch;
+use resources::SpriteFrame;
+use resources::Texture;
+use super::Renderer;
+use super::to_float;
+use super::lighting::light_step;
+use super::lighting::scale_light;
+use super::walls::WallProjection;
+
+// Which sides of a drawseg hide sprites behind it
+pub const SIL_NONE : u8 = 0;
+pub const SIL_BOTTOM : u8 = 1;
+pub const SIL_TOP : u8 = 2;
+pub const SIL_BOTH : u8 = 3;
+
+// Things closer than this to the eye are not drawn, in map units.
+const MIN_SPRITE_DISTANCE : f32 = 4.0;
+
+/// A masked mid-texture of a two-sided line. Its columns are drawn once,
+/// either behind the first sprite in front of them or at the end.
+pub struct MaskedTexture {
+	pub texture : Arc<Texture>,
+	pub top : f32,
+	pub light : i32,
+	pub drawn : usize
+}
+
+/// A wall drawn to some screen columns, kept until the end of the frame so
+/// that sprites and masked textures can be clipped against it, like the
+/// drawseg_t of vanilla.
+pub struct DrawSeg {
+	pub x1 : i32,
+	pub x2 : i32,
+	pub wall : WallProjection,
+	pub v1 : bsp::Vertex,
+	pub v2 : bsp::Vertex,
+
+	// Sprites below the bottom height or above the top height are hidden by
+	// the wall, outside the rows saved in the openings.
+	pub silhouette : u8,
+	pub bottom_height : i32,
+	pub top_height : i32,
+	pub top_clip : usize,
+	pub bottom_clip : usize,
+
+	pub masked : Option<MaskedTexture>
+}
+
+impl DrawSeg {
+	/// Returns true if the point is behind the seg, like R_PointOnSegSide.
+	fn is_behind(&self, x : i32, y : i32) -> bool {
+		// Points at opposite ends of the map are 33 bits apart, so the
+		// products take more than 64.
+		let dx = self.v2.x as i128 - self.v1.x as i128;
+		let dy = self.v2.y as i128 - self.v1.y as i128;
+		let px = x as i128 - self.v1.x as i128;
+		let py = y as i128 - self.v1.y as i128;
+		dx * py - dy * px >= 0
+	}
+}
+
+/// A thing projected to the screen, like the vissprite_t of vanilla.
+pub struct VisSprite {
+	thing : usize,
+	x1 : i32,
+	x2 : i32,
+	left : f32,
+	scale : f32,
+	thing_x : i32,
+	thing_y : i32,
+	bottom : i32,
+	top : i32,
+	patch : Arc<Patch>,
+	flipped : bool,
+	colormap : usize
+}
+
+impl<'a> Renderer<'a> {
+	fn sprite_frame(&mut self, sprite : &str, frame : u8) -> Option<Arc<SpriteFrame>> {
+		let key = format!("{}{}", sprite, frame as char);
+		if let Some(frame) = self.sprite_frames.get(&key) {
+			return frame.clone();
+		}
+
+		let value = self.resources.find_sprite_frame(sprite, frame);
+		self.sprite_frames.insert(key, value.clone());
+		value
+	}
+
+	/// Projects the things of a sector, like R_AddSprites. They are drawn
+	/// after the walls, from back to front.
+	pub(super) fn add_sprites(&mut self, sector : usize) {
+		if self.sector_sprites_added[sector] {
+			return;
+		}
+		self.sector_sprites_added[sector] = true;
+
+		let level = self.level;
+		let floor_height = level.sectors[sector].floor_height;
+		let light = light_step(level.sectors[sector].light_level, self.extra_light);
+
+		let sector_things = self.sector_things.clone();
+		for &index in sector_things[sector].iter() {
+			let thing = &level.things[index];
+			if thing.flags & bsp::MTF_NOTSINGLE != 0 || thing.flags & bsp::MTF_NORMAL == 0 {
+				continue;
+			}
+
+			let info = match thing_info(thing.thing_type) {
+				Some(value) => value,
+				None => continue
+			};
+			let frame = match self.sprite_frame(info.sprite, info.frame) {
+				Some(value) => value,
+				None => continue
+			};
+
+			let tx = to_float(thing.x) - self.view_x;
+			let ty = to_float(thing.y) - self.view_y;
+			let depth = tx * self.view_cos + ty * self.view_sin;
+			if depth < MIN_SPRITE_DISTANCE {
+				continue;
+			}
+
+			// Too far off to the side to be seen
+			let across = tx * self.view_sin - ty * self.view_cos;
+			if across.abs() > depth * 4.0 {
+				continue;
+			}
+
+			// Pick the rotation from the angle the thing is seen from.
+			let view_angle = (ty.atan2(tx) / (::std::f32::consts::PI * 2.0) * 4294967296.0) as i64 as u32;
+			let direction = view_angle.wrapping_sub((thing.angle as u32) << 16).wrapping_add(0x10000000 * 9) >> 29;
+			let rotation = match frame.rotation(direction as usize) {
+				Some(value) => value.clone(),
+				None => continue
+			};
+
+			let patch = rotation.patch;
+			let scale = self.focal / depth;
+			let offset = if rotation.flipped { patch.width - patch.left_offset } else { patch.left_offset };
+			let left = self.center_x + (across - offset as f32) * scale;
+			let right = left + patch.width as f32 * scale;
+
+			let x1 = (left.ceil() as i32).max(self.first_column);
+			let x2 = (right.ceil() as i32 - 1).min(self.last_column);
+			if x1 > x2 {
+				continue;
+			}
+
+			self.vis_sprites.push(VisSprite {
+				thing: index,
+				x1: x1,
+				x2: x2,
+				left: left,
+				scale: scale,
+				thing_x: thing.x,
+				thing_y: thing.y,
+				bottom: floor_height,
+				top: floor_height + (patch.top_offset << 16),
+				patch: patch,
+				flipped: rotation.flipped,
+				colormap: if info.full_bright { 0 } else { scale_light(light, scale, self.focal) }
+			});
+		}
+	}
+
+	/// Draws the sprites from back to front, then the masked textures that
+	/// are not behind any sprite, like R_DrawMasked.
+	pub(super) fn draw_masked(&mut self) {
+		let mut sprites = ::std::mem::replace(&mut self.vis_sprites, Vec::new());
+		// Things at the same distance are drawn in map order, not in the order
+		// their sectors were seen, so the order doesn't depend on which
+		// columns are drawn.
+		sprites.sort_by(|a, b| a.scale.partial_cmp(&b.scale).unwrap_or(Ordering::Equal).then(a.thing.cmp(&b.thing)));
+		self.stats.sprites = sprites.len();
+
+		for sprite in sprites.iter() {
+			self.draw_sprite(sprite);
+		}
+
+		for index in (0..self.draw_segs.len()).rev() {
+			if self.draw_segs[index].masked.is_some() {
+				let (x1, x2) = (self.draw_segs[index].x1, self.draw_segs[index].x2);
+				self.draw_masked_range(index, x1, x2);
+			}
+		}
+
+		sprites.clear();
+		self.vis_sprites = sprites;
+	}
+
+	/// Clips a sprite against the walls in front of it and draws it, like
+	/// R_DrawSprite.
+	fn draw_sprite(&mut self, sprite : &VisSprite) {
+		const UNCLIPPED : i32 = -2;
+		for x in sprite.x1..sprite.x2 + 1 {
+			self.sprite_top_clip[x as usize] = UNCLIPPED;
+			self.sprite_bottom_clip[x as usize] = UNCLIPPED;
+		}
+
+		// The walls drawn last are the closest.
+		for index in (0..self.draw_segs.len()).rev() {
+			let (r1, r2, silhouette) = {
+				let seg = &self.draw_segs[index];
+				if seg.x1 > sprite.x2 || seg.x2 < sprite.x1 || (seg.silhouette == SIL_NONE && seg.masked.is_none()) {
+					continue;
+				}
+
+				let r1 = seg.x1.max(sprite.x1);
+				let r2 = seg.x2.min(sprite.x2);
+
+				// The scales of the whole wall rather than of the columns it
+				// was drawn to, which depend on what was in front of it.
+				let (low_scale, high_scale) = seg.wall.scale_range(self.focal);
+
+				(r1, r2, if high_scale < sprite.scale || (low_scale < sprite.scale && !seg.is_behind(sprite.thing_x, sprite.thing_y)) {
+					None
+				} else {
+					let mut silhouette = seg.silhouette;
+					if sprite.bottom >= seg.bottom_height {
+						silhouette &= !SIL_BOTTOM;
+					}
+					if sprite.top <= seg.top_height {
+						silhouette &= !SIL_TOP;
+					}
+					Some(silhouette)
+				})
+			};
+
+			let silhouette = match silhouette {
+				Some(value) => value,
+				None => {
+					// The wall is behind the sprite, so its masked texture
+					// has to be drawn first.
+					if self.draw_segs[index].masked.is_some() {
+						self.draw_masked_range(index, r1, r2);
+					}
+					continue;
+				}
+			};
+
+			let seg = &self.draw_segs[index];
+			for x in r1..r2 + 1 {
+				let column = x as usize;
+				let opening = (x - seg.x1) as usize;
+				if silhouette & SIL_BOTTOM != 0 && self.sprite_bottom_clip[column] == UNCLIPPED {
+					self.sprite_bottom_clip[column] = self.openings[seg.bottom_clip + opening];
+				}
+				if silhouette & SIL_TOP != 0 && self.sprite_top_clip[column] == UNCLIPPED {
+					self.sprite_top_clip[column] = self.openings[seg.top_clip + opening];
+				}
+			}
+		}
+
+		for x in sprite.x1..sprite.x2 + 1 {
+			let column = x as usize;
+			if self.sprite_bottom_clip[column] == UNCLIPPED {
+				self.sprite_bottom_clip[column] = self.height;
+			}
+			if self.sprite_top_clip[column] == UNCLIPPED {
+				self.sprite_top_clip[column] = -1;
+			}
+		}
+
+		let patch = &sprite.patch;
+		let texture_top = to_float(sprite.top) - self.view_z;
+		let center_y = self.center_y;
+		let shade = self.colormap.map(sprite.colormap);
+		let left = self.first_column;
+		let (screen, _, _, pitch) = self.canvas.get();
+
+		for x in sprite.x1..sprite.x2 + 1 {
+			let column = x as usize;
+			let mut u = (((x as f32 - sprite.left) / sprite.scale) as i32).max(0).min(patch.width - 1);
+			if sprite.flipped {
+				u = patch.width - 1 - u;
+			}
+
+			for post in patch.columns[u as usize].iter() {
+				let top = center_y - (texture_top - post.top as f32) * sprite.scale;
+				let bottom = top + post.pixels.len() as f32 * sprite.scale;
+				let y_start = (top.ceil() as i32).max(self.sprite_top_clip[column] + 1);
+				let y_end = (bottom.ceil() as i32 - 1).min(self.sprite_bottom_clip[column] - 1);
+
+				for y in y_start..y_end + 1 {
+					let v = (((y as f32 - top) / sprite.scale) as usize).min(post.pixels.len() - 1);
+					screen[y as usize * pitch + (x - left) as usize] = shade[post.pixels[v] as usize];
+				}
+				self.debug.count_column(x, y_start, y_end);
+			}
+		}
+	}
+
+	/// Draws the columns from `x1` to `x2` of a masked texture that are not
+	/// drawn yet, like R_RenderMaskedSegRange. Masked textures don't repeat
+	/// vertically.
+	fn draw_masked_range(&mut self, index : usize, x1 : i32, x2 : i32) {
+		let focal = self.focal;
+		let center_y = self.center_y;
+		let colormap = self.colormap;
+		let left = self.first_column;
+		let (screen, _, _, pitch) = self.canvas.get();
+
+		let seg = &self.draw_segs[index];
+		let masked = match seg.masked {
+			Some(ref value) => value,
+			None => return
+		};
+		let texture = &masked.texture;
+
+		for x in x1..x2 + 1 {
+			let opening = (x - seg.x1) as usize;
+			if self.masked_drawn[masked.drawn + opening] {
+				continue;
+			}
+			self.masked_drawn[masked.drawn + opening] = true;
+
+			let (scale, u) = seg.wall.at(x, focal);
+			let pixels = texture.column(u);
+			let opaque = texture.opaque_column(u);
+			let shade = colormap.map(scale_light(masked.light, scale, focal));
+
+			let top = center_y - masked.top * scale;
+			let bottom = top + texture.height as f32 * scale;
+			let y_start = (top.ceil() as i32).max(self.openings[seg.top_clip + opening] + 1);
+			let y_end = (bottom.ceil() as i32 - 1).min(self.openings[seg.bottom_clip + opening] - 1);
+
+			for y in y_start..y_end + 1 {
+				let v = (((y as f32 - top) / scale) as usize).min(texture.height as usize - 1);
+				if !opaque[v] {
+					continue;
+				}
+				screen[y as usize * pitch + (x - left) as usize] = shade[pixels[v] as usize];
+				self.debug.count(x, y);
+			}
+		}
+	}
+}
@@ -0,0 +1,732 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use resources::bsp;
+use resources::ColorMap;
+use resources::ResourceManager;
+use resources::Flat;
+use resources::SpriteFrame;
+use resources::Texture;
+use self::clip::ClipRange;
+use self::clip::SolidSegs;
+use self::debug::DebugBuffer;
+use self::masked::DrawSeg;
+use self::masked::VisSprite;
+use self::planes::Visplane;
+use self::sky::SKY_FLAT;
+use self::sky::sky_texture_name;
+
+pub use self::canvas::*;
+pub use self::debug::DebugView;
+pub use self::parallel::ParallelRenderer;
+pub use self::planes::VANILLA_MAX_VISPLANES;
+pub use self::software::SoftwareRenderer;
+pub use self::wireframe::WireframeRenderer;
+
+mod canvas;
+mod clip;
+mod debug;
+mod lighting;
+mod masked;
+mod parallel;
+mod planes;
+mod sky;
+mod software;
+mod walls;
+mod wireframe;
+
+/// Height of the eyes above the floor, like VIEWHEIGHT.
+pub const VIEW_HEIGHT : i32 = 41 << 16;
+
+// Walls closer than this to the eye are clipped, in map units.
+const NEAR_CLIP : f32 = 1.0;
+
+// Half of the horizontal field of view, as a binary angle
+const CLIP_ANGLE : u32 = 0x20000000;
+
+// The furthest the view looks up or down, 30 degrees as a binary angle.
+const MAX_PITCH : i16 = 0x1555;
+
+/// Where the scene is seen from. The position is in 16.16 fixed point map
+/// coordinates.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct View {
+	pub x : i32,
+	pub y : i32,
+	pub z : i32,
+	pub angle : u16,
+
+	/// How far the view looks up, as a binary angle like `angle`. Looking
+	/// down is negative. The walls stay upright and the horizon moves, like
+	/// looking up in Heretic.
+	pub pitch : i16,
+
+	/// Light steps added to every sector, like the extralight of weapon
+	/// flashes.
+	pub extra_light : i32
+}
+
+impl View {
+	/// A view standing on the floor of the sector at (x, y).
+	pub fn on_floor(level : &bsp::Map, x : i32, y : i32, angle : u16) -> View {
+		let floor = level.sector_at(x, y).map_or(0, |sector| sector.floor_height);
+		View {
+			x: x,
+			y: y,
+			z: floor + VIEW_HEIGHT,
+			angle: angle,
+			pitch: 0,
+			extra_light: 0
+		}
+	}
+
+	// The row of the horizon on a screen `height` pixels high.
+	fn horizon(&self, height : i32, focal : f32) -> f32 {
+		let pitch = self.pitch.max(-MAX_PITCH).min(MAX_PITCH);
+		let radians = pitch as f32 / 65536.0 * ::std::f32::consts::PI * 2.0;
+		height as f32 / 2.0 + radians.tan() * focal
+	}
+}
+
+/// Draws views of maps. The software renderer and the debugging renderers
+/// all draw through this, so they can be swapped while the game runs, and
+/// tools can draw views without it.
+pub trait ViewRenderer {
+	/// A short name to show when switching renderers.
+	fn name(&self) -> &'static str;
+
+	/// Draws the map as seen from the view into the canvas.
+	fn render_view(&mut self, level : &bsp::Map, view : &View, target : Canvas);
+
+	/// The counts of the last rendered frame.
+	fn stats(&self) -> RenderStats;
+
+	/// Shows a debug view over the frames. Renderers without debug views
+	/// ignore it.
+	fn set_debug_view(&mut self, _view : DebugView) {
+	}
+}
+
+/// How much of the map a frame had to look at.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RenderStats {
+	pub nodes : usize,
+	pub subsectors : usize,
+	pub segs : usize,
+	pub visplanes : usize,
+	pub drawsegs : usize,
+	pub sprites : usize,
+	pub visplane_overflow : bool
+}
+
+pub struct Renderer<'a> {
+	level : &'a bsp::Map,
+	resources : &'a ResourceManager,
+	colormap : &'a ColorMap,
+	canvas : Canvas<'a>,
+	node_culling : bool,
+	stats : RenderStats,
+	textures : HashMap<String, Option<Arc<Texture>>>,
+	flats : HashMap<String, Option<Arc<Flat>>>,
+	sprite_frames : HashMap<String, Option<Arc<SpriteFrame>>>,
+
+	// Columns already covered by solid walls, and a scratch list for the
+	// visible parts of a wall
+	solid_segs : SolidSegs,
+	visible : Vec<ClipRange>,
+
+	// The last row above and the first row below the openings left by two
+	// sided walls in each column
+	ceiling_clip : Vec<i32>,
+	floor_clip : Vec<i32>,
+
+	// The floors and ceilings seen so far, and the planes of the current
+	// subsector. The planes are kept between frames to reuse their memory.
+	planes : Vec<Visplane>,
+	plane_count : usize,
+	visplane_limit : Option<usize>,
+	floor_plane : Option<usize>,
+	ceiling_plane : Option<usize>,
+	span_start : Vec<i32>,
+
+	// The walls drawn this frame, and the clip rows they saved for sprites
+	// and masked textures. Which columns of masked textures are drawn is
+	// kept in masked_drawn.
+	draw_segs : Vec<DrawSeg>,
+	openings : Vec<i32>,
+	masked_drawn : Vec<bool>,
+
+	// The things of each sector, and the sectors whose things have been
+	// projected this frame
+	sector_things : Arc<Vec<Vec<usize>>>,
+	sector_sprites_added : Vec<bool>,
+	vis_sprites : Vec<VisSprite>,
+	sprite_top_clip : Vec<i32>,
+	sprite_bottom_clip : Vec<i32>,
+
+	// The sky texture of the map, and the light steps added to all sectors
+	sky_texture : &'static str,
+	extra_light : i32,
+
+	debug : DebugBuffer,
+
+	// Projection, and the columns of the screen in the canvas
+	width : i32,
+	height : i32,
+	first_column : i32,
+	last_column : i32,
+	center_x : f32,
+	center_y : f32,
+	focal : f32,
+
+	// The view in floating point map units
+	view_x : f32,
+	view_y : f32,
+	view_z : f32,
+	view_angle : u32,
+	view_cos : f32,
+	view_sin : f32
+}
+
+/// The buffers of a renderer, kept between frames so drawing a frame
+/// doesn't allocate once they have grown to fit the screen and the map.
+pub struct RenderMemory {
+	textures : HashMap<String, Option<Arc<Texture>>>,
+	flats : HashMap<String, Option<Arc<Flat>>>,
+	sprite_frames : HashMap<String, Option<Arc<SpriteFrame>>>,
+	solid_segs : SolidSegs,
+	visible : Vec<ClipRange>,
+	ceiling_clip : Vec<i32>,
+	floor_clip : Vec<i32>,
+	planes : Vec<Visplane>,
+	span_start : Vec<i32>,
+	draw_segs : Vec<DrawSeg>,
+	openings : Vec<i32>,
+	masked_drawn : Vec<bool>,
+	sector_sprites_added : Vec<bool>,
+	vis_sprites : Vec<VisSprite>,
+	sprite_top_clip : Vec<i32>,
+	sprite_bottom_clip : Vec<i32>,
+	debug : DebugBuffer
+}
+
+impl RenderMemory {
+	pub fn new() -> RenderMemory {
+		RenderMemory {
+			textures: HashMap::<String, Option<Arc<Texture>>>::new(),
+			flats: HashMap::<String, Option<Arc<Flat>>>::new(),
+			sprite_frames: HashMap::<String, Option<Arc<SpriteFrame>>>::new(),
+			solid_segs: SolidSegs::new(),
+			visible: Vec::<ClipRange>::new(),
+			ceiling_clip: Vec::<i32>::new(),
+			floor_clip: Vec::<i32>::new(),
+			planes: Vec::<Visplane>::new(),
+			span_start: Vec::<i32>::new(),
+			draw_segs: Vec::<DrawSeg>::new(),
+			openings: Vec::<i32>::new(),
+			masked_drawn: Vec::<bool>::new(),
+			sector_sprites_added: Vec::<bool>::new(),
+			vis_sprites: Vec::<VisSprite>::new(),
+			sprite_top_clip: Vec::<i32>::new(),
+			sprite_bottom_clip: Vec::<i32>::new(),
+			debug: DebugBuffer::new()
+		}
+	}
+}
+
+/// Lists the things of each sector of the level, reusing the lists from the
+/// last frame.
+pub fn sort_things(level : &bsp::Map, sector_things : &mut Vec<Vec<usize>>) {
+	sector_things.resize(level.sectors.len(), Vec::<usize>::new());
+	for things in sector_things.iter_mut() {
+		things.clear();
+	}
+	for (index, thing) in level.things.iter().enumerate() {
+		if let Some(sector) = subsector_sector(level, level.subsector_at(thing.x, thing.y)) {
+			sector_things[sector].push(index);
+		}
+	}
+}
+
+impl<'a> Renderer<'a> {
+	pub fn new(level : &'a bsp::Map, resources : &'a ResourceManager, colormap : &'a ColorMap, canvas : Canvas<'a>) -> Renderer<'a> {
+		let mut sector_things = Vec::<Vec<usize>>::new();
+		sort_things(level, &mut sector_things);
+		Renderer::with_memory(level, resources, colormap, canvas, RenderMemory::new(), Arc::new(sector_things))
+	}
+
+	/// A renderer drawing with the buffers of an earlier one. The things of
+	/// each sector come from `sort_things`, and can be shared by the
+	/// renderers of a frame.
+	pub fn with_memory(level : &'a bsp::Map, resources : &'a ResourceManager, colormap : &'a ColorMap, canvas : Canvas<'a>,
+		memory : RenderMemory, sector_things : Arc<Vec<Vec<usize>>>) -> Renderer<'a> {
+
+		let RenderMemory {
+			mut textures,
+			mut flats,
+			mut sprite_frames,
+			solid_segs,
+			visible,
+			ceiling_clip,
+			floor_clip,
+			planes,
+			span_start,
+			draw_segs,
+			openings,
+			masked_drawn,
+			mut sector_sprites_added,
+			vis_sprites,
+			sprite_top_clip,
+			sprite_bottom_clip,
+			debug
+		} = memory;
+
+		// The packages may have been reloaded since the last frame.
+		textures.clear();
+		flats.clear();
+		sprite_frames.clear();
+		sector_sprites_added.clear();
+		sector_sprites_added.resize(level.sectors.len(), false);
+
+		Renderer {
+			level: level,
+			resources: resources,
+			colormap: colormap,
+			canvas: canvas,
+			node_culling: true,
+			stats: RenderStats::default(),
+			textures: textures,
+			flats: flats,
+			sprite_frames: sprite_frames,
+			solid_segs: solid_segs,
+			visible: visible,
+			ceiling_clip: ceiling_clip,
+			floor_clip: floor_clip,
+			planes: planes,
+			plane_count: 0,
+			visplane_limit: None,
+			floor_plane: None,
+			ceiling_plane: None,
+			span_start: span_start,
+			sky_texture: sky_texture_name(&level.name),
+			extra_light: 0,
+			debug: debug,
+			draw_segs: draw_segs,
+			openings: openings,
+			masked_drawn: masked_drawn,
+			sector_things: sector_things,
+			sector_sprites_added: sector_sprites_added,
+			vis_sprites: vis_sprites,
+			sprite_top_clip: sprite_top_clip,
+			sprite_bottom_clip: sprite_bottom_clip,
+			width: 0,
+			height: 0,
+			first_column: 0,
+			last_column: -1,
+			center_x: 0.0,
+			center_y: 0.0,
+			focal: 0.0,
+			view_x: 0.0,
+			view_y: 0.0,
+			view_z: 0.0,
+			view_angle: 0,
+			view_cos: 1.0,
+			view_sin: 0.0
+		}
+	}
+
+	/// Gives back the buffers, for the renderer of the next frame.
+	pub fn into_memory(self) -> RenderMemory {
+		RenderMemory {
+			textures: self.textures,
+			flats: self.flats,
+			sprite_frames: self.sprite_frames,
+			solid_segs: self.solid_segs,
+			visible: self.visible,
+			ceiling_clip: self.ceiling_clip,
+			floor_clip: self.floor_clip,
+			planes: self.planes,
+			span_start: self.span_start,
+			draw_segs: self.draw_segs,
+			openings: self.openings,
+			masked_drawn: self.masked_drawn,
+			sector_sprites_added: self.sector_sprites_added,
+			vis_sprites: self.vis_sprites,
+			sprite_top_clip: self.sprite_top_clip,
+			sprite_bottom_clip: self.sprite_bottom_clip,
+			debug: self.debug
+		}
+	}
+
+	/// Turns skipping of BSP nodes outside the view on or off. It is on by
+	/// default, turning it off is only useful for comparing.
+	pub fn set_node_culling(&mut self, enabled : bool) {
+		self.node_culling = enabled;
+	}
+
+	/// The counts of the last rendered frame.
+	pub fn stats(&self) -> RenderStats {
+		self.stats
+	}
+
+	/// Shows a debug view over the frames instead of the frames as they are.
+	pub fn set_debug_view(&mut self, view : DebugView) {
+		self.debug.set_view(view);
+	}
+
+	pub fn render_view(&mut self, view : &View) {
+		self.render_walls(view);
+		self.render_masked();
+	}
+
+	// The first half of a frame: walks the BSP tree, draws the walls and
+	// marks the planes, and projects the things of the sectors seen.
+	fn render_walls(&mut self, view : &View) {
+		self.setup_view(view);
+		self.canvas.clear(0);
+
+		if self.level.nodes.is_empty() {
+			self.render_subsector(0);
+		} else {
+			self.render_bsp_node(self.level.nodes.len() as u32 - 1);
+		}
+	}
+
+	// The second half of a frame: draws the planes, then the sprites and the
+	// masked textures, and the debug view over them.
+	fn render_masked(&mut self) {
+		self.draw_planes();
+		self.draw_masked();
+		self.draw_debug_view();
+	}
+
+	fn setup_view(&mut self, view : &View) {
+		let width = self.canvas.screen_width();
+		self.width = width as i32;
+		self.height = self.canvas.height() as i32;
+		self.first_column = self.canvas.left() as i32;
+		self.last_column = self.first_column + self.canvas.width() as i32 - 1;
+		self.stats = RenderStats::default();
+
+		// 90 degrees horizontal field of view
+		self.center_x = width as f32 / 2.0;
+		self.focal = self.center_x;
+		self.center_y = view.horizon(self.height, self.focal);
+		self.extra_light = view.extra_light;
+
+		// The columns outside the canvas count as covered, so nothing is
+		// drawn there and what only shows there is skipped.
+		self.solid_segs.reset(self.width);
+		if self.first_column > 0 {
+			self.solid_segs.add(0, self.first_column - 1);
+		}
+		if self.last_column < self.width - 1 {
+			self.solid_segs.add(self.last_column + 1, self.width - 1);
+		}
+		self.ceiling_clip.clear();
+		self.ceiling_clip.resize(width, -1);
+		self.floor_clip.clear();
+		self.floor_clip.resize(width, self.height);
+		self.plane_count = 0;
+		self.debug.reset(self.first_column, self.canvas.width(), self.height as usize);
+
+		self.draw_segs.clear();
+		self.openings.clear();
+		self.masked_drawn.clear();
+		self.vis_sprites.clear();
+		for added in self.sector_sprites_added.iter_mut() {
+			*added = false;
+		}
+		self.sprite_top_clip.resize(width, -1);
+		self.sprite_bottom_clip.resize(width, self.height);
+
+		let angle = (view.angle as f32) / (0x10000 as f32) * ::std::f32::consts::PI * 2.0;
+		self.view_x = to_float(view.x);
+		self.view_y = to_float(view.y);
+		self.view_z = to_float(view.z);
+		self.view_angle = (view.angle as u32) << 16;
+		self.view_cos = angle.cos();
+		self.view_sin = angle.sin();
+	}
+
+	fn render_bsp_node(&mut self, node : u32) {
+		// Nothing behind a full screen of walls can be seen.
+		if self.solid_segs.is_full() {
+			return;
+		}
+
+		if node & bsp::NF_SUBSECTOR != 0 {
+			self.render_subsector(node & !bsp::NF_SUBSECTOR);
+			return;
+		}
+
+		self.stats.nodes += 1;
+
+		let level = self.level;
+		let node = &level.nodes[node as usize];
+		let side = node.point_side(to_fixed(self.view_x), to_fixed(self.view_y));
+
+		self.render_bsp_node(node.child[side]);
+
+		// The far side is often out of view, or hidden by what was just drawn.
+		if !self.node_culling || self.check_bounds(&node.bounds[side ^ 1]) {
+			self.render_bsp_node(node.child[side ^ 1]);
+		}
+	}
+
+	/// Returns true if some of the bounding box may be visible, like
+	/// R_CheckBBox. The box is reduced to the two corners that span it as seen
+	/// from the view, and the columns between them are checked against the
+	/// walls drawn so far.
+	fn check_bounds(&self, bounds : &bsp::Bounds) -> bool {
+		let x = to_fixed(self.view_x);
+		let y = to_fixed(self.view_y);
+
+		let box_x = if x <= bounds.left { 0 } else if x < bounds.right { 1 } else { 2 };
+		let box_y = if y >= bounds.top { 0 } else if y > bounds.bottom { 1 } else { 2 };
+
+		let (x1, y1, x2, y2) = match (box_x, box_y) {
+			(0, 0) => (bounds.right, bounds.top, bounds.left, bounds.bottom),
+			(1, 0) => (bounds.right, bounds.top, bounds.left, bounds.top),
+			(2, 0) => (bounds.right, bounds.bottom, bounds.left, bounds.top),
+			(0, 1) => (bounds.left, bounds.top, bounds.left, bounds.bottom),
+			(2, 1) => (bounds.right, bounds.bottom, bounds.right, bounds.top),
+			(0, 2) => (bounds.left, bounds.top, bounds.right, bounds.bottom),
+			(1, 2) => (bounds.left, bounds.bottom, bounds.right, bounds.bottom),
+			(2, 2) => (bounds.left, bounds.bottom, bounds.right, bounds.top),
+			// The view is inside the box.
+			_ => return true
+		};
+
+		// Angles relative to the view direction, to the left is positive.
+		let mut angle1 = self.view_angle_to(x1, y1);
+		let mut angle2 = self.view_angle_to(x2, y2);
+
+		// The view is on the edge of the box.
+		let span = angle1.wrapping_sub(angle2);
+		if span >= 0x80000000 {
+			return true;
+		}
+
+		// Clip to the field of view
+		let mut tspan = angle1.wrapping_add(CLIP_ANGLE);
+		if tspan > 2 * CLIP_ANGLE {
+			tspan -= 2 * CLIP_ANGLE;
+			if tspan >= span {
+				return false;
+			}
+			angle1 = CLIP_ANGLE;
+		}
+
+		tspan = CLIP_ANGLE.wrapping_sub(angle2);
+		if tspan > 2 * CLIP_ANGLE {
+			tspan -= 2 * CLIP_ANGLE;
+			if tspan >= span {
+				return false;
+			}
+			angle2 = CLIP_ANGLE.wrapping_neg();
+		}
+
+		let first = self.angle_to_column(angle1).max(0);
+		let last = (self.angle_to_column(angle2) - 1).min(self.width - 1);
+		if first > last {
+			return false;
+		}
+
+		!self.solid_segs.is_covered(first, last)
+	}
+
+	// The binary angle from the view direction to a point.
+	fn view_angle_to(&self, x : i32, y : i32) -> u32 {
+		let dx = to_float(x) - self.view_x;
+		let dy = to_float(y) - self.view_y;
+		let along = dx * self.view_cos + dy * self.view_sin;
+		let across = dy * self.view_cos - dx * self.view_sin;
+		let angle = across.atan2(along) / (::std::f32::consts::PI * 2.0);
+		(angle * 4294967296.0) as i64 as u32
+	}
+
+	// The first column whose left edge is right of the angle.
+	fn angle_to_column(&self, angle : u32) -> i32 {
+		let radians = angle as i32 as f32 / 4294967296.0 * ::std::f32::consts::PI * 2.0;
+		(self.center_x - radians.tan() * self.focal).ceil() as i32
+	}
+
+	fn render_subsector(&mut self, index : u32) {
+		let level = self.level;
+		let subsector = &level.subsectors[index as usize];
+
+		let segs = &level.segs[(subsector.first_seg as usize)..(subsector.first_seg as usize + subsector.num_segs as usize)];
+		self.stats.subsectors += 1;
+		self.debug.enter_subsector(index as usize, self.stats.subsectors);
+
+		// The floor is only seen from above and the ceiling from below, except
+		// for the sky, which is seen through any ceiling with the sky flat.
+		self.floor_plane = None;
+		self.ceiling_plane = None;
+		if let Some(index) = subsector_sector(level, index as usize) {
+			let sector = &level.sectors[index];
+			if to_float(sector.floor_height) < self.view_z {
+				self.floor_plane = self.find_plane(sector.floor_height, &sector.floor_texture, sector.light_level);
+			}
+			if to_float(sector.ceiling_height) > self.view_z || sector.ceiling_texture == SKY_FLAT {
+				self.ceiling_plane = self.find_plane(sector.ceiling_height, &sector.ceiling_texture, sector.light_level);
+			}
+
+			// Things are projected with the first subsector of their sector
+			// that is seen.
+			self.add_sprites(index);
+		}
+
+		for seg in segs {
+			self.draw_line(seg);
+		}
+	}
+
+	fn texture(&mut self, name : &str) -> Option<Arc<Texture>> {
+		if name == bsp::NO_TEXTURE {
+			return None;
+		}
+
+		if let Some(texture) = self.textures.get(name) {
+			return texture.clone();
+		}
+
+		let texture = self.resources.find_texture(name);
+		self.textures.insert(name.to_string(), texture.clone());
+		texture
+	}
+}
+
+// The index of the sector a subsector is in, found through its first seg.
+fn subsector_sector(level : &bsp::Map, subsector : usize) -> Option<usize> {
+	let seg = match level.subsectors.get(subsector).and_then(|subsector| level.segs.get(subsector.first_seg as usize)) {
+		Some(value) => value,
+		None => return None
+	};
+	let line = match level.lines.get(seg.line as usize) {
+		Some(value) => value,
+		None => return None
+	};
+	level.sides.get(line.side[(seg.side & 1) as usize] as usize)
+		.map(|side| side.sector as usize)
+		.filter(|&sector| sector < level.sectors.len())
+}
+
+fn to_float(value : i32) -> f32 {
+	value as f32 / 65536.0
+}
+
+fn to_fixed(value : f32) -> i32 {
+	(value * 65536.0) as i32
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use resources::testwad::*;
+	use super::*;
+
+	// The test room with gray 40 walls, a gray 20 floor and a gray 60 ceiling.
+	fn load_room(file_name : &str) -> (ResourceManager, Arc<bsp::Map>) {
+		let path = temp_path(file_name);
+		let mut lumps = room_map("E1M1", &[]);
+		lumps.push(("PNAMES", pnames(&["WALLPAT"])));
+		lumps.push(("TEXTURE1", texture1(&["WALL"])));
+		lumps.push(("WALLPAT", patch(40)));
+		lumps.push(("FLOOR", flat(20)));
+		lumps.push(("CEIL", flat(60)));
+		write_wad(&path, &lumps);
+
+		let resources = ResourceManager::new();
+		resources.load_package(&path).unwrap();
+		let _ = fs::remove_file(&path);
+		let level = resources.find_map("E1M1").unwrap();
+		(resources, level)
+	}
+
+	fn seg(v0 : u32, v1 : u32, line : u32, offset : i32) -> bsp::LineSegment {
+		bsp::LineSegment { v: [v0, v1], angle: 0, side: 0, line: line, offset: offset << 16 }
+	}
+
+	fn bounds(left : i32, top : i32, right : i32, bottom : i32) -> bsp::Bounds {
+		bsp::Bounds { left: left << 16, top: top << 16, right: right << 16, bottom: bottom << 16 }
+	}
+
+	// The room split by a node builder: x 64 splits off the far end, x 128
+	// the end of that, and y 0 splits the end in two.
+	fn split_room(room : &bsp::Map) -> bsp::Map {
+		let mut level = room.clone();
+		for &(x, y) in [(64, 64), (128, 64), (192, 0), (128, -64), (64, -64)].iter() {
+			level.vertices.push(bsp::Vertex { x: x << 16, y: y << 16 });
+		}
+
+		level.segs = vec![
+			// x -64 to 64
+			seg(0, 4, 0, 0), seg(8, 3, 2, 128), seg(3, 0, 3, 0),
+			// x 64 to 128
+			seg(4, 5, 0, 128), seg(7, 8, 2, 64),
+			// x 128 to 192, above and below y 0
+			seg(5, 1, 0, 192), seg(1, 6, 1, 0),
+			seg(6, 2, 1, 64), seg(2, 7, 2, 0)
+		];
+		level.subsectors = vec![
+			bsp::Subsector { num_segs: 3, first_seg: 0 },
+			bsp::Subsector { num_segs: 2, first_seg: 3 },
+			bsp::Subsector { num_segs: 2, first_seg: 5 },
+			bsp::Subsector { num_segs: 2, first_seg: 7 }
+		];
+		level.nodes = vec![
+			bsp::Node {
+				x: 128 << 16, y: 0, dx: 1 << 16, dy: 0,
+				bounds: [bounds(128, 0, 192, -64), bounds(128, 64, 192, 0)],
+				child: [bsp::NF_SUBSECTOR | 3, bsp::NF_SUBSECTOR | 2]
+			},
+			bsp::Node {
+				x: 128 << 16, y: 0, dx: 0, dy: -1 << 16,
+				bounds: [bounds(64, 64, 128, -64), bounds(128, 64, 192, -64)],
+				child: [bsp::NF_SUBSECTOR | 1, 0]
+			},
+			bsp::Node {
+				x: 64 << 16, y: 0, dx: 0, dy: -1 << 16,
+				bounds: [bounds(-64, 64, 64, -64), bounds(64, 64, 192, -64)],
+				child: [bsp::NF_SUBSECTOR, 1]
+			}
+		];
+		level
+	}
+
+	fn render(level : &bsp::Map, resources : &ResourceManager, view : &View, node_culling : bool) -> (Vec<u8>, RenderStats) {
+		let lights = ColorMap::read(&colormap()).unwrap();
+		let mut pixels = vec![0u8; 64 * 40];
+		let mut renderer = Renderer::new(level, resources, &lights, Canvas::new(&mut pixels, 64, 40, 64));
+		renderer.set_node_culling(node_culling);
+		renderer.render_view(view);
+		let stats = renderer.stats();
+		(pixels, stats)
+	}
+
+	#[test]
+	fn node_culling_skips_only_what_is_out_of_view() {
+		let (resources, room) = load_room("culling");
+		let level = split_room(&room);
+
+		// Looking back along the room from x 80, the far end is behind the view.
+		let view = View::on_floor(&level, 80 << 16, 0, 0x8000);
+		let (culled, culled_stats) = render(&level, &resources, &view, true);
+		let (all, all_stats) = render(&level, &resources, &view, false);
+		assert!(culled == all);
+		assert_eq!((culled_stats.nodes, all_stats.nodes), (2, 3));
+
+		// The split room looks like the room it was split from.
+		let (unsplit, _) = render(&room, &resources, &view, true);
+		assert!(culled == unsplit);
+		assert!(culled.contains(&40) && culled.contains(&20) && culled.contains(&60));
+
+		// Looking along it, everything is in view.
+		let view = View::on_floor(&level, 0, 0, 0);
+		let (culled, culled_stats) = render(&level, &resources, &view, true);
+		let (all, all_stats) = render(&level, &resources, &view, false);
+		assert!(culled == all);
+		assert_eq!(culled_stats.nodes, all_stats.nodes);
+	}
+}
@@ -0,0 +1,271 @@
+use std::mem;
+use std::sync::Arc;
+use std::thread;
+use resources::bsp;
+use resources::ColorMap;
+use resources::ResourceManager;
+use super::Canvas;
+use super::DebugView;
+use super::RenderMemory;
+use super::RenderStats;
+use super::Renderer;
+use super::View;
+use super::sort_things;
+
+/// Renders views on several threads, each drawing a strip of the screen
+/// columns with its own clip arrays and visplanes. The strips are drawn
+/// into their own buffers, then copied to the canvas. The pixels are the
+/// same as those of `Renderer`.
+///
+/// Things are projected by every strip whose columns they cover, so after
+/// walking the BSP tree the strips share which sectors they have seen, like
+/// one walk of the whole screen would have.
+///
+/// The threads are scoped to each half of a frame, so they can borrow the
+/// level and the strips. The buffers of the strips and the things of each
+/// sector are kept from one frame to the next.
+pub struct ParallelRenderer<'a> {
+	resources : &'a ResourceManager,
+	colormap : &'a ColorMap,
+	threads : usize,
+	memory : Vec<RenderMemory>,
+	buffers : Vec<Vec<u8>>,
+	sector_things : Arc<Vec<Vec<usize>>>,
+	node_culling : bool,
+	visplane_limit : Option<usize>,
+	debug_view : DebugView,
+	stats : RenderStats
+}
+
+impl<'a> ParallelRenderer<'a> {
+	/// A renderer that splits the screen into a strip for each of `threads`
+	/// threads.
+	pub fn new(resources : &'a ResourceManager, colormap : &'a ColorMap, threads : usize) -> ParallelRenderer<'a> {
+		ParallelRenderer {
+			resources: resources,
+			colormap: colormap,
+			threads: threads.max(1),
+			memory: Vec::<RenderMemory>::new(),
+			buffers: Vec::<Vec<u8>>::new(),
+			sector_things: Arc::new(Vec::<Vec<usize>>::new()),
+			node_culling: true,
+			visplane_limit: None,
+			debug_view: DebugView::None,
+			stats: RenderStats::default()
+		}
+	}
+
+	/// Changes the number of threads from the next frame on.
+	pub fn set_threads(&mut self, threads : usize) {
+		self.threads = threads.max(1);
+	}
+
+	/// See `Renderer::set_node_culling`.
+	pub fn set_node_culling(&mut self, enabled : bool) {
+		self.node_culling = enabled;
+	}
+
+	/// See `Renderer::set_visplane_limit`. The limit is for the whole screen,
+	/// so limited frames are drawn on one thread.
+	pub fn set_visplane_limit(&mut self, limit : Option<usize>) {
+		self.visplane_limit = limit;
+	}
+
+	/// See `Renderer::set_debug_view`. The debug views show the BSP walk of
+	/// the whole screen, so their frames are drawn on one thread.
+	pub fn set_debug_view(&mut self, view : DebugView) {
+		self.debug_view = view;
+	}
+
+	/// The counts of the last rendered frame, added up over the strips. Walls
+	/// and sprites that cross strips count once in each.
+	pub fn stats(&self) -> RenderStats {
+		self.stats
+	}
+
+	pub fn render_view(&mut self, level : &bsp::Map, view : &View, mut canvas : Canvas) {
+		let resources = self.resources;
+		let colormap = self.colormap;
+		let node_culling = self.node_culling;
+		let left = canvas.left();
+		let screen_width = canvas.screen_width();
+		let (screen, width, height, pitch) = canvas.get();
+		if width == 0 || height == 0 {
+			self.stats = RenderStats::default();
+			return;
+		}
+		let strips = self.threads.min(width);
+
+		// The renderers of the last frame are gone, so the lists aren't
+		// shared and are updated in place.
+		sort_things(level, Arc::make_mut(&mut self.sector_things));
+
+		// Renderers that panicked took their buffers with them.
+		let mut memory = mem::replace(&mut self.memory, Vec::<RenderMemory>::new());
+		while memory.len() < strips {
+			memory.push(RenderMemory::new());
+		}
+
+		if strips <= 1 || self.visplane_limit.is_some() || self.debug_view != DebugView::None {
+			let canvas = Canvas::strip(screen, left, width, screen_width, height, pitch);
+			let mut renderer = Renderer::with_memory(level, resources, colormap, canvas, memory.remove(0), self.sector_things.clone());
+			renderer.set_node_culling(node_culling);
+			renderer.set_visplane_limit(self.visplane_limit);
+			renderer.set_debug_view(self.debug_view);
+			renderer.render_view(view);
+			self.stats = renderer.stats();
+			memory.insert(0, renderer.into_memory());
+			self.memory = memory;
+			return;
+		}
+
+		// The first column of each strip, and the end of the last one
+		let bounds : Vec<usize> = (0..strips + 1).map(|strip| strip * width / strips).collect();
+		self.buffers.resize(strips, Vec::<u8>::new());
+		for (buffer, ends) in self.buffers.iter_mut().zip(bounds.windows(2)) {
+			buffer.resize((ends[1] - ends[0]) * height, 0);
+		}
+
+		let sector_things = &self.sector_things;
+		let mut renderers : Vec<Renderer> = self.buffers.iter_mut().zip(bounds.windows(2)).zip(memory.drain(..strips)).map(|((buffer, ends), memory)| {
+			let strip_width = ends[1] - ends[0];
+			let canvas = Canvas::strip(buffer, left + ends[0], strip_width, screen_width, height, strip_width);
+			let mut renderer = Renderer::with_memory(level, resources, colormap, canvas, memory, sector_things.clone());
+			renderer.set_node_culling(node_culling);
+			renderer
+		}).collect();
+
+		thread::scope(|scope| {
+			for renderer in renderers.iter_mut() {
+				scope.spawn(move || renderer.render_walls(view));
+			}
+		});
+
+		// A sector seen in any strip is seen by the whole screen.
+		let mut seen = vec![false; level.sectors.len()];
+		for renderer in renderers.iter() {
+			for (seen, &added) in seen.iter_mut().zip(renderer.sector_sprites_added.iter()) {
+				*seen |= added;
+			}
+		}
+
+		thread::scope(|scope| {
+			for renderer in renderers.iter_mut() {
+				let seen = &seen;
+				scope.spawn(move || {
+					for sector in (0..seen.len()).filter(|&sector| seen[sector]) {
+						renderer.add_sprites(sector);
+					}
+					renderer.render_masked();
+				});
+			}
+		});
+
+		let mut stats = RenderStats::default();
+		for renderer in renderers.into_iter() {
+			let strip = renderer.stats();
+			stats.nodes += strip.nodes;
+			stats.subsectors += strip.subsectors;
+			stats.segs += strip.segs;
+			stats.visplanes += strip.visplanes;
+			stats.drawsegs += strip.drawsegs;
+			stats.sprites += strip.sprites;
+			self.memory.push(renderer.into_memory());
+		}
+		self.memory.extend(memory);
+
+		for (buffer, ends) in self.buffers.iter().zip(bounds.windows(2)) {
+			let strip_width = ends[1] - ends[0];
+			for (y, row) in buffer.chunks(strip_width).enumerate() {
+				let start = y * pitch + ends[0];
+				screen[start..start + strip_width].copy_from_slice(row);
+			}
+		}
+		self.stats = stats;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use resources::testwad::*;
+	use super::*;
+
+	#[test]
+	fn strips_draw_the_pixels_of_one_renderer() {
+		let path = temp_path("parallel");
+		let mut lumps = room_map("E1M1", &[(96, 0, 2035), (40, 30, 2028), (150, -50, 2035)]);
+		lumps.push(("PNAMES", pnames(&["WALLPAT"])));
+		lumps.push(("TEXTURE1", texture1(&["WALL"])));
+		lumps.push(("WALLPAT", patch(40)));
+		lumps.push(("FLOOR", flat(20)));
+		lumps.push(("CEIL", flat(60)));
+		lumps.push(("S_START", Vec::<u8>::new()));
+		lumps.push(("BAR1A0", sprite_patch(16, 32, 8, 32, 90)));
+		lumps.push(("COLUA0", sprite_patch(16, 48, 8, 48, 120)));
+		lumps.push(("S_END", Vec::<u8>::new()));
+		write_wad(&path, &lumps);
+
+		let resources = ResourceManager::new();
+		resources.load_package(&path).unwrap();
+		let _ = fs::remove_file(&path);
+		let level = resources.find_map("E1M1").unwrap();
+		let lights = ColorMap::read(&colormap()).unwrap();
+
+		let mut looking_up = View::on_floor(&level, 0, 0, 0x0400);
+		looking_up.pitch = 0x0800;
+		let views = [
+			View::on_floor(&level, 0, 0, 0),
+			View::on_floor(&level, 32 << 16, -16 << 16, 0x2000),
+			View::on_floor(&level, 180 << 16, 40 << 16, 0x9000),
+			looking_up
+		];
+
+		for &(width, height) in [(67, 41), (131, 80), (5, 9)].iter() {
+			let expected : Vec<Vec<u8>> = views.iter().map(|view| {
+				let mut pixels = vec![0u8; width * height];
+				Renderer::new(&level, &resources, &lights, Canvas::new(&mut pixels, width, height, width)).render_view(view);
+				pixels
+			}).collect();
+			if width > 5 {
+				assert!(expected[0].contains(&90) && expected[0].contains(&120), "The sprites aren't in view");
+			}
+
+			for &threads in [2, 3, 7, 16].iter() {
+				// The same renderer draws all the views, reusing its buffers.
+				let mut renderer = ParallelRenderer::new(&resources, &lights, threads);
+				for (index, view) in views.iter().enumerate() {
+					let mut pixels = vec![0u8; width * height];
+					renderer.render_view(&level, view, Canvas::new(&mut pixels, width, height, width));
+					assert!(pixels == expected[index], "{} threads differ at {}x{} in view {}", threads, width, height, index);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn empty_canvases_draw_nothing() {
+		let path = temp_path("parallel-empty");
+		write_wad(&path, &room_map("E1M1", &[]));
+		let resources = ResourceManager::new();
+		resources.load_package(&path).unwrap();
+		let _ = fs::remove_file(&path);
+		let level = resources.find_map("E1M1").unwrap();
+		let lights = ColorMap::read(&colormap()).unwrap();
+		let view = View::on_floor(&level, 0, 0, 0);
+
+		for &threads in [1, 4].iter() {
+			let mut renderer = ParallelRenderer::new(&resources, &lights, threads);
+			for &(width, height) in [(0, 10), (10, 0), (0, 0)].iter() {
+				let mut pixels = vec![0u8; width * height];
+				renderer.render_view(&level, &view, Canvas::new(&mut pixels, width, height, width));
+				assert_eq!(renderer.stats(), RenderStats::default());
+			}
+
+			// The renderer still draws after them.
+			let mut pixels = vec![0u8; 16 * 10];
+			renderer.render_view(&level, &view, Canvas::new(&mut pixels, 16, 10, 16));
+			assert!(renderer.stats().segs > 0);
+		}
+	}
+}
@@ -0,0 +1,326 @@
+use std::mem;
+use std::sync::Arc;
+use resources::Flat;
+use super::DebugView;
+use super::Renderer;
+use super::to_float;
+use super::lighting::light_step;
+use super::lighting::z_light;
+use super::sky::SKY_FLAT;
+
+/// The number of visplanes vanilla has room for. More crash it with
+/// "R_FindPlane: no more visplanes".
+pub const VANILLA_MAX_VISPLANES : usize = 128;
+
+// Marks a column of a visplane that has no rows.
+const UNUSED : i32 = ::std::i32::MAX;
+
+/// A floor or ceiling area of one height, flat and light level, as rows of
+/// pixels in each screen column. Like vanilla, a visplane covers each column
+/// at most once, so the same area seen through two openings may need two.
+pub struct Visplane {
+	height : i32,
+	flat : String,
+	light_level : u32,
+	min_x : i32,
+	max_x : i32,
+	top : Vec<i32>,
+	bottom : Vec<i32>
+}
+
+impl Visplane {
+	fn reset(&mut self, height : i32, flat : &str, light_level : u32, width : i32) {
+		self.height = height;
+		self.flat.clear();
+		self.flat.push_str(flat);
+		self.light_level = light_level;
+		self.min_x = width;
+		self.max_x = -1;
+		self.top.clear();
+		self.top.resize(width as usize, UNUSED);
+		self.bottom.clear();
+		self.bottom.resize(width as usize, -1);
+	}
+
+	/// Adds the rows from `top` to `bottom` of a column to the plane.
+	pub fn mark(&mut self, x : i32, top : i32, bottom : i32) {
+		if top <= bottom {
+			self.top[x as usize] = top;
+			self.bottom[x as usize] = bottom;
+		}
+	}
+
+	// The rows of column x, with the columns next to the plane empty.
+	fn column(&self, x : i32) -> (i32, i32) {
+		if x < self.min_x || x > self.max_x {
+			(UNUSED, -1)
+		} else {
+			(self.top[x as usize], self.bottom[x as usize])
+		}
+	}
+}
+
+impl<'a> Renderer<'a> {
+	/// Limits the visplanes of a frame, like vanilla does. Planes over the
+	/// limit are not drawn, and the overflow is reported in the stats.
+	pub fn set_visplane_limit(&mut self, limit : Option<usize>) {
+		self.visplane_limit = limit;
+	}
+
+	/// Finds the visplane for a floor or ceiling, or starts a new one, like
+	/// R_FindPlane. Returns None if the limit has been reached.
+	pub(super) fn find_plane(&mut self, mut height : i32, flat : &str, mut light_level : u32) -> Option<usize> {
+		// The sky looks the same at any height and light, so all of it can
+		// share planes.
+		if flat == SKY_FLAT {
+			height = 0;
+			light_level = 0;
+		}
+
+		let existing = self.planes[..self.plane_count].iter()
+			.position(|plane| plane.height == height && plane.light_level == light_level && plane.flat == flat);
+		if existing.is_some() {
+			return existing;
+		}
+
+		self.new_plane(height, flat, light_level)
+	}
+
+	/// Makes sure the columns from `start` to `stop` of the plane are free,
+	/// like R_CheckPlane. If some are already used, the plane is split and a
+	/// new one with the same properties is returned.
+	pub(super) fn check_plane(&mut self, index : usize, start : i32, stop : i32) -> Option<usize> {
+		{
+			let plane = &mut self.planes[index];
+			let first = start.max(plane.min_x);
+			let last = stop.min(plane.max_x);
+			if (first..last + 1).all(|x| plane.top[x as usize] == UNUSED) {
+				plane.min_x = plane.min_x.min(start);
+				plane.max_x = plane.max_x.max(stop);
+				return Some(index);
+			}
+		}
+
+		let (height, flat, light_level) = {
+			let plane = &self.planes[index];
+			(plane.height, plane.flat.clone(), plane.light_level)
+		};
+
+		let split = self.new_plane(height, &flat, light_level);
+		if let Some(split) = split {
+			self.planes[split].min_x = start;
+			self.planes[split].max_x = stop;
+		}
+		split
+	}
+
+	fn new_plane(&mut self, height : i32, flat : &str, light_level : u32) -> Option<usize> {
+		if let Some(limit) = self.visplane_limit {
+			if self.plane_count >= limit {
+				self.stats.visplane_overflow = true;
+				return None;
+			}
+		}
+
+		if self.plane_count == self.planes.len() {
+			self.planes.push(Visplane {
+				height: 0,
+				flat: String::new(),
+				light_level: 0,
+				min_x: 0,
+				max_x: 0,
+				top: Vec::<i32>::new(),
+				bottom: Vec::<i32>::new()
+			});
+		}
+
+		let index = self.plane_count;
+		self.planes[index].reset(height, flat, light_level, self.width);
+		self.plane_count += 1;
+		self.stats.visplanes = self.plane_count;
+		Some(index)
+	}
+
+	fn flat(&mut self, name : &str) -> Option<Arc<Flat>> {
+		if let Some(flat) = self.flats.get(name) {
+			return flat.clone();
+		}
+
+		let flat = self.resources.find_flat(name);
+		self.flats.insert(name.to_string(), flat.clone());
+		flat
+	}
+
+	/// Fills the visplanes with horizontal spans, like R_DrawPlanes. Each span
+	/// starts where the plane appears in a row and ends where it disappears.
+	pub(super) fn draw_planes(&mut self) {
+		let planes = mem::replace(&mut self.planes, Vec::new());
+		let mut span_start = mem::replace(&mut self.span_start, Vec::new());
+		span_start.clear();
+		span_start.resize(self.height as usize, 0);
+
+		for (index, plane) in planes[..self.plane_count].iter().enumerate() {
+			if plane.min_x > plane.max_x {
+				continue;
+			}
+
+			if self.debug.view() == DebugView::Visplanes {
+				for x in plane.min_x..plane.max_x + 1 {
+					self.debug.tag_column(x, plane.top[x as usize], plane.bottom[x as usize], index as u32 + 1);
+				}
+			}
+
+			if plane.flat == SKY_FLAT {
+				self.draw_sky(plane.min_x, plane.max_x, &plane.top, &plane.bottom);
+				continue;
+			}
+
+			let flat = match self.flat(&plane.flat) {
+				Some(value) => value,
+				None => continue
+			};
+			let height = to_float(plane.height) - self.view_z;
+			let light = light_step(plane.light_level, self.extra_light);
+
+			for x in plane.min_x..plane.max_x + 2 {
+				let (mut t1, mut b1) = plane.column(x - 1);
+				let (mut t2, mut b2) = plane.column(x);
+
+				// Rows that end in the previous column
+				while t1 < t2 && t1 <= b1 {
+					self.draw_span(&flat, height, light, t1, span_start[t1 as usize], x - 1);
+					t1 += 1;
+				}
+				while b1 > b2 && b1 >= t1 {
+					self.draw_span(&flat, height, light, b1, span_start[b1 as usize], x - 1);
+					b1 -= 1;
+				}
+
+				// Rows that start in this column
+				while t2 < t1 && t2 <= b2 {
+					span_start[t2 as usize] = x;
+					t2 += 1;
+				}
+				while b2 > b1 && b2 >= t2 {
+					span_start[b2 as usize] = x;
+					b2 -= 1;
+				}
+			}
+		}
+
+		self.planes = planes;
+		self.span_start = span_start;
+	}
+
+	/// Draws the columns from `x1` to `x2` of a row of a plane. The plane is
+	/// at `height` relative to the eye, so each row is at a single distance,
+	/// with a single light, and the flat is stepped through linearly.
+	fn draw_span(&mut self, flat : &Flat, height : f32, light : i32, y : i32, x1 : i32, x2 : i32) {
+		let distance = height * self.focal / (self.center_y - y as f32);
+		if distance <= 0.0 || !distance.is_finite() {
+			return;
+		}
+
+		// The map position of the row at column 0, and the step per column.
+		// Each column is found from column 0 rather than from the start of the
+		// span, so a column looks the same whichever span it is drawn in.
+		let across = -self.center_x * distance / self.focal;
+		let origin_x = self.view_x + distance * self.view_cos + across * self.view_sin;
+		let origin_y = self.view_y + distance * self.view_sin - across * self.view_cos;
+		let step_x = self.view_sin * distance / self.focal;
+		let step_y = -self.view_cos * distance / self.focal;
+
+		let shade = self.colormap.map(z_light(light, distance));
+		let left = self.first_column;
+		let (screen, _, _, pitch) = self.canvas.get();
+		for x in x1..x2 + 1 {
+			let map_x = origin_x + step_x * x as f32;
+			let map_y = origin_y + step_y * x as f32;
+
+			// Flats are aligned to the map grid, with y flipped.
+			let index = flat.pixel(map_x.floor() as i32, (-map_y).floor() as i32);
+			screen[y as usize * pitch + (x - left) as usize] = shade[index as usize];
+		}
+		self.debug.count_row(y, x1, x2);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use resources::ColorMap;
+	use resources::ResourceManager;
+	use resources::bsp;
+	use resources::testwad::*;
+	use render::Canvas;
+	use render::View;
+	use super::*;
+
+	fn load_room(file_name : &str) -> (ResourceManager, Arc<bsp::Map>) {
+		let path = temp_path(file_name);
+		write_wad(&path, &room_map("E1M1", &[]));
+		let resources = ResourceManager::new();
+		resources.load_package(&path).unwrap();
+		let _ = fs::remove_file(&path);
+		let level = resources.find_map("E1M1").unwrap();
+		(resources, level)
+	}
+
+	#[test]
+	fn used_columns_split_planes() {
+		let (resources, level) = load_room("plane-split");
+		let lights = ColorMap::read(&colormap()).unwrap();
+		let mut pixels = vec![0u8; 64 * 40];
+		let mut renderer = Renderer::new(&level, &resources, &lights, Canvas::new(&mut pixels, 64, 40, 64));
+		renderer.width = 64;
+
+		let floor = renderer.find_plane(0, "FLOOR", 160).unwrap();
+		assert_eq!(renderer.check_plane(floor, 10, 20), Some(floor));
+		for x in 10..21 {
+			renderer.planes[floor].mark(x, 30, 39);
+		}
+
+		// Free columns on either side grow the plane.
+		assert_eq!(renderer.check_plane(floor, 21, 30), Some(floor));
+		assert_eq!(renderer.check_plane(floor, 0, 9), Some(floor));
+		assert_eq!((renderer.planes[floor].min_x, renderer.planes[floor].max_x), (0, 30));
+
+		// Marked columns need a new plane, which is found for the same
+		// floor from then on.
+		let split = renderer.check_plane(floor, 15, 25).unwrap();
+		assert!(split != floor);
+		assert_eq!((renderer.planes[split].min_x, renderer.planes[split].max_x), (15, 25));
+		assert_eq!((renderer.planes[split].height, &renderer.planes[split].flat[..], renderer.planes[split].light_level), (0, "FLOOR", 160));
+		assert_eq!(renderer.find_plane(0, "FLOOR", 160), Some(floor));
+		assert_eq!(renderer.stats.visplanes, 2);
+		assert!(!renderer.stats.visplane_overflow);
+	}
+
+	#[test]
+	fn planes_over_the_limit_are_dropped() {
+		let (resources, level) = load_room("plane-limit");
+		let lights = ColorMap::read(&colormap()).unwrap();
+		let view = View::on_floor(&level, 0, 0, 0);
+
+		// The room has a floor and a ceiling.
+		for &(limit, overflow) in [(None, false), (Some(2), false), (Some(1), true)].iter() {
+			let mut pixels = vec![0u8; 64 * 40];
+			let mut renderer = Renderer::new(&level, &resources, &lights, Canvas::new(&mut pixels, 64, 40, 64));
+			renderer.set_visplane_limit(limit);
+			renderer.render_view(&view);
+			assert_eq!(renderer.stats().visplanes, if overflow { 1 } else { 2 });
+			assert_eq!(renderer.stats().visplane_overflow, overflow);
+		}
+
+		// A full split fails the same way.
+		let mut pixels = vec![0u8; 64 * 40];
+		let mut renderer = Renderer::new(&level, &resources, &lights, Canvas::new(&mut pixels, 64, 40, 64));
+		renderer.width = 64;
+		renderer.set_visplane_limit(Some(1));
+		let floor = renderer.find_plane(0, "FLOOR", 160).unwrap();
+		renderer.check_plane(floor, 0, 63);
+		renderer.planes[floor].mark(0, 30, 39);
+		assert_eq!(renderer.check_plane(floor, 0, 0), None);
+		assert!(renderer.stats.visplane_overflow);
+	}
+}
@@ -0,0 +1,92 @@
+use super::Renderer;
+use super::lighting::VANILLA_CENTER_X;
+
+/// Ceilings with this flat show the sky instead.
+pub const SKY_FLAT : &'static str = "F_SKY1";
+
+// The row of the sky texture at the middle of the screen
+const SKY_TEXTURE_MID : f32 = 100.0;
+
+// The sky texture turns four times around the view, so a 256 wide texture
+// covers 90 degrees, like ANGLETOSKYSHIFT.
+const ANGLE_TO_SKY_SHIFT : u32 = 22;
+
+/// The sky texture of a map, like G_InitNew and G_DoLoadLevel choose it: one
+/// per episode for ExMy maps, and one per group of maps for MAPxx maps.
+pub fn sky_texture_name(map_name : &str) -> &'static str {
+	let name = map_name.to_ascii_uppercase();
+	let bytes = name.as_bytes();
+
+	if name.starts_with("MAP") {
+		return match name[3..].parse::<u32>() {
+			Ok(map) if map < 12 => "SKY1",
+			Ok(map) if map < 21 => "SKY2",
+			Ok(_) => "SKY3",
+			Err(_) => "SKY1"
+		};
+	}
+
+	if bytes.len() >= 4 && bytes[0] == b'E' && bytes[2] == b'M' {
+		return match bytes[1] {
+			b'2' => "SKY2",
+			b'3' => "SKY3",
+			b'4' => "SKY4",
+			_ => "SKY1"
+		};
+	}
+
+	"SKY1"
+}
+
+impl<'a> Renderer<'a> {
+	/// Fills the columns of a sky plane with the sky texture, like the sky
+	/// case of R_DrawPlanes. The sky is mapped to a cylinder around the view,
+	/// so it only moves when the view turns, and is always full bright.
+	pub(super) fn draw_sky(&mut self, min_x : i32, max_x : i32, top : &[i32], bottom : &[i32]) {
+		let texture = match self.texture(self.sky_texture) {
+			Some(value) => value,
+			None => return
+		};
+
+		// The sky keeps the size it has at 320 pixels wide.
+		let step = VANILLA_CENTER_X / self.focal;
+		let view_angle = self.view_angle;
+		let shade = self.colormap.map(0);
+		let center_x = self.center_x;
+		let center_y = self.center_y;
+		let focal = self.focal;
+		let left = self.first_column;
+		let (screen, _, _, pitch) = self.canvas.get();
+
+		for x in min_x..max_x + 1 {
+			let column_angle = ((center_x - x as f32) / focal).atan() / (::std::f32::consts::PI * 2.0) * 4294967296.0;
+			let angle = view_angle.wrapping_add(column_angle as i64 as u32);
+			let pixels = texture.column((angle >> ANGLE_TO_SKY_SHIFT) as i32);
+
+			for y in top[x as usize]..bottom[x as usize] + 1 {
+				let v = (SKY_TEXTURE_MID + (y as f32 - center_y) * step).floor() as i32;
+				let index = pixels[v.rem_euclid(texture.height) as usize];
+				screen[y as usize * pitch + (x - left) as usize] = shade[index as usize];
+			}
+			self.debug.count_column(x, top[x as usize], bottom[x as usize]);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sky_textures_match_vanilla() {
+		let table = [
+			("MAP01", "SKY1"), ("MAP11", "SKY1"), ("MAP12", "SKY2"), ("MAP20", "SKY2"),
+			("MAP21", "SKY3"), ("MAP32", "SKY3"), ("map12", "SKY2"),
+			("E1M1", "SKY1"), ("E2M5", "SKY2"), ("E3M9", "SKY3"), ("E4M1", "SKY4"), ("e4m1", "SKY4"),
+			("MAPXX", "SKY1"), ("TEST", "SKY1")
+		];
+		for &(map_name, sky) in table.iter() {
+			assert_eq!(sky_texture_name(map_name), sky, "{}", map_name);
+		}
+	}
+}
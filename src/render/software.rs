@@ -0,0 +1,53 @@
+use resources::bsp;
+use resources::ColorMap;
+use resources::ResourceManager;
+use super::Canvas;
+use super::DebugView;
+use super::ParallelRenderer;
+use super::RenderStats;
+use super::View;
+use super::ViewRenderer;
+
+/// The renderer of vanilla: textured walls, flats, sky and sprites, lit
+/// through COLORMAP. The frames are drawn by a `ParallelRenderer` that is
+/// kept from one frame to the next, with its buffers.
+pub struct SoftwareRenderer<'a> {
+	renderer : ParallelRenderer<'a>
+}
+
+impl<'a> SoftwareRenderer<'a> {
+	pub fn new(resources : &'a ResourceManager, colormap : &'a ColorMap) -> SoftwareRenderer<'a> {
+		SoftwareRenderer {
+			renderer: ParallelRenderer::new(resources, colormap, 1)
+		}
+	}
+
+	/// Draws the frames in strips on this many threads. See
+	/// `ParallelRenderer`.
+	pub fn set_threads(&mut self, threads : usize) {
+		self.renderer.set_threads(threads);
+	}
+
+	/// See `Renderer::set_visplane_limit`.
+	pub fn set_visplane_limit(&mut self, limit : Option<usize>) {
+		self.renderer.set_visplane_limit(limit);
+	}
+}
+
+impl<'a> ViewRenderer for SoftwareRenderer<'a> {
+	fn name(&self) -> &'static str {
+		"software"
+	}
+
+	fn render_view(&mut self, level : &bsp::Map, view : &View, target : Canvas) {
+		self.renderer.render_view(level, view, target);
+	}
+
+	fn stats(&self) -> RenderStats {
+		self.renderer.stats()
+	}
+
+	fn set_debug_view(&mut self, view : DebugView) {
+		self.renderer.set_debug_view(view);
+	}
+}
@@ -0,0 +1,542 @@
+use std::sync::Arc;
+use resources::bsp;
+use resources::Texture;
+use super::Renderer;
+use super::masked::DrawSeg;
+use super::masked::MaskedTexture;
+use super::masked::SIL_BOTH;
+use super::masked::SIL_BOTTOM;
+use super::masked::SIL_NONE;
+use super::masked::SIL_TOP;
+use super::NEAR_CLIP;
+use super::to_float;
+use super::lighting::light_step;
+use super::lighting::scale_light;
+use super::sky::SKY_FLAT;
+
+/// A seg projected to the screen. 1/z and u/z change linearly across the
+/// screen, so they are interpolated between the ends.
+#[derive(Debug, Copy, Clone)]
+pub struct WallProjection {
+	sx0 : f32,
+	sx1 : f32,
+	iz0 : f32,
+	iz1 : f32,
+	uz0 : f32,
+	uz1 : f32,
+	x_start : i32,
+	x_end : i32
+}
+
+impl WallProjection {
+	/// The scale, in pixels per map unit, and the texture column at x.
+	pub fn at(&self, x : i32, focal : f32) -> (f32, i32) {
+		let t = (x as f32 - self.sx0) / (self.sx1 - self.sx0);
+		let iz = self.iz0 + (self.iz1 - self.iz0) * t;
+		let u = (self.uz0 + (self.uz1 - self.uz0) * t) / iz;
+		(focal * iz, u.floor() as i32)
+	}
+
+	/// The lowest and the highest scale in the columns of the whole wall.
+	pub fn scale_range(&self, focal : f32) -> (f32, f32) {
+		let scale1 = self.at(self.x_start, focal).0;
+		let scale2 = self.at(self.x_end - 1, focal).0;
+		if scale1 > scale2 { (scale2, scale1) } else { (scale1, scale2) }
+	}
+}
+
+/// A wall texture, and the height of its top edge relative to the eye.
+struct WallTexture {
+	texture : Arc<Texture>,
+	top : f32
+}
+
+impl<'a> Renderer<'a> {
+	pub(super) fn draw_line(&mut self, seg : &bsp::LineSegment) {
+		let level = self.level;
+		let line = &level.lines[seg.line as usize];
+		let front_side = seg.side as usize & 1;
+		let side = &level.sides[line.side[front_side] as usize];
+		let front = &level.sectors[side.sector as usize];
+		let back = match line.side[front_side ^ 1] {
+			bsp::NO_SIDE => None,
+			back_side => Some(&level.sectors[level.sides[back_side as usize].sector as usize])
+		};
+
+		let wall = match self.project(seg, side) {
+			Some(value) => value,
+			None => return
+		};
+
+		// Like R_AddLine: closed doors hide everything behind them, and lines
+		// between two identical sectors have nothing to draw.
+		let solid = match back {
+			None => true,
+			Some(back) => back.ceiling_height <= front.floor_height || back.floor_height >= front.ceiling_height
+		};
+		if let Some(back) = back {
+			if !solid && back.ceiling_height == front.ceiling_height && back.floor_height == front.floor_height
+				&& back.ceiling_texture == front.ceiling_texture && back.floor_texture == front.floor_texture
+				&& back.light_level == front.light_level && side.middle_texture == bsp::NO_TEXTURE {
+				return;
+			}
+		}
+
+		let mut visible = ::std::mem::replace(&mut self.visible, Vec::new());
+		self.solid_segs.clip(wall.x_start, wall.x_end - 1, &mut visible);
+		if visible.is_empty() {
+			self.visible = visible;
+			return;
+		}
+
+		self.stats.segs += 1;
+
+		// Heights relative to the eye
+		let mut ceiling = to_float(front.ceiling_height) - self.view_z;
+		let floor = to_float(front.floor_height) - self.view_z;
+		let y_offset = to_float(side.y_offset);
+
+		let v1 = level.vertices[seg.v[0] as usize];
+		let v2 = level.vertices[seg.v[1] as usize];
+		let contrast = if v1.y == v2.y { -1 } else if v1.x == v2.x { 1 } else { 0 };
+		let light = light_step(front.light_level, contrast + self.extra_light);
+
+		let mut middle = None;
+		let mut upper = None;
+		let mut lower = None;
+		let mut masked = None;
+		let sky_hack = back.map_or(false, |back| front.ceiling_texture == SKY_FLAT && back.ceiling_texture == SKY_FLAT);
+		let mut back_ceiling = ceiling;
+		let mut back_floor = floor;
+
+		match back {
+			None => {
+				// The middle texture hangs from the ceiling, or stands on the
+				// floor for lower unpegged lines.
+				middle = self.texture(&side.middle_texture).map(|texture| {
+					let top = if line.flags & bsp::ML_DONTPEGBOTTOM != 0 { floor + texture.height as f32 } else { ceiling };
+					WallTexture { top: top + y_offset, texture: texture }
+				});
+			}
+			Some(back) => {
+				back_ceiling = to_float(back.ceiling_height) - self.view_z;
+				back_floor = to_float(back.floor_height) - self.view_z;
+
+				// The sky hack: between two sky sectors the upper wall is left
+				// out, and the sky shows down to the lower ceiling.
+				if sky_hack {
+					ceiling = back_ceiling;
+				}
+
+				// The upper texture hangs from the lower ceiling, or from the
+				// higher one for upper unpegged lines.
+				if back_ceiling < ceiling {
+					upper = self.texture(&side.upper_texture).map(|texture| {
+						let top = if line.flags & bsp::ML_DONTPEGTOP != 0 { ceiling } else { back_ceiling + texture.height as f32 };
+						WallTexture { top: top + y_offset, texture: texture }
+					});
+				}
+
+				// The lower texture starts from the higher floor, or is aligned
+				// with the ceiling for lower unpegged lines.
+				if back_floor > floor {
+					lower = self.texture(&side.lower_texture).map(|texture| {
+						let top = if line.flags & bsp::ML_DONTPEGBOTTOM != 0 { ceiling } else { back_floor };
+						WallTexture { top: top + y_offset, texture: texture }
+					});
+				}
+
+				// The middle texture of a two-sided line is see-through, and is
+				// drawn with the sprites once, from the floor or the ceiling of
+				// the opening.
+				masked = self.texture(&side.middle_texture).map(|texture| {
+					let top = if line.flags & bsp::ML_DONTPEGBOTTOM != 0 {
+						floor.max(back_floor) + texture.height as f32
+					} else {
+						ceiling.min(back_ceiling)
+					};
+					(texture, top + y_offset)
+				});
+			}
+		}
+
+		// Like R_StoreWallRange: which sides of the wall hide the sprites
+		// behind it, and from which heights. Closed doors hide everything.
+		let mut silhouette = SIL_NONE;
+		let mut bottom_height = ::std::i32::MIN;
+		let mut top_height = ::std::i32::MAX;
+		let mut closed_bottom = false;
+		let mut closed_top = false;
+		match back {
+			None => {
+				silhouette = SIL_BOTH;
+				bottom_height = ::std::i32::MAX;
+				top_height = ::std::i32::MIN;
+			}
+			Some(back) => {
+				if front.floor_height > back.floor_height {
+					silhouette |= SIL_BOTTOM;
+					bottom_height = front.floor_height;
+				} else if to_float(back.floor_height) > self.view_z {
+					silhouette |= SIL_BOTTOM;
+					bottom_height = ::std::i32::MAX;
+				}
+				if front.ceiling_height < back.ceiling_height {
+					silhouette |= SIL_TOP;
+					top_height = front.ceiling_height;
+				} else if to_float(back.ceiling_height) < self.view_z {
+					silhouette |= SIL_TOP;
+					top_height = ::std::i32::MIN;
+				}
+				if back.ceiling_height <= front.floor_height {
+					silhouette |= SIL_BOTTOM;
+					bottom_height = ::std::i32::MAX;
+					closed_bottom = true;
+				}
+				if back.floor_height >= front.ceiling_height {
+					silhouette |= SIL_TOP;
+					top_height = ::std::i32::MIN;
+					closed_top = true;
+				}
+			}
+		}
+		if masked.is_some() {
+			if silhouette & SIL_BOTTOM == 0 {
+				silhouette |= SIL_BOTTOM;
+				bottom_height = ::std::i32::MAX;
+			}
+			if silhouette & SIL_TOP == 0 {
+				silhouette |= SIL_TOP;
+				top_height = ::std::i32::MIN;
+			}
+		}
+
+		// Like R_StoreWallRange: the floor and ceiling are marked up to the
+		// wall when the plane changes at the line, and are never seen from
+		// the wrong side.
+		let (mut mark_floor, mut mark_ceiling) = match back {
+			None => (true, true),
+			Some(_) if solid => (true, true),
+			Some(back) => (
+				back.floor_height != front.floor_height || back.floor_texture != front.floor_texture || back.light_level != front.light_level,
+				(back.ceiling_height != front.ceiling_height && !sky_hack) || back.ceiling_texture != front.ceiling_texture || back.light_level != front.light_level
+			)
+		};
+		if floor >= 0.0 {
+			mark_floor = false;
+		}
+		if ceiling <= 0.0 && front.ceiling_texture != SKY_FLAT {
+			mark_ceiling = false;
+		}
+
+		let focal = self.focal;
+		let center_y = self.center_y;
+		let colormap = self.colormap;
+		let left = self.first_column;
+		let tag = self.debug.wall_tag(solid);
+
+		for range in visible.iter() {
+			// The planes of the subsector may already be used in these
+			// columns, through another opening.
+			if mark_ceiling {
+				self.ceiling_plane = match self.ceiling_plane {
+					Some(index) => self.check_plane(index, range.first, range.last),
+					None => None
+				};
+			}
+			if mark_floor {
+				self.floor_plane = match self.floor_plane {
+					Some(index) => self.check_plane(index, range.first, range.last),
+					None => None
+				};
+			}
+
+			let (screen, _, _, pitch) = self.canvas.get();
+			for x in range.first..range.last + 1 {
+				let (scale, u) = wall.at(x, focal);
+				let column = x as usize;
+				let shade = colormap.map(scale_light(light, scale, focal));
+				let (old_ceiling_clip, old_floor_clip) = (self.ceiling_clip[column], self.floor_clip[column]);
+
+				// The rows between the clip arrays are still open.
+				let y_top = ((center_y - ceiling * scale).ceil() as i32).max(self.ceiling_clip[column] + 1);
+				let y_bottom = ((center_y - floor * scale).ceil() as i32 - 1).min(self.floor_clip[column] - 1);
+
+				if mark_ceiling {
+					if let Some(index) = self.ceiling_plane {
+						let bottom = (y_top - 1).min(self.floor_clip[column] - 1);
+						self.planes[index].mark(x, self.ceiling_clip[column] + 1, bottom);
+					}
+				}
+
+				if mark_floor {
+					if let Some(index) = self.floor_plane {
+						let top = (y_bottom + 1).max(self.ceiling_clip[column] + 1);
+						self.planes[index].mark(x, top, self.floor_clip[column] - 1);
+					}
+				}
+
+				if back.is_none() {
+					if let Some(ref middle) = middle {
+						draw_column(screen, pitch, shade, middle, x - left, y_top, y_bottom, u, scale, center_y);
+						self.debug.count_column(x, y_top, y_bottom);
+					}
+					self.ceiling_clip[column] = self.height;
+					self.floor_clip[column] = -1;
+					self.debug.tag_column(x, old_ceiling_clip + 1, old_floor_clip - 1, tag);
+					continue;
+				}
+
+				// Upper and lower walls narrow the opening for what is drawn
+				// behind them later.
+				if let Some(ref upper) = upper {
+					let mid = ((center_y - back_ceiling * scale).ceil() as i32 - 1).min(self.floor_clip[column] - 1);
+					if mid >= y_top {
+						draw_column(screen, pitch, shade, upper, x - left, y_top, mid, u, scale, center_y);
+						self.debug.count_column(x, y_top, mid);
+						self.ceiling_clip[column] = mid;
+					} else {
+						self.ceiling_clip[column] = y_top - 1;
+					}
+				} else if mark_ceiling {
+					self.ceiling_clip[column] = y_top - 1;
+				}
+
+				if let Some(ref lower) = lower {
+					let mid = ((center_y - back_floor * scale).ceil() as i32).max(self.ceiling_clip[column] + 1);
+					if mid <= y_bottom {
+						draw_column(screen, pitch, shade, lower, x - left, mid, y_bottom, u, scale, center_y);
+						self.debug.count_column(x, mid, y_bottom);
+						self.floor_clip[column] = mid;
+					} else {
+						self.floor_clip[column] = y_bottom + 1;
+					}
+				} else if mark_floor {
+					self.floor_clip[column] = y_bottom + 1;
+				}
+
+				// The rows the wall closed, down to the ceiling and up from
+				// the floor
+				self.debug.tag_column(x, old_ceiling_clip + 1, self.ceiling_clip[column].min(old_floor_clip - 1), tag);
+				self.debug.tag_column(x, self.floor_clip[column].max(old_ceiling_clip + 1), old_floor_clip - 1, tag);
+			}
+
+			// Save the clip rows left after the wall, which sprites and the
+			// masked texture behind it are drawn between.
+			if silhouette == SIL_NONE {
+				continue;
+			}
+			let columns = range.first as usize..range.last as usize + 1;
+			let top_clip = self.openings.len();
+			if closed_top {
+				let height = self.height;
+				self.openings.extend(columns.clone().map(|_| height));
+			} else {
+				self.openings.extend_from_slice(&self.ceiling_clip[columns.clone()]);
+			}
+			let bottom_clip = self.openings.len();
+			if closed_bottom {
+				self.openings.extend(columns.clone().map(|_| -1));
+			} else {
+				self.openings.extend_from_slice(&self.floor_clip[columns.clone()]);
+			}
+
+			let masked = masked.as_ref().map(|&(ref texture, top)| {
+				let drawn = self.masked_drawn.len();
+				self.masked_drawn.extend(columns.clone().map(|_| false));
+				MaskedTexture { texture: texture.clone(), top: top, light: light, drawn: drawn }
+			});
+
+			self.draw_segs.push(DrawSeg {
+				x1: range.first,
+				x2: range.last,
+				wall: wall,
+				v1: v1,
+				v2: v2,
+				silhouette: silhouette,
+				bottom_height: bottom_height,
+				top_height: top_height,
+				top_clip: top_clip,
+				bottom_clip: bottom_clip,
+				masked: masked
+			});
+			self.stats.drawsegs = self.draw_segs.len();
+		}
+
+		if solid {
+			self.solid_segs.add(wall.x_start, wall.x_end - 1);
+		}
+		self.visible = visible;
+	}
+
+	/// Projects a seg to screen columns, or returns None if it faces away or
+	/// is not in view.
+	fn project(&self, seg : &bsp::LineSegment, side : &bsp::SideDef) -> Option<WallProjection> {
+		let level = self.level;
+		let v0 = level.vertices[seg.v[0] as usize];
+		let v1 = level.vertices[seg.v[1] as usize];
+
+		let tx0 = to_float(v0.x) - self.view_x;
+		let tx1 = to_float(v1.x) - self.view_x;
+		let ty0 = to_float(v0.y) - self.view_y;
+		let ty1 = to_float(v1.y) - self.view_y;
+
+		// Backface culling
+		if ty0 * (tx0 - tx1) + tx0 * (ty1 - ty0) >= 0.0 {
+			return None;
+		}
+
+		// To view space, x to the right and z into the screen.
+		let mut vx0 = tx0 * self.view_sin - ty0 * self.view_cos;
+		let mut vx1 = tx1 * self.view_sin - ty1 * self.view_cos;
+		let mut vz0 = tx0 * self.view_cos + ty0 * self.view_sin;
+		let mut vz1 = tx1 * self.view_cos + ty1 * self.view_sin;
+
+		// Horizontal texture coordinates at the ends of the seg
+		let length = ((tx1 - tx0) * (tx1 - tx0) + (ty1 - ty0) * (ty1 - ty0)).sqrt();
+		let mut u0 = to_float(seg.offset) + to_float(side.x_offset);
+		let mut u1 = u0 + length;
+
+		// Clip against the near plane
+		if vz0 < NEAR_CLIP && vz1 < NEAR_CLIP {
+			return None;
+		}
+		if vz0 < NEAR_CLIP {
+			let t = (NEAR_CLIP - vz0) / (vz1 - vz0);
+			vx0 += (vx1 - vx0) * t;
+			u0 += (u1 - u0) * t;
+			vz0 = NEAR_CLIP;
+		} else if vz1 < NEAR_CLIP {
+			let t = (NEAR_CLIP - vz1) / (vz0 - vz1);
+			vx1 += (vx0 - vx1) * t;
+			u1 += (u0 - u1) * t;
+			vz1 = NEAR_CLIP;
+		}
+
+		// Project to screen columns. A column is covered when its left edge
+		// is inside the wall, so walls sharing a vertex don't overlap.
+		let sx0 = self.center_x + vx0 * self.focal / vz0;
+		let sx1 = self.center_x + vx1 * self.focal / vz1;
+		if sx0 >= sx1 {
+			return None;
+		}
+
+		let x_start = (sx0.ceil() as i32).max(0);
+		let x_end = (sx1.ceil() as i32).min(self.width);
+		if x_start >= x_end {
+			return None;
+		}
+
+		Some(WallProjection {
+			sx0: sx0,
+			sx1: sx1,
+			iz0: 1.0 / vz0,
+			iz1: 1.0 / vz1,
+			uz0: u0 / vz0,
+			uz1: u1 / vz1,
+			x_start: x_start,
+			x_end: x_end
+		})
+	}
+}
+
+/// Draws the rows from `y_start` to `y_end` of a wall column, darkened by
+/// the colormap `shade`. `x` is the column of the canvas.
+fn draw_column(screen : &mut [u8], pitch : usize, shade : &[u8; 256], wall : &WallTexture,
+	x : i32, y_start : i32, y_end : i32, u : i32, scale : f32, center_y : f32) {
+
+	let texture = &wall.texture;
+	let column = texture.column(u);
+	for y in y_start..y_end + 1 {
+		let v = wall.top + (y as f32 - center_y) / scale;
+		let index = column[(v.floor() as i32).rem_euclid(texture.height) as usize];
+		screen[y as usize * pitch + x as usize] = shade[index as usize];
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const FOCAL : f32 = 160.0;
+
+	fn projection(vx0 : f32, vz0 : f32, vx1 : f32, vz1 : f32, length : f32) -> WallProjection {
+		let sx0 = 160.0 + vx0 * FOCAL / vz0;
+		let sx1 = 160.0 + vx1 * FOCAL / vz1;
+		WallProjection {
+			sx0: sx0,
+			sx1: sx1,
+			iz0: 1.0 / vz0,
+			iz1: 1.0 / vz1,
+			uz0: 0.0,
+			uz1: length / vz1,
+			x_start: sx0.ceil() as i32,
+			x_end: sx1.ceil() as i32
+		}
+	}
+
+	// Two columns of three rows, 1 2 3 and 4 5 6.
+	fn wall_texture(top : f32) -> WallTexture {
+		WallTexture {
+			texture: Arc::new(Texture {
+				name: "WALL".to_string(),
+				width: 2,
+				height: 3,
+				pixels: vec![1, 2, 3, 4, 5, 6],
+				opaque: vec![true; 6]
+			}),
+			top: top
+		}
+	}
+
+	fn column(wall : &WallTexture, u : i32, y_start : i32, y_end : i32) -> Vec<u8> {
+		let mut shade = [0u8; 256];
+		for (i, value) in shade.iter_mut().enumerate() {
+			*value = i as u8;
+		}
+		let mut screen = vec![0u8; 3 * 32];
+		draw_column(&mut screen, 3, &shade, wall, 1, y_start, y_end, u, 1.0, 20.0);
+
+		// Only the column x = 1 is drawn.
+		for (i, &value) in screen.iter().enumerate() {
+			assert!(i % 3 == 1 || value == 0);
+		}
+		screen.iter().skip(1).step_by(3).cloned().collect()
+	}
+
+	#[test]
+	fn wall_columns_are_perspective_correct() {
+		// Facing the wall, across the whole screen.
+		let wall = projection(-64.0, 64.0, 64.0, 64.0, 128.0);
+		assert_eq!(wall.at(0, FOCAL), (2.5, 0));
+		assert_eq!(wall.at(160, FOCAL), (2.5, 64));
+		assert_eq!(wall.at(319, FOCAL), (2.5, 127));
+
+		// At an angle the middle of the wall is right of the middle of the
+		// screen, where it is 80 units away.
+		let length = (160.0f32 * 160.0 + 96.0 * 96.0).sqrt();
+		let wall = projection(-32.0, 32.0, 128.0, 128.0, length);
+		let (scale, u) = wall.at(256, FOCAL);
+		assert!((scale - 2.0).abs() < 1e-4);
+		assert_eq!(u, (length / 2.0).floor() as i32);
+	}
+
+	#[test]
+	fn pegging_sets_the_first_texture_row() {
+		// The ceiling is 16 above the eye and the floor 9 below, so the wall
+		// covers the rows 4 to 28 at a scale of 1.
+		let (ceiling, floor) = (16.0, -9.0);
+
+		// Pegged to the ceiling, the texture starts at the top of the wall.
+		let pegged = column(&wall_texture(ceiling), 0, 4, 28);
+		assert_eq!(&pegged[4..8], &[1, 2, 3, 1]);
+		assert_eq!(&pegged[26..29], &[2, 3, 1]);
+
+		// Lower unpegged, it ends at the bottom of the wall.
+		let unpegged = column(&wall_texture(floor + 3.0), 1, 4, 28);
+		assert_eq!(&unpegged[4..8], &[6, 4, 5, 6]);
+		assert_eq!(&unpegged[26..29], &[4, 5, 6]);
+
+		assert!(pegged[..4].iter().chain(pegged[29..].iter()).all(|&value| value == 0));
+	}
+}
@@ -0,0 +1,230 @@
+use resources::bsp;
+use super::Canvas;
+use super::RenderStats;
+use super::View;
+use super::ViewRenderer;
+use super::NEAR_CLIP;
+use super::to_float;
+
+// Palette indices of the automap colors, the REDS, BROWNS, YELLOWS and
+// GRAYS of am_map.c
+const WALL_COLOR : u8 = 176;
+const FLOOR_STEP_COLOR : u8 = 64;
+const CEILING_STEP_COLOR : u8 = 231;
+const TWO_SIDED_COLOR : u8 = 96;
+
+/// Draws the edges of the walls as lines in the colors of the automap:
+/// one-sided walls red, floor steps brown, ceiling steps yellow and the
+/// lines between sectors of the same heights gray. Nothing is hidden, so
+/// the whole map shows through the walls, which helps to find what a wall
+/// is in front of.
+pub struct WireframeRenderer {
+	stats : RenderStats
+}
+
+impl WireframeRenderer {
+	pub fn new() -> WireframeRenderer {
+		WireframeRenderer {
+			stats: RenderStats::default()
+		}
+	}
+}
+
+impl ViewRenderer for WireframeRenderer {
+	fn name(&self) -> &'static str {
+		"wireframe"
+	}
+
+	fn render_view(&mut self, level : &bsp::Map, view : &View, mut target : Canvas) {
+		target.clear(0);
+		self.stats = RenderStats::default();
+
+		let angle = (view.angle as f32) / (0x10000 as f32) * ::std::f32::consts::PI * 2.0;
+		let width = target.screen_width() as i32;
+		let focal = width as f32 / 2.0;
+		let projection = Projection {
+			view_x: to_float(view.x),
+			view_y: to_float(view.y),
+			view_z: to_float(view.z),
+			view_cos: angle.cos(),
+			view_sin: angle.sin(),
+			center_x: focal,
+			center_y: view.horizon(target.height() as i32, focal),
+			focal: focal,
+			left: target.left() as i32,
+			right: (target.left() + target.width()) as i32 - 1,
+			bottom: target.height() as i32 - 1
+		};
+
+		for line in level.lines.iter() {
+			let front = match level.sides.get(line.side[0] as usize).and_then(|side| level.sectors.get(side.sector as usize)) {
+				Some(value) => value,
+				None => continue
+			};
+			let back = level.sides.get(line.side[1] as usize).and_then(|side| level.sectors.get(side.sector as usize));
+
+			let v1 = level.vertices[line.v[0] as usize];
+			let v2 = level.vertices[line.v[1] as usize];
+			let (x1, y1, x2, y2) = (to_float(v1.x), to_float(v1.y), to_float(v2.x), to_float(v2.y));
+			let front_floor = to_float(front.floor_height);
+			let front_ceiling = to_float(front.ceiling_height);
+
+			// Each wall is drawn as its outline, between the heights where
+			// the sectors differ.
+			let mut walls = Vec::<(f32, f32, u8)>::new();
+			match back {
+				None => walls.push((front_floor, front_ceiling, WALL_COLOR)),
+				Some(back) => {
+					let back_floor = to_float(back.floor_height);
+					let back_ceiling = to_float(back.ceiling_height);
+					if back_floor != front_floor {
+						walls.push((front_floor.min(back_floor), front_floor.max(back_floor), FLOOR_STEP_COLOR));
+					}
+					if back_ceiling != front_ceiling {
+						walls.push((front_ceiling.min(back_ceiling), front_ceiling.max(back_ceiling), CEILING_STEP_COLOR));
+					}
+					if walls.is_empty() {
+						walls.push((front_floor, front_floor, TWO_SIDED_COLOR));
+					}
+				}
+			}
+
+			let mut drawn = false;
+			{
+				let (pixels, _, _, pitch) = target.get();
+				for &(bottom, top, color) in walls.iter() {
+					drawn |= projection.draw_edge(pixels, pitch, (x1, y1, bottom), (x2, y2, bottom), color);
+					if top != bottom {
+						drawn |= projection.draw_edge(pixels, pitch, (x1, y1, top), (x2, y2, top), color);
+						drawn |= projection.draw_edge(pixels, pitch, (x1, y1, bottom), (x1, y1, top), color);
+						drawn |= projection.draw_edge(pixels, pitch, (x2, y2, bottom), (x2, y2, top), color);
+					}
+				}
+			}
+			if drawn {
+				self.stats.segs += 1;
+			}
+		}
+	}
+
+	fn stats(&self) -> RenderStats {
+		self.stats
+	}
+}
+
+// The view, and the part of the screen in the canvas
+struct Projection {
+	view_x : f32,
+	view_y : f32,
+	view_z : f32,
+	view_cos : f32,
+	view_sin : f32,
+	center_x : f32,
+	center_y : f32,
+	focal : f32,
+	left : i32,
+	right : i32,
+	bottom : i32
+}
+
+impl Projection {
+	/// Draws the line between two map points, clipped to the near plane and
+	/// the canvas. Returns true if some of it was drawn.
+	fn draw_edge(&self, pixels : &mut [u8], pitch : usize, from : (f32, f32, f32), to : (f32, f32, f32), color : u8) -> bool {
+		// To view space, x to the right, y up and z into the screen.
+		let (mut x0, mut y0, mut z0) = self.to_view(from);
+		let (mut x1, mut y1, mut z1) = self.to_view(to);
+
+		if z0 < NEAR_CLIP && z1 < NEAR_CLIP {
+			return false;
+		}
+		if z0 < NEAR_CLIP {
+			let t = (NEAR_CLIP - z0) / (z1 - z0);
+			x0 += (x1 - x0) * t;
+			y0 += (y1 - y0) * t;
+			z0 = NEAR_CLIP;
+		} else if z1 < NEAR_CLIP {
+			let t = (NEAR_CLIP - z1) / (z0 - z1);
+			x1 += (x0 - x1) * t;
+			y1 += (y0 - y1) * t;
+			z1 = NEAR_CLIP;
+		}
+
+		let start = (self.center_x + x0 * self.focal / z0, self.center_y - y0 * self.focal / z0);
+		let end = (self.center_x + x1 * self.focal / z1, self.center_y - y1 * self.focal / z1);
+		let (start, end) = match self.clip(start, end) {
+			Some(value) => value,
+			None => return false
+		};
+
+		// Bresenham's algorithm, like MapImage::draw_line
+		let (x1, y1) = (end.0.round() as i32, end.1.round() as i32);
+		let (mut x, mut y) = (start.0.round() as i32, start.1.round() as i32);
+		let dx = (x1 - x).abs();
+		let dy = -(y1 - y).abs();
+		let sx = if x < x1 { 1 } else { -1 };
+		let sy = if y < y1 { 1 } else { -1 };
+		let mut err = dx + dy;
+
+		loop {
+			if x >= self.left && x <= self.right && y >= 0 && y <= self.bottom {
+				pixels[y as usize * pitch + (x - self.left) as usize] = color;
+			}
+			if x == x1 && y == y1 {
+				break;
+			}
+			let e2 = err * 2;
+			if e2 >= dy {
+				err += dy;
+				x += sx;
+			}
+			if e2 <= dx {
+				err += dx;
+				y += sy;
+			}
+		}
+		true
+	}
+
+	fn to_view(&self, point : (f32, f32, f32)) -> (f32, f32, f32) {
+		let tx = point.0 - self.view_x;
+		let ty = point.1 - self.view_y;
+		(tx * self.view_sin - ty * self.view_cos, point.2 - self.view_z, tx * self.view_cos + ty * self.view_sin)
+	}
+
+	// Clips a line to the canvas, like Liang-Barsky. Returns None if none of
+	// it is inside.
+	fn clip(&self, start : (f32, f32), end : (f32, f32)) -> Option<((f32, f32), (f32, f32))> {
+		let dx = end.0 - start.0;
+		let dy = end.1 - start.1;
+		let edges = [
+			(-dx, start.0 - self.left as f32),
+			(dx, self.right as f32 - start.0),
+			(-dy, start.1),
+			(dy, self.bottom as f32 - start.1)
+		];
+
+		let mut t0 = 0.0f32;
+		let mut t1 = 1.0f32;
+		for &(p, q) in edges.iter() {
+			if p == 0.0 {
+				if q < 0.0 {
+					return None;
+				}
+				continue;
+			}
+
+			let t = q / p;
+			if p < 0.0 {
+				t0 = t0.max(t);
+			} else {
+				t1 = t1.min(t);
+			}
+		}
+
+		if t0 > t1 {
+			return None;
+		}
+		Some(((start.0 + dx * t0, start.1 + dy * t0), (start.0 + dx * t1, start.1 + dy * t1)))
+	}
+}
@@ -1,9 +1,42 @@
+// Line flags
+pub const ML_BLOCKING : u32 = 0x0001;
+pub const ML_BLOCKMONSTERS : u32 = 0x0002;
+pub const ML_TWOSIDED : u32 = 0x0004;
+pub const ML_DONTPEGTOP : u32 = 0x0008;
+pub const ML_DONTPEGBOTTOM : u32 = 0x0010;
+pub const ML_SECRET : u32 = 0x0020;
+pub const ML_SOUNDBLOCK : u32 = 0x0040;
+pub const ML_DONTDRAW : u32 = 0x0080;
+pub const ML_MAPPED : u32 = 0x0100;
+
+// Thing flags
+pub const MTF_EASY : u32 = 0x0001;
+pub const MTF_NORMAL : u32 = 0x0002;
+pub const MTF_HARD : u32 = 0x0004;
+pub const MTF_AMBUSH : u32 = 0x0008;
+pub const MTF_NOTSINGLE : u32 = 0x0010;
+
+/// Marks a node child that is a subsector.
+pub const NF_SUBSECTOR : u32 = 0x80000000;
+
+/// Side index of a line that has no side.
+pub const NO_SIDE : u32 = 0xFFFF;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Vertex {
 	pub x : i32,
 	pub y : i32
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Thing {
+	pub x : i32,
+	pub y : i32,
+	pub angle : u16,
+	pub thing_type : u32,
+	pub flags : u32
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct LineDef {
 	pub v : [u32; 2],
@@ -13,17 +46,28 @@ pub struct LineDef {
 	pub side : [u32; 2]
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+/// Texture name used for sides that show no texture.
+pub const NO_TEXTURE : &'static str = "-";
+
+/// Ceiling flat that is drawn as the sky.
+pub const SKY_FLAT : &'static str = "F_SKY1";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct SideDef {
 	pub x_offset : i32,
 	pub y_offset : i32,
+	pub upper_texture : String,
+	pub lower_texture : String,
+	pub middle_texture : String,
 	pub sector : u32
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Sector {
 	pub floor_height : i32,
 	pub ceiling_height : i32,
+	pub floor_texture : String,
+	pub ceiling_texture : String,
 	pub light_level : u32,
 	pub sector_type : u32,
 	pub tag : u32
@@ -62,11 +106,33 @@ pub struct Node {
 	pub child : [u32; 2],
 }
 
+impl Node {
+	/// Which side of the partition line a point is on, like R_PointOnSide:
+	/// 0 for the front (right) side and 1 for the back.
+	pub fn point_side(&self, x : i32, y : i32) -> usize {
+		if self.dx == 0 {
+			return if x <= self.x { (self.dy > 0) as usize } else { (self.dy < 0) as usize };
+		}
+
+		if self.dy == 0 {
+			return if y <= self.y { (self.dx < 0) as usize } else { (self.dx > 0) as usize };
+		}
+
+		let dx = x as i64 - self.x as i64;
+		let dy = y as i64 - self.y as i64;
+
+		let left = ((self.dy >> 16) as i64 * dx) >> 16;
+		let right = (dy * (self.dx >> 16) as i64) >> 16;
+		(right >= left) as usize
+	}
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Map {
 	pub name : String,
 
 	// Original level data
+	pub things : Vec<Thing>,
 	pub lines : Vec<LineDef>,
 	pub sides : Vec<SideDef>,
 	pub sectors : Vec<Sector>,
@@ -80,3 +146,38 @@ pub struct Map {
 	pub vertices : Vec<Vertex>
 }
 
+impl Map {
+	/// Index of the subsector that contains the point.
+	pub fn subsector_at(&self, x : i32, y : i32) -> usize {
+		// A map with a single subsector has no nodes.
+		if self.nodes.is_empty() {
+			return 0;
+		}
+
+		let mut node = self.nodes.len() as u32 - 1;
+		while node & NF_SUBSECTOR == 0 {
+			let current = &self.nodes[node as usize];
+			node = current.child[current.point_side(x, y)];
+		}
+		(node & !NF_SUBSECTOR) as usize
+	}
+
+	/// The sector a seg faces.
+	pub fn seg_sector(&self, seg : &LineSegment) -> Option<&Sector> {
+		let line = match self.lines.get(seg.line as usize) {
+			Some(value) => value,
+			None => return None
+		};
+		let side = line.side[(seg.side & 1) as usize];
+		self.sides.get(side as usize).and_then(|side| self.sectors.get(side.sector as usize))
+	}
+
+	/// The sector that contains the point.
+	pub fn sector_at(&self, x : i32, y : i32) -> Option<&Sector> {
+		let subsector = match self.subsectors.get(self.subsector_at(x, y)) {
+			Some(value) => value,
+			None => return None
+		};
+		self.segs.get(subsector.first_seg as usize).and_then(|seg| self.seg_sector(seg))
+	}
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+use resources::bsp;
+use resources::Patch;
+use resources::Post;
+use resources::Texture;
+use resources::TextureDirectory;
+use resources::Flat;
+use resources::SpriteFrame;
+use resources::SpriteRotation;
+use audio::Sound;
+
+/// Memory budget of the resource cache if none is given, in bytes.
+pub const DEFAULT_CACHE_BUDGET : usize = 64 * 1024 * 1024;
+
+/// Identifies a cached resource. Names are stored in upper case.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum ResourceKey {
+	Lump(String),
+	Map(String),
+	Patch(String),
+	Texture(String),
+	Flat(String),
+	SpriteFrame(String),
+	Sound(String),
+	TextureDirectory
+}
+
+#[derive(Clone)]
+pub enum CachedResource {
+	Lump(Arc<Vec<u8>>),
+	Map(Arc<bsp::Map>),
+	Patch(Arc<Patch>),
+	Texture(Arc<Texture>),
+	Flat(Arc<Flat>),
+	SpriteFrame(Arc<SpriteFrame>),
+	Sound(Arc<Sound>),
+	TextureDirectory(Arc<TextureDirectory>),
+	/// Remembers that no package has the resource, so it isn't looked for
+	/// again on every frame.
+	Missing
+}
+
+impl CachedResource {
+	/// Whether something outside of the cache holds on to the resource.
+	/// Evicting it would not free any memory.
+	fn in_use(&self) -> bool {
+		match *self {
+			CachedResource::Lump(ref value) => Arc::strong_count(value) > 1,
+			CachedResource::Map(ref value) => Arc::strong_count(value) > 1,
+			CachedResource::Patch(ref value) => Arc::strong_count(value) > 1,
+			CachedResource::Texture(ref value) => Arc::strong_count(value) > 1,
+			CachedResource::Flat(ref value) => Arc::strong_count(value) > 1,
+			CachedResource::SpriteFrame(ref value) => Arc::strong_count(value) > 1,
+			CachedResource::Sound(ref value) => Arc::strong_count(value) > 1,
+			CachedResource::TextureDirectory(ref value) => Arc::strong_count(value) > 1,
+			CachedResource::Missing => false
+		}
+	}
+}
+
+struct CacheEntry {
+	resource : CachedResource,
+	size : usize,
+	last_used : u64
+}
+
+/// Statistics of the cache, for debugging and tuning the budget.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct CacheStats {
+	pub entries : usize,
+	pub used : usize,
+	pub budget : usize,
+	pub hits : u64,
+	pub misses : u64,
+	pub evictions : u64
+}
+
+/// Keeps decoded resources around until the memory budget runs out. The
+/// least recently used resources that nothing else refers to are evicted
+/// first. Resources that are still in use stay cached even over the budget,
+/// since evicting them would only load them twice.
+pub struct ResourceCache {
+	entries : HashMap<ResourceKey, CacheEntry>,
+	// Counts the clears, so resources read before a clear are not inserted
+	// after it
+	generation : u64,
+	used : usize,
+	budget : usize,
+	clock : u64,
+	hits : u64,
+	misses : u64,
+	evictions : u64
+}
+
+impl ResourceCache {
+	pub fn new(budget : usize) -> ResourceCache {
+		ResourceCache {
+			entries: HashMap::<ResourceKey, CacheEntry>::new(),
+			generation: 0,
+			used: 0,
+			budget: budget,
+			clock: 0,
+			hits: 0,
+			misses: 0,
+			evictions: 0
+		}
+	}
+
+	pub fn get(&mut self, key : &ResourceKey) -> Option<CachedResource> {
+		self.clock += 1;
+		match self.entries.get_mut(key) {
+			Some(entry) => {
+				entry.last_used = self.clock;
+				self.hits += 1;
+				Some(entry.resource.clone())
+			}
+			None => {
+				self.misses += 1;
+				None
+			}
+		}
+	}
+
+	/// The number of times the cache was cleared. Take it before reading a
+	/// resource, and insert the resource with it.
+	pub fn generation(&self) -> u64 {
+		self.generation
+	}
+
+	/// Inserts a resource, unless the cache was cleared since `generation`.
+	/// The resource may then come from packages that were replaced.
+	pub fn insert(&mut self, key : ResourceKey, resource : CachedResource, generation : u64) {
+		if generation != self.generation {
+			return;
+		}
+
+		self.clock += 1;
+		let size = resource_size(&resource);
+		if let Some(old) = self.entries.insert(key, CacheEntry {
+			resource: resource,
+			size: size,
+			last_used: self.clock
+		}) {
+			self.used -= old.size;
+		}
+		self.used += size;
+		self.evict();
+	}
+
+	pub fn set_budget(&mut self, budget : usize) {
+		self.budget = budget;
+		self.evict();
+	}
+
+	pub fn clear(&mut self) {
+		self.entries.clear();
+		self.used = 0;
+		self.generation += 1;
+	}
+
+	pub fn stats(&self) -> CacheStats {
+		CacheStats {
+			entries: self.entries.len(),
+			used: self.used,
+			budget: self.budget,
+			hits: self.hits,
+			misses: self.misses,
+			evictions: self.evictions
+		}
+	}
+
+	fn evict(&mut self) {
+		if self.used <= self.budget {
+			return;
+		}
+
+		let mut candidates : Vec<(u64, ResourceKey)> = self.entries.iter()
+			.filter(|&(_, entry)| !entry.resource.in_use())
+			.map(|(key, entry)| (entry.last_used, key.clone()))
+			.collect();
+		candidates.sort_by(|a, b| a.0.cmp(&b.0));
+
+		for (_, key) in candidates {
+			if self.used <= self.budget {
+				break;
+			}
+			if let Some(entry) = self.entries.remove(&key) {
+				self.used -= entry.size;
+				self.evictions += 1;
+			}
+		}
+	}
+}
+
+/// Approximate memory used by a resource.
+fn resource_size(resource : &CachedResource) -> usize {
+	match *resource {
+		CachedResource::Lump(ref data) => data.len(),
+		CachedResource::Map(ref map) => {
+			map.things.len() * mem::size_of::<bsp::Thing>() +
+			map.lines.len() * mem::size_of::<bsp::LineDef>() +
+			map.sides.len() * mem::size_of::<bsp::SideDef>() +
+			map.sectors.len() * mem::size_of::<bsp::Sector>() +
+			map.subsectors.len() * mem::size_of::<bsp::Subsector>() +
+			map.segs.len() * mem::size_of::<bsp::LineSegment>() +
+			map.nodes.len() * mem::size_of::<bsp::Node>() +
+			map.vertices.len() * mem::size_of::<bsp::Vertex>()
+		}
+		CachedResource::Patch(ref patch) => {
+			patch.columns.iter()
+				.map(|posts| posts.iter().map(|post| post.pixels.len() + mem::size_of::<Post>()).sum::<usize>())
+				.sum()
+		}
+		CachedResource::Texture(ref texture) => texture.pixels.len() + texture.opaque.len(),
+		CachedResource::Flat(ref flat) => flat.pixels.len(),
+		// The patches are cached on their own.
+		CachedResource::SpriteFrame(ref frame) => frame.rotations.len() * mem::size_of::<Option<SpriteRotation>>(),
+		CachedResource::Sound(ref sound) => sound.samples.len(),
+		CachedResource::TextureDirectory(ref directory) => directory.size(),
+		CachedResource::Missing => mem::size_of::<CacheEntry>()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn lump(size : usize) -> CachedResource {
+		CachedResource::Lump(Arc::new(vec![0u8; size]))
+	}
+
+	#[test]
+	fn resources_read_before_a_clear_are_not_inserted() {
+		let mut cache = ResourceCache::new(DEFAULT_CACHE_BUDGET);
+		let key = ResourceKey::Lump("PLAYPAL".to_string());
+		let generation = cache.generation();
+		cache.clear();
+		cache.insert(key.clone(), lump(16), generation);
+		assert!(cache.get(&key).is_none());
+
+		cache.insert(key.clone(), lump(16), cache.generation());
+		assert!(cache.get(&key).is_some());
+	}
+
+	#[test]
+	fn unused_resources_are_evicted_first() {
+		let mut cache = ResourceCache::new(100);
+		let generation = cache.generation();
+		let used = lump(60);
+		cache.insert(ResourceKey::Lump("USED".to_string()), used.clone(), generation);
+		cache.insert(ResourceKey::Lump("OLD".to_string()), lump(30), generation);
+		cache.insert(ResourceKey::Lump("NEW".to_string()), lump(30), generation);
+
+		assert!(cache.get(&ResourceKey::Lump("OLD".to_string())).is_none());
+		assert!(cache.get(&ResourceKey::Lump("USED".to_string())).is_some());
+		assert!(cache.get(&ResourceKey::Lump("NEW".to_string())).is_some());
+		assert_eq!(cache.stats().evictions, 1);
+	}
+}
@@ -0,0 +1,39 @@
+use resources::WadResult;
+use resources::WadError;
+
+/// The number of light levels in COLORMAP, from full bright to black.
+pub const NUM_COLORMAPS : usize = 32;
+
+/// The COLORMAP lump: tables that map each palette index to a darker one,
+/// one table per light level. The tables after the light levels are for
+/// special effects, like the inverted grays of invulnerability.
+#[derive(Clone)]
+pub struct ColorMap {
+	pub maps : Vec<[u8; 256]>
+}
+
+impl ColorMap {
+	pub fn read(data : &[u8]) -> WadResult<ColorMap> {
+		if data.len() < NUM_COLORMAPS * 256 {
+			return Err(WadError::InvalidLump("COLORMAP"));
+		}
+
+		let maps = data.chunks(256)
+			.filter(|chunk| chunk.len() == 256)
+			.map(|chunk| {
+				let mut map = [0u8; 256];
+				map.copy_from_slice(chunk);
+				map
+			})
+			.collect();
+
+		Ok(ColorMap {
+			maps: maps
+		})
+	}
+
+	/// The table of light level `index`, 0 for full bright.
+	pub fn map(&self, index : usize) -> &[u8; 256] {
+		&self.maps[index]
+	}
+}
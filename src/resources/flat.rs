@@ -0,0 +1,32 @@
+use resources::WadResult;
+use resources::WadError;
+
+/// Flats are always 64 by 64 pixels.
+pub const FLAT_SIZE : i32 = 64;
+
+/// A floor or ceiling texture. The pixels are stored row by row, as palette
+/// indices.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Flat {
+	pub name : String,
+	pub pixels : Vec<u8>
+}
+
+impl Flat {
+	pub fn new(name : &str, data : &[u8]) -> WadResult<Flat> {
+		let size = (FLAT_SIZE * FLAT_SIZE) as usize;
+		if data.len() < size {
+			return Err(WadError::InvalidLump("flat"));
+		}
+
+		Ok(Flat {
+			name: name.to_ascii_uppercase(),
+			pixels: data[..size].to_vec()
+		})
+	}
+
+	/// The pixel at (x, y). The flat repeats in both directions.
+	pub fn pixel(&self, x : i32, y : i32) -> u8 {
+		self.pixels[((y & (FLAT_SIZE - 1)) * FLAT_SIZE + (x & (FLAT_SIZE - 1))) as usize]
+	}
+}
@@ -0,0 +1,131 @@
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::TryRecvError;
+use std::thread;
+use resources::PackageLoadResult;
+use resources::PackageLoadError;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A thread that runs load jobs one at a time, in the order they were
+/// queued. A job that panics fails its load, and the thread goes on with the
+/// next one. The thread exits when the worker is dropped.
+pub struct LoadWorker {
+	sender : Mutex<Sender<Job>>
+}
+
+impl LoadWorker {
+	pub fn new() -> LoadWorker {
+		let (sender, receiver) = mpsc::channel::<Job>();
+		thread::Builder::new()
+			.name("resource loader".to_string())
+			.spawn(move || {
+				for job in receiver.iter() {
+					job();
+				}
+			})
+			.expect("Cannot start the resource loader thread");
+
+		LoadWorker {
+			sender: Mutex::new(sender)
+		}
+	}
+
+	/// Queues a job and returns a handle for its result.
+	pub fn run<T, F>(&self, job : F) -> LoadHandle<T>
+		where T : Send + 'static, F : FnOnce() -> PackageLoadResult<T> + Send + 'static {
+
+		let (sender, receiver) = mpsc::channel::<PackageLoadResult<T>>();
+		let job : Job = Box::new(move || {
+			// The resource manager uses poisoned locks as they are, so it is
+			// still usable after a panic.
+			let result = match panic::catch_unwind(AssertUnwindSafe(job)) {
+				Ok(result) => result,
+				Err(_) => Err(PackageLoadError::LoaderFailed)
+			};
+			// Nobody may be waiting for the result anymore.
+			let _ = sender.send(result);
+		});
+
+		let sender = match self.sender.lock() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner()
+		};
+		let _ = sender.send(job);
+
+		LoadHandle {
+			receiver: receiver,
+			result: None,
+			taken: false
+		}
+	}
+}
+
+/// The result of a load running on the loader thread. Poll it from the main
+/// loop, or wait for it.
+pub struct LoadHandle<T> {
+	receiver : Receiver<PackageLoadResult<T>>,
+	result : Option<PackageLoadResult<T>>,
+	taken : bool
+}
+
+impl<T> LoadHandle<T> {
+	/// Returns true when the load has finished, successfully or not.
+	pub fn is_done(&mut self) -> bool {
+		if self.taken {
+			return true;
+		}
+		if self.result.is_none() {
+			self.result = match self.receiver.try_recv() {
+				Ok(result) => Some(result),
+				Err(TryRecvError::Empty) => None,
+				Err(TryRecvError::Disconnected) => Some(Err(PackageLoadError::LoaderFailed))
+			};
+		}
+		self.result.is_some()
+	}
+
+	/// Takes the result if the load has finished. The result is only
+	/// returned once.
+	pub fn poll(&mut self) -> Option<PackageLoadResult<T>> {
+		if self.is_done() && !self.taken {
+			self.taken = true;
+			self.result.take()
+		} else {
+			None
+		}
+	}
+
+	/// Blocks until the load has finished.
+	pub fn wait(mut self) -> PackageLoadResult<T> {
+		match self.result.take() {
+			Some(result) => result,
+			None => match self.receiver.recv() {
+				Ok(result) => result,
+				Err(_) => Err(PackageLoadError::LoaderFailed)
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn worker_survives_a_panicking_job() {
+		let worker = LoadWorker::new();
+		let failed = worker.run::<u32, _>(|| panic!("broken package"));
+		let next = worker.run(|| Ok(7));
+
+		match failed.wait() {
+			Err(PackageLoadError::LoaderFailed) => (),
+			other => panic!("unexpected result {:?}", other)
+		}
+		assert_eq!(next.wait().unwrap(), 7);
+	}
+}
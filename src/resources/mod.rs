@@ -1,6 +1,27 @@
 pub use self::wadpackage::*;
 pub use self::resourcemanager::*;
+pub use self::patch::*;
+pub use self::palette::*;
+pub use self::colormap::*;
+pub use self::texture::*;
+pub use self::flat::*;
+pub use self::sprite::*;
+pub use self::things::*;
+pub use self::cache::CacheStats;
+pub use self::cache::DEFAULT_CACHE_BUDGET;
+pub use self::loader::LoadHandle;
 
 pub mod bsp;
 mod wadpackage;
 mod resourcemanager;
+mod patch;
+mod palette;
+mod colormap;
+mod texture;
+mod flat;
+mod sprite;
+mod things;
+mod cache;
+mod loader;
+#[cfg(test)]
+pub mod testwad;
@@ -0,0 +1,94 @@
+use resources::WadResult;
+use resources::WadError;
+
+/// A vertical run of opaque pixels in a patch column.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Post {
+	pub top : i32,
+	pub pixels : Vec<u8>
+}
+
+/// A picture in the Doom patch format, used for wall patches, sprites and
+/// most of the interface graphics. Pixels are palette indices.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Patch {
+	pub width : i32,
+	pub height : i32,
+	pub left_offset : i32,
+	pub top_offset : i32,
+	pub columns : Vec<Vec<Post>>
+}
+
+impl Patch {
+	pub fn new(data : &[u8]) -> WadResult<Patch> {
+		if data.len() < 8 {
+			return Err(WadError::InvalidLump("patch"));
+		}
+
+		let width = read_u16(data, 0) as i32;
+		let height = read_u16(data, 2) as i32;
+		let left_offset = read_u16(data, 4) as i16 as i32;
+		let top_offset = read_u16(data, 6) as i16 as i32;
+
+		if data.len() < 8 + width as usize * 4 {
+			return Err(WadError::InvalidLump("patch"));
+		}
+
+		let mut columns = Vec::<Vec<Post>>::with_capacity(width as usize);
+		for x in 0..width as usize {
+			let mut pos = read_u32(data, 8 + x * 4) as usize;
+			let mut posts = Vec::<Post>::new();
+			let mut last_top = -1i32;
+
+			loop {
+				if pos >= data.len() {
+					return Err(WadError::InvalidLump("patch"));
+				}
+				if data[pos] == 0xFF {
+					break;
+				}
+				if pos + 3 > data.len() {
+					return Err(WadError::InvalidLump("patch"));
+				}
+
+				// Tall patches store the offset relative to the previous post.
+				let mut top = data[pos] as i32;
+				if top <= last_top {
+					top += last_top;
+				}
+				last_top = top;
+
+				let length = data[pos + 1] as usize;
+				let start = pos + 3;
+				if start + length > data.len() {
+					return Err(WadError::InvalidLump("patch"));
+				}
+
+				posts.push(Post {
+					top: top,
+					pixels: data[start..start + length].to_vec()
+				});
+
+				pos = start + length + 1;
+			}
+
+			columns.push(posts);
+		}
+
+		Ok(Patch {
+			width: width,
+			height: height,
+			left_offset: left_offset,
+			top_offset: top_offset,
+			columns: columns
+		})
+	}
+}
+
+fn read_u16(data : &[u8], pos : usize) -> u16 {
+	data[pos] as u16 | (data[pos + 1] as u16) << 8
+}
+
+fn read_u32(data : &[u8], pos : usize) -> u32 {
+	read_u16(data, pos) as u32 | (read_u16(data, pos + 2) as u32) << 16
+}
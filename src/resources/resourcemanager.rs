@@ -1,5 +1,14 @@
+use std::fs;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
 use std::fs::File;
 use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
 use std::io;
 use std::io::Read;
 use std::io::BufReader;
@@ -7,6 +16,23 @@ use std::io::SeekFrom;
 use std::io::Seek;
 use resources::bsp;
 use resources::WadPackage;
+use resources::Patch;
+use resources::Texture;
+use resources::TextureDef;
+use resources::TextureDirectory;
+use resources::Flat;
+use resources::Namespace;
+use resources::SpriteFrame;
+use resources::read_patch_names;
+use resources::read_texture_defs;
+use resources::cache::ResourceCache;
+use resources::cache::CachedResource;
+use resources::cache::ResourceKey;
+use resources::cache::CacheStats;
+use resources::cache::DEFAULT_CACHE_BUDGET;
+use resources::loader::LoadWorker;
+use resources::loader::LoadHandle;
+use audio::Sound;
 use resources::WadResult;
 use resources::WadError;
 
@@ -22,52 +48,377 @@ enum PackageFormat {
 pub enum PackageLoadError {
 	UnknownPackage,
 	IoFailure(io::Error),
-	WadError(WadError)
+	WadError(WadError),
+	/// The load panicked, or the loader thread stopped before finishing it.
+	LoaderFailed
 }
 
+/// A package loaded from a file, remembered so it can be reloaded when the
+/// file changes.
+struct LoadedPackage {
+	path : PathBuf,
+	modified : Option<SystemTime>,
+	package : WadPackage
+}
+
+/// Finds resources in the loaded packages. Packages are opened by reading
+/// their directory only, and lumps, maps, patches, textures, flats, sprites
+/// and sounds are read when they are first asked for. Decoded resources are kept in a
+/// cache with a memory budget.
+///
+/// The resource manager can be shared between threads in an `Arc`. Packages
+/// and maps can be loaded on the loader thread with the `_async` methods,
+/// which return a handle to poll from the main loop.
 pub struct ResourceManager {
-	maps : Vec<bsp::Map>
+	packages : RwLock<Vec<LoadedPackage>>,
+	cache : Mutex<ResourceCache>,
+	worker : LoadWorker
 }
 
 impl ResourceManager {
 	pub fn new() -> ResourceManager {
+		ResourceManager::with_cache_budget(DEFAULT_CACHE_BUDGET)
+	}
+
+	/// Creates a resource manager whose cache holds about `budget` bytes.
+	pub fn with_cache_budget(budget : usize) -> ResourceManager {
 		ResourceManager {
-			maps: Vec::<bsp::Map>::new()
+			packages: RwLock::new(Vec::<LoadedPackage>::new()),
+			cache: Mutex::new(ResourceCache::new(budget)),
+			worker: LoadWorker::new()
+		}
+	}
+
+	pub fn set_cache_budget(&self, budget : usize) {
+		self.cache().set_budget(budget);
+	}
+
+	pub fn cache_stats(&self) -> CacheStats {
+		self.cache().stats()
+	}
+
+	/// Finds the map with the given name. Like lumps, maps from packages
+	/// loaded later override the earlier ones. Returns None if the map is
+	/// missing or cannot be read.
+	pub fn find_map(&self, name : &str) -> Option<Arc<bsp::Map>> {
+		self.load_map(name).ok().and_then(|map| map)
+	}
+
+	/// Finds the lump with the given name. Lumps from packages loaded later
+	/// override the earlier ones, like PWADs override the IWAD.
+	pub fn find_lump(&self, name : &str) -> Option<Arc<Vec<u8>>> {
+		let key = ResourceKey::Lump(name.to_ascii_uppercase());
+		let (cached, generation) = self.cached(&key);
+		if let Some(CachedResource::Lump(data)) = cached {
+			return Some(data);
+		}
+
+		let data = match self.read_lump(name) {
+			Some(value) => Arc::new(value),
+			None => return None
+		};
+
+		self.cache().insert(key, CachedResource::Lump(data.clone()), generation);
+		Some(data)
+	}
+
+	/// Finds a patch, like a sprite frame or an interface graphic.
+	pub fn find_patch(&self, name : &str) -> Option<Arc<Patch>> {
+		let key = ResourceKey::Patch(name.to_ascii_uppercase());
+		let (cached, generation) = self.cached(&key);
+		if let Some(CachedResource::Patch(patch)) = cached {
+			return Some(patch);
 		}
+
+		let patch = match self.read_lump(name).and_then(|data| Patch::new(&data).ok()) {
+			Some(value) => Arc::new(value),
+			None => return None
+		};
+
+		self.cache().insert(key, CachedResource::Patch(patch.clone()), generation);
+		Some(patch)
 	}
 
-	pub fn find_map(&self, name : &str) -> Option<&bsp::Map> {
-		for level in &self.maps {
-			if level.name == name {
-				return Some(level);
+	/// Finds a wall texture defined in TEXTURE1 or TEXTURE2, and composes it
+	/// from its patches.
+	pub fn find_texture(&self, name : &str) -> Option<Arc<Texture>> {
+		let key = ResourceKey::Texture(name.to_ascii_uppercase());
+		let (cached, generation) = self.cached(&key);
+		match cached {
+			Some(CachedResource::Texture(texture)) => return Some(texture),
+			Some(CachedResource::Missing) => return None,
+			_ => ()
+		}
+
+		let texture = match self.texture_directory().and_then(|directory| {
+			directory.find(name).map(|def| Texture::compose(def, &directory.patch_names, |patch_name| self.find_patch(patch_name)))
+		}) {
+			Some(value) => Arc::new(value),
+			None => {
+				self.cache().insert(key, CachedResource::Missing, generation);
+				return None;
 			}
+		};
+
+		self.cache().insert(key, CachedResource::Texture(texture.clone()), generation);
+		Some(texture)
+	}
+
+	/// The texture definitions of the packages, read once after the packages
+	/// change. Returns None without a PNAMES lump.
+	pub fn texture_directory(&self) -> Option<Arc<TextureDirectory>> {
+		let key = ResourceKey::TextureDirectory;
+		let (cached, generation) = self.cached(&key);
+		match cached {
+			Some(CachedResource::TextureDirectory(directory)) => return Some(directory),
+			Some(CachedResource::Missing) => return None,
+			_ => ()
 		}
-		return None;
+
+		let patch_names = match self.read_lump("PNAMES").and_then(|data| read_patch_names(&data).ok()) {
+			Some(value) => value,
+			None => {
+				self.cache().insert(key, CachedResource::Missing, generation);
+				return None;
+			}
+		};
+
+		// TEXTURE2 only exists in the registered and commercial IWADs.
+		let mut defs = Vec::<TextureDef>::new();
+		for lump_name in ["TEXTURE1", "TEXTURE2"].iter() {
+			if let Some(lump_defs) = self.read_lump(lump_name).and_then(|data| read_texture_defs(&data).ok()) {
+				defs.extend(lump_defs);
+			}
+		}
+
+		let directory = Arc::new(TextureDirectory::new(patch_names, defs));
+		self.cache().insert(key, CachedResource::TextureDirectory(directory.clone()), generation);
+		Some(directory)
 	}
 
-	pub fn load_package<P : AsRef<Path>>(&mut self, path : P) -> PackageLoadResult<()> {
-		let mut file = match File::open(path) {
-			Ok(value) => value,
-			Err(err) => return Err(PackageLoadError::IoFailure(err))
+	/// Finds a floor or ceiling texture.
+	pub fn find_flat(&self, name : &str) -> Option<Arc<Flat>> {
+		let key = ResourceKey::Flat(name.to_ascii_uppercase());
+		let (cached, generation) = self.cached(&key);
+		match cached {
+			Some(CachedResource::Flat(flat)) => return Some(flat),
+			Some(CachedResource::Missing) => return None,
+			_ => ()
+		}
+
+		let flat = match self.read_lump(name).and_then(|data| Flat::new(name, &data).ok()) {
+			Some(value) => Arc::new(value),
+			None => {
+				self.cache().insert(key, CachedResource::Missing, generation);
+				return None;
+			}
 		};
 
-		match try!(detect_package_format(&mut file)) {
-			PackageFormat::IWad | PackageFormat::PWad => {
-				self.add_package(&try!(wrap_wad_error(WadPackage::new(&mut file))));
-			},
-			_ => return Err(PackageLoadError::UnknownPackage)
+		self.cache().insert(key, CachedResource::Flat(flat.clone()), generation);
+		Some(flat)
+	}
+
+	/// Finds a frame of a sprite, like frame A of POSS, with the patches for
+	/// each direction.
+	pub fn find_sprite_frame(&self, sprite : &str, frame : u8) -> Option<Arc<SpriteFrame>> {
+		let key = ResourceKey::SpriteFrame(format!("{}{}", sprite.to_ascii_uppercase(), frame.to_ascii_uppercase() as char));
+		let (cached, generation) = self.cached(&key);
+		match cached {
+			Some(CachedResource::SpriteFrame(frame)) => return Some(frame),
+			Some(CachedResource::Missing) => return None,
+			_ => ()
 		}
 
+		// The lumps of the later packages come last, so they override.
+		let lump_names : Vec<String> = {
+			let packages = self.packages();
+			packages.iter()
+				.flat_map(|loaded| loaded.package.get_lumps().iter())
+				.filter(|lump| lump.namespace == Namespace::Sprites && lump.name.len() >= 6 && lump.name.as_bytes()[..4].eq_ignore_ascii_case(sprite.as_bytes()))
+				.map(|lump| lump.name.clone())
+				.collect()
+		};
+
+		let frame = match SpriteFrame::new(sprite, frame, &lump_names, |name| self.find_patch(name)) {
+			Some(value) => Arc::new(value),
+			None => {
+				self.cache().insert(key, CachedResource::Missing, generation);
+				return None;
+			}
+		};
+
+		self.cache().insert(key, CachedResource::SpriteFrame(frame.clone()), generation);
+		Some(frame)
+	}
+
+	/// Finds a sound effect, by its lump name (DSPISTOL).
+	pub fn find_sound(&self, name : &str) -> Option<Arc<Sound>> {
+		let key = ResourceKey::Sound(name.to_ascii_uppercase());
+		let (cached, generation) = self.cached(&key);
+		if let Some(CachedResource::Sound(sound)) = cached {
+			return Some(sound);
+		}
+
+		let sound = match self.read_lump(name).and_then(|data| Sound::new(&data).ok()) {
+			Some(value) => Arc::new(value),
+			None => return None
+		};
+
+		self.cache().insert(key, CachedResource::Sound(sound.clone()), generation);
+		Some(sound)
+	}
+
+	/// Reads and decodes a map, or takes it from the cache. Returns None if
+	/// no package has the map.
+	pub fn load_map(&self, name : &str) -> PackageLoadResult<Option<Arc<bsp::Map>>> {
+		let key = ResourceKey::Map(name.to_ascii_uppercase());
+		let (cached, generation) = self.cached(&key);
+		if let Some(CachedResource::Map(map)) = cached {
+			return Ok(Some(map));
+		}
+
+		let map = {
+			let packages = self.packages();
+			let loaded = match packages.iter().rev().find(|loaded| loaded.package.has_map(name)) {
+				Some(value) => value,
+				None => return Ok(None)
+			};
+			match try!(wrap_wad_error(loaded.package.read_map(name))) {
+				Some(value) => Arc::new(value),
+				None => return Ok(None)
+			}
+		};
+
+		self.cache().insert(key, CachedResource::Map(map.clone()), generation);
+		Ok(Some(map))
+	}
+
+	/// Loads a map on the loader thread, so it is in the cache when it is
+	/// needed.
+	pub fn load_map_async(self : &Arc<ResourceManager>, name : &str) -> LoadHandle<Option<Arc<bsp::Map>>> {
+		let resources = self.clone();
+		let name = name.to_string();
+		self.worker.run(move || resources.load_map(&name))
+	}
+
+	pub fn load_package<P : AsRef<Path>>(&self, path : P) -> PackageLoadResult<()> {
+		let path = path.as_ref();
+		let modified = modification_time(path);
+		let package = try!(read_package(path));
+
+		self.packages_mut().push(LoadedPackage {
+			path: path.to_path_buf(),
+			modified: modified,
+			package: package
+		});
+
+		// The new package may override cached resources.
+		self.cache().clear();
 		Ok(())
 	}
 
-	pub fn clear_resources(&mut self) {
-		self.maps.clear();
+	/// Loads a package on the loader thread. Packages loaded this way are
+	/// added in the order the calls were made.
+	pub fn load_package_async<P : AsRef<Path>>(self : &Arc<ResourceManager>, path : P) -> LoadHandle<()> {
+		let resources = self.clone();
+		let path = path.as_ref().to_path_buf();
+		self.worker.run(move || resources.load_package(&path))
+	}
+
+	/// Reloads the packages whose files changed since they were loaded, and
+	/// returns the names of the maps in them. Packages keep their place in
+	/// the load order. If a package fails to load, for example because an
+	/// editor is still writing it, it is tried again on the next call. Until
+	/// then lumps that aren't cached can't be read from it, since its
+	/// directory no longer matches the file. The other packages are still
+	/// reloaded, but only the error is returned.
+	pub fn reload_changed_packages(&self) -> PackageLoadResult<Vec<String>> {
+		let mut changed_maps = Vec::<String>::new();
+		let mut result = Ok(());
+
+		let mut packages = self.packages_mut();
+		for loaded in packages.iter_mut() {
+			let modified = modification_time(&loaded.path);
+			if modified.is_none() || modified == loaded.modified {
+				continue;
+			}
+
+			match read_package(&loaded.path) {
+				Ok(package) => {
+					loaded.modified = modified;
+					loaded.package = package;
+					changed_maps.extend(loaded.package.get_map_names().iter().map(|name| name.to_string()));
+					self.cache().clear();
+				}
+				Err(err) => result = Err(err)
+			}
+		}
+
+		result.map(|_| changed_maps)
+	}
+
+	pub fn clear_resources(&self) {
+		self.packages_mut().clear();
+		self.cache().clear();
+	}
+
+	/// Reads the raw data of a lump, bypassing the cache.
+	fn read_lump(&self, name : &str) -> Option<Vec<u8>> {
+		for loaded in self.packages().iter().rev() {
+			if let Some(index) = loaded.package.find_lump(name) {
+				return loaded.package.read_lump(index).ok();
+			}
+		}
+		return None;
 	}
 
-	fn add_package(&mut self, package : &WadPackage) {
-		self.maps.extend_from_slice(package.get_maps());
+	// A panic on another thread doesn't leave the packages or the cache
+	// broken, so poisoned locks are used as they are.
+
+	fn packages(&self) -> RwLockReadGuard<Vec<LoadedPackage>> {
+		match self.packages.read() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner()
+		}
 	}
+
+	fn packages_mut(&self) -> RwLockWriteGuard<Vec<LoadedPackage>> {
+		match self.packages.write() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner()
+		}
+	}
+
+	// Looks a resource up in the cache. Also returns the generation of the
+	// cache to insert the resource with if it has to be read, so a resource
+	// read while the packages change is not cached.
+	fn cached(&self, key : &ResourceKey) -> (Option<CachedResource>, u64) {
+		let mut cache = self.cache();
+		(cache.get(key), cache.generation())
+	}
+
+	fn cache(&self) -> MutexGuard<ResourceCache> {
+		match self.cache.lock() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner()
+		}
+	}
+}
+
+fn read_package(path : &Path) -> PackageLoadResult<WadPackage> {
+	let mut file = match File::open(path) {
+		Ok(value) => value,
+		Err(err) => return Err(PackageLoadError::IoFailure(err))
+	};
+
+	match try!(detect_package_format(&mut file)) {
+		PackageFormat::IWad | PackageFormat::PWad => wrap_wad_error(WadPackage::new(file)),
+		_ => Err(PackageLoadError::UnknownPackage)
+	}
+}
+
+fn modification_time(path : &Path) -> Option<SystemTime> {
+	fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
 }
 
 fn detect_package_format(file : &mut File) -> PackageLoadResult<PackageFormat> {
@@ -103,3 +454,105 @@ fn wrap_wad_error<T>(error : WadResult<T>) -> PackageLoadResult<T> {
 		Err(error) => Err(PackageLoadError::WadError(error))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::fs::OpenOptions;
+	use std::time::Duration;
+	use resources::testwad::*;
+	use super::*;
+
+	fn test_wad(file_name : &str, color : u8) -> PathBuf {
+		let path = temp_path(file_name);
+		write_wad(&path, &[
+			("PNAMES", pnames(&["WALLPAT"])),
+			("TEXTURE1", texture1(&["WALL", "DOOR"])),
+			("WALLPAT", patch(color))
+		]);
+		path
+	}
+
+	#[test]
+	fn textures_come_from_the_directory() {
+		let path = test_wad("textures", 7);
+		let resources = ResourceManager::new();
+		resources.load_package(&path).unwrap();
+		let _ = fs::remove_file(&path);
+
+		let texture = resources.find_texture("wall").unwrap();
+		assert_eq!((texture.width, texture.height), (1, 2));
+		assert_eq!(texture.column(0), &[7, 7]);
+		assert!(resources.find_texture("DOOR").is_some());
+		assert!(resources.texture_directory().unwrap().find("door").is_some());
+	}
+
+	#[test]
+	fn missing_resources_are_cached() {
+		let path = test_wad("missing", 7);
+		let resources = ResourceManager::new();
+		resources.load_package(&path).unwrap();
+		let _ = fs::remove_file(&path);
+
+		assert!(resources.find_texture("NOTHERE").is_none());
+		assert!(resources.find_flat("NOTHERE").is_none());
+		assert!(resources.find_sprite_frame("NOTH", b'A').is_none());
+		let hits = resources.cache_stats().hits;
+
+		assert!(resources.find_texture("NOTHERE").is_none());
+		assert!(resources.find_flat("NOTHERE").is_none());
+		assert!(resources.find_sprite_frame("NOTH", b'A').is_none());
+		assert_eq!(resources.cache_stats().hits, hits + 3);
+	}
+
+	#[test]
+	fn loading_a_package_replaces_cached_textures() {
+		let first = test_wad("first", 7);
+		let second = test_wad("second", 9);
+		let resources = ResourceManager::new();
+		resources.load_package(&first).unwrap();
+		assert_eq!(resources.find_texture("WALL").unwrap().column(0), &[7, 7]);
+
+		resources.load_package(&second).unwrap();
+		let _ = fs::remove_file(&first);
+		let _ = fs::remove_file(&second);
+		assert_eq!(resources.find_texture("WALL").unwrap().column(0), &[9, 9]);
+	}
+
+	// Gives a file a modification time that can't be the one it had, however
+	// coarse the clock of the file system is.
+	fn touch(path : &Path, seconds : u64) {
+		let file = OpenOptions::new().write(true).open(path).unwrap();
+		file.set_modified(SystemTime::now() + Duration::from_secs(seconds)).unwrap();
+	}
+
+	#[test]
+	fn changed_packages_are_reloaded() {
+		let path = temp_path("reload");
+		write_wad(&path, &room_map("MAP01", &[]));
+		let resources = ResourceManager::new();
+		resources.load_package(&path).unwrap();
+		assert_eq!(resources.reload_changed_packages().unwrap(), Vec::<String>::new());
+
+		// The directory of the old file doesn't fit the new one.
+		write_wad(&path, &room_map("MAP02", &[(32, 0, 1)]));
+		touch(&path, 10);
+		assert!(resources.find_lump("THINGS").is_none());
+
+		// A half written file keeps the old package until it can be read.
+		fs::write(&path, b"PWAD").unwrap();
+		touch(&path, 20);
+		assert!(resources.reload_changed_packages().is_err());
+		assert!(resources.reload_changed_packages().is_err());
+
+		write_wad(&path, &room_map("MAP02", &[(32, 0, 1)]));
+		touch(&path, 30);
+		assert_eq!(resources.reload_changed_packages().unwrap(), vec!["MAP02".to_string()]);
+		let _ = fs::remove_file(&path);
+
+		assert!(resources.find_map("MAP01").is_none());
+		let map = resources.find_map("MAP02").unwrap();
+		assert_eq!(map.things.len(), 1);
+		assert_eq!(map.things[0].x, 32 << 16);
+	}
+}
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+use resources::Patch;
+
+/// A sprite patch seen from one direction. Flipped patches are drawn
+/// mirrored, so one patch can serve two directions.
+#[derive(Debug, Clone)]
+pub struct SpriteRotation {
+	pub patch : Arc<Patch>,
+	pub flipped : bool
+}
+
+/// One frame of a sprite, like POSSA. Frames have a patch for each of the
+/// eight directions a thing can be seen from, or one for all of them.
+#[derive(Debug, Clone)]
+pub struct SpriteFrame {
+	pub rotations : Vec<Option<SpriteRotation>>
+}
+
+impl SpriteFrame {
+	/// Builds a frame from the names of the sprite lumps, like
+	/// R_InitSpriteDefs. POSSA1 is the front view, POSSA2A8 is seen from
+	/// front-left and mirrored from front-right, and POSSA0 is used from all
+	/// directions. Names later in the list override earlier ones.
+	pub fn new<F>(sprite : &str, frame : u8, lump_names : &[String], find_patch : F) -> Option<SpriteFrame>
+		where F : Fn(&str) -> Option<Arc<Patch>> {
+
+		let prefix = sprite.to_ascii_uppercase();
+		let frame = frame.to_ascii_uppercase();
+		let mut names : Vec<Option<(&str, bool)>> = vec![None; 8];
+
+		for name in lump_names.iter() {
+			let bytes = name.as_bytes();
+			if bytes.len() < 6 || !bytes[..4].eq_ignore_ascii_case(prefix.as_bytes()) {
+				continue;
+			}
+
+			for &(frame_at, rotation_at, flipped) in [(4, 5, false), (6, 7, true)].iter() {
+				if bytes.len() <= rotation_at || bytes[frame_at].to_ascii_uppercase() != frame {
+					continue;
+				}
+				match bytes[rotation_at] {
+					b'0' => {
+						for rotation in names.iter_mut() {
+							*rotation = Some((&name[..], flipped));
+						}
+					}
+					digit @ b'1'..=b'8' => names[(digit - b'1') as usize] = Some((&name[..], flipped)),
+					_ => ()
+				}
+			}
+		}
+
+		if names.iter().all(|name| name.is_none()) {
+			return None;
+		}
+
+		let rotations = names.iter().map(|name| {
+			name.and_then(|(name, flipped)| find_patch(name).map(|patch| SpriteRotation {
+				patch: patch,
+				flipped: flipped
+			}))
+		}).collect();
+
+		Some(SpriteFrame {
+			rotations: rotations
+		})
+	}
+
+	/// The rotation seen from `direction`, 0 for the front and counting
+	/// counterclockwise in steps of 45 degrees.
+	pub fn rotation(&self, direction : usize) -> Option<&SpriteRotation> {
+		self.rotations[direction & 7].as_ref()
+			.or_else(|| self.rotations.iter().filter_map(|rotation| rotation.as_ref()).next())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use resources::Patch;
+	use super::*;
+
+	fn patch() -> Option<Arc<Patch>> {
+		Some(Arc::new(Patch::new(&[1, 0, 1, 0, 0, 0, 0, 0, 12, 0, 0, 0, 0xFF]).unwrap()))
+	}
+
+	#[test]
+	fn rotations_come_from_the_lump_names() {
+		let names = vec!["POSSA1".to_string(), "POSSA2A8".to_string(), "POSSB0".to_string()];
+		let frame = SpriteFrame::new("poss", b'a', &names, |_| patch()).unwrap();
+		assert!(frame.rotations[0].as_ref().map_or(false, |rotation| !rotation.flipped));
+		assert!(frame.rotations[1].as_ref().map_or(false, |rotation| !rotation.flipped));
+		assert!(frame.rotations[7].as_ref().map_or(false, |rotation| rotation.flipped));
+		assert!(frame.rotations[2].is_none());
+		assert!(SpriteFrame::new("POSS", b'C', &names, |_| patch()).is_none());
+	}
+
+	#[test]
+	fn names_with_other_characters_are_skipped() {
+		let names = vec!["POS\u{e9}A0".to_string(), "\u{e9}\u{e9}\u{e9}".to_string(), "POSSA0".to_string()];
+		let frame = SpriteFrame::new("POSS", b'A', &names, |_| patch()).unwrap();
+		assert!(frame.rotations.iter().all(|rotation| rotation.is_some()));
+	}
+}
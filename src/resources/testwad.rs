@@ -0,0 +1,190 @@
+//! Small PWADs written by the tests.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+pub fn name(name : &str) -> Vec<u8> {
+	let mut bytes = name.as_bytes().to_vec();
+	bytes.resize(8, 0);
+	bytes
+}
+
+pub fn i16_le(value : i16) -> Vec<u8> {
+	u16_le(value as u16)
+}
+
+pub fn u16_le(value : u16) -> Vec<u8> {
+	vec![value as u8, (value >> 8) as u8]
+}
+
+pub fn u32_le(value : u32) -> Vec<u8> {
+	vec![value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
+/// A path in the temporary directory that no other test process uses.
+pub fn temp_path(file_name : &str) -> PathBuf {
+	env::temp_dir().join(format!("doom-{}-{}.wad", file_name, ::std::process::id()))
+}
+
+pub fn write_wad(path : &Path, lumps : &[(&str, Vec<u8>)]) {
+	let mut data = b"PWAD".to_vec();
+	data.extend(u32_le(lumps.len() as u32));
+	let directory = 12 + lumps.iter().map(|lump| lump.1.len()).sum::<usize>();
+	data.extend(u32_le(directory as u32));
+	for lump in lumps.iter() {
+		data.extend_from_slice(&lump.1);
+	}
+	let mut pos = 12;
+	for lump in lumps.iter() {
+		data.extend(u32_le(pos as u32));
+		data.extend(u32_le(lump.1.len() as u32));
+		data.extend(name(lump.0));
+		pos += lump.1.len();
+	}
+	fs::write(path, data).unwrap();
+}
+
+/// A 1x2 patch of `color`.
+pub fn patch(color : u8) -> Vec<u8> {
+	sprite_patch(1, 2, 0, 0, color)
+}
+
+/// A patch of `color` with the given offsets, like those of sprites.
+pub fn sprite_patch(width : u16, height : u8, left : i16, top : i16, color : u8) -> Vec<u8> {
+	let mut data = Vec::<u8>::new();
+	data.extend(u16_le(width));
+	data.extend(u16_le(height as u16));
+	data.extend(i16_le(left));
+	data.extend(i16_le(top));
+	let column_size = height as u32 + 5;
+	for x in 0..width as u32 {
+		data.extend(u32_le(8 + width as u32 * 4 + x * column_size));
+	}
+	for _ in 0..width {
+		data.extend_from_slice(&[0, height, 0]);
+		data.extend(vec![color; height as usize]);
+		data.extend_from_slice(&[0, 0xFF]);
+	}
+	data
+}
+
+pub fn pnames(names : &[&str]) -> Vec<u8> {
+	let mut data = u32_le(names.len() as u32);
+	for patch in names.iter() {
+		data.extend(name(patch));
+	}
+	data
+}
+
+/// TEXTURE1 with textures of the given names, each made of patch 0.
+pub fn texture1(names : &[&str]) -> Vec<u8> {
+	let mut data = u32_le(names.len() as u32);
+	for i in 0..names.len() {
+		data.extend(u32_le(4 + names.len() as u32 * 4 + i as u32 * 32));
+	}
+	for texture in names.iter() {
+		data.extend(name(texture));
+		data.extend(u32_le(0));
+		data.extend(u16_le(1));
+		data.extend(u16_le(2));
+		data.extend(u32_le(0));
+		data.extend(u16_le(1));
+		data.extend(u16_le(0));
+		data.extend(u16_le(0));
+		data.extend(u16_le(0));
+		data.extend(u16_le(1));
+		data.extend(u16_le(0));
+	}
+	data
+}
+
+/// A flat of a single color.
+pub fn flat(color : u8) -> Vec<u8> {
+	vec![color; 64 * 64]
+}
+
+/// A palette where each index is the gray of the same value.
+pub fn playpal() -> Vec<u8> {
+	(0..256).flat_map(|i| vec![i as u8; 3]).collect()
+}
+
+/// A COLORMAP that keeps every index at every light level.
+pub fn colormap() -> Vec<u8> {
+	(0..34 * 256).map(|i| i as u8).collect()
+}
+
+/// The lumps of a map with a single room, 256 units long from x -64 to 192
+/// and 128 wide around y 0, so the origin looks along it. The walls are
+/// WALL, the floor FLOOR, and the ceiling CEIL, 128 above it. The things
+/// are at the positions with the types given, on every skill.
+pub fn room_map(map_name : &'static str, things : &[(i16, i16, u16)]) -> Vec<(&'static str, Vec<u8>)> {
+	let corners = [(-64i16, 64i16), (192, 64), (192, -64), (-64, -64)];
+	// Clockwise, so the room is on the right of each line.
+	let angles = [0x0000u16, 0xC000, 0x8000, 0x4000];
+
+	let mut thing_data = Vec::<u8>::new();
+	for &(x, y, thing_type) in things.iter() {
+		thing_data.extend(i16_le(x));
+		thing_data.extend(i16_le(y));
+		thing_data.extend(i16_le(0));
+		thing_data.extend(u16_le(thing_type));
+		thing_data.extend(u16_le(7));
+	}
+
+	let mut lines = Vec::<u8>::new();
+	let mut sides = Vec::<u8>::new();
+	let mut vertices = Vec::<u8>::new();
+	let mut segs = Vec::<u8>::new();
+	for i in 0..4 {
+		let next = (i + 1) % 4;
+		lines.extend(u16_le(i as u16));
+		lines.extend(u16_le(next as u16));
+		lines.extend(u16_le(1));
+		lines.extend(u16_le(0));
+		lines.extend(u16_le(0));
+		lines.extend(u16_le(i as u16));
+		lines.extend(u16_le(0xFFFF));
+
+		sides.extend(i16_le(0));
+		sides.extend(i16_le(0));
+		sides.extend(name("-"));
+		sides.extend(name("-"));
+		sides.extend(name("WALL"));
+		sides.extend(u16_le(0));
+
+		vertices.extend(i16_le(corners[i].0));
+		vertices.extend(i16_le(corners[i].1));
+
+		segs.extend(u16_le(i as u16));
+		segs.extend(u16_le(next as u16));
+		segs.extend(u16_le(angles[i]));
+		segs.extend(u16_le(i as u16));
+		segs.extend(u16_le(0));
+		segs.extend(i16_le(0));
+	}
+
+	let mut subsectors = u16_le(4);
+	subsectors.extend(u16_le(0));
+
+	let mut sectors = i16_le(0);
+	sectors.extend(i16_le(128));
+	sectors.extend(name("FLOOR"));
+	sectors.extend(name("CEIL"));
+	sectors.extend(u16_le(255));
+	sectors.extend(u16_le(0));
+	sectors.extend(u16_le(0));
+
+	vec![
+		(map_name, Vec::<u8>::new()),
+		("THINGS", thing_data),
+		("LINEDEFS", lines),
+		("SIDEDEFS", sides),
+		("VERTEXES", vertices),
+		("SEGS", segs),
+		("SSECTORS", subsectors),
+		("NODES", Vec::<u8>::new()),
+		("SECTORS", sectors)
+	]
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+use resources::Patch;
+use resources::WadResult;
+use resources::WadError;
+
+/// A patch placed on a texture.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TexturePatch {
+	pub x : i32,
+	pub y : i32,
+	pub patch : usize
+}
+
+/// A wall texture as defined in TEXTURE1 or TEXTURE2. `patch` indexes the
+/// names in PNAMES.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TextureDef {
+	pub name : String,
+	pub width : i32,
+	pub height : i32,
+	pub patches : Vec<TexturePatch>
+}
+
+/// The texture definitions of TEXTURE1 and TEXTURE2 with the patch names of
+/// PNAMES, to look textures up by name without reading the lumps again.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TextureDirectory {
+	pub patch_names : Vec<String>,
+	defs : HashMap<String, TextureDef>
+}
+
+impl TextureDirectory {
+	/// Makes a directory of the definitions. Like the texture lookup of
+	/// vanilla, the first definition of a name wins.
+	pub fn new(patch_names : Vec<String>, defs : Vec<TextureDef>) -> TextureDirectory {
+		let mut directory = HashMap::<String, TextureDef>::with_capacity(defs.len());
+		for def in defs {
+			directory.entry(def.name.to_ascii_uppercase()).or_insert(def);
+		}
+
+		TextureDirectory {
+			patch_names: patch_names,
+			defs: directory
+		}
+	}
+
+	pub fn find(&self, name : &str) -> Option<&TextureDef> {
+		self.defs.get(&name.to_ascii_uppercase())
+	}
+
+	/// Approximate memory used by the directory.
+	pub fn size(&self) -> usize {
+		self.patch_names.len() * 8 +
+		self.defs.values().map(|def| mem::size_of::<TextureDef>() + def.patches.len() * mem::size_of::<TexturePatch>()).sum::<usize>()
+	}
+}
+
+/// A wall texture composed from its patches. The pixels are stored column by
+/// column, as palette indices. Pixels that no patch covers are transparent
+/// when the texture is drawn masked, like the mid-texture of a grate.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Texture {
+	pub name : String,
+	pub width : i32,
+	pub height : i32,
+	pub pixels : Vec<u8>,
+	pub opaque : Vec<bool>
+}
+
+impl Texture {
+	/// Draws the patches of the definition into a texture. Patches that are
+	/// missing are left out, like some ports do, instead of failing.
+	pub fn compose<F>(def : &TextureDef, patch_names : &[String], find_patch : F) -> Texture
+		where F : Fn(&str) -> Option<Arc<Patch>> {
+
+		let width = if def.width > 0 { def.width } else { 1 };
+		let height = if def.height > 0 { def.height } else { 1 };
+		let mut pixels = vec![0u8; (width * height) as usize];
+		let mut opaque = vec![false; (width * height) as usize];
+
+		for placement in def.patches.iter() {
+			let patch = match patch_names.get(placement.patch).and_then(|name| find_patch(name)) {
+				Some(value) => value,
+				None => continue
+			};
+
+			for (column_x, posts) in patch.columns.iter().enumerate() {
+				let x = placement.x + column_x as i32;
+				if x < 0 || x >= width {
+					continue;
+				}
+
+				for post in posts {
+					for (i, &index) in post.pixels.iter().enumerate() {
+						let y = placement.y + post.top + i as i32;
+						if y >= 0 && y < height {
+							pixels[(x * height + y) as usize] = index;
+							opaque[(x * height + y) as usize] = true;
+						}
+					}
+				}
+			}
+		}
+
+		Texture {
+			name: def.name.clone(),
+			width: width,
+			height: height,
+			pixels: pixels,
+			opaque: opaque
+		}
+	}
+
+	/// A column of the texture. The texture repeats horizontally.
+	pub fn column(&self, x : i32) -> &[u8] {
+		let x = x.rem_euclid(self.width) as usize;
+		let height = self.height as usize;
+		&self.pixels[x * height..(x + 1) * height]
+	}
+
+	/// Which pixels of a column are opaque.
+	pub fn opaque_column(&self, x : i32) -> &[bool] {
+		let x = x.rem_euclid(self.width) as usize;
+		let height = self.height as usize;
+		&self.opaque[x * height..(x + 1) * height]
+	}
+}
+
+/// Reads the patch names from the PNAMES lump.
+pub fn read_patch_names(data : &[u8]) -> WadResult<Vec<String>> {
+	if data.len() < 4 {
+		return Err(WadError::InvalidLump("PNAMES"));
+	}
+
+	let count = read_u32(data, 0) as usize;
+	if data.len() < 4 + count * 8 {
+		return Err(WadError::InvalidLump("PNAMES"));
+	}
+
+	Ok((0..count).map(|i| read_name(&data[4 + i * 8..12 + i * 8])).collect())
+}
+
+/// Reads the texture definitions from a TEXTURE1 or TEXTURE2 lump.
+pub fn read_texture_defs(data : &[u8]) -> WadResult<Vec<TextureDef>> {
+	if data.len() < 4 {
+		return Err(WadError::InvalidLump("TEXTURE1"));
+	}
+
+	let count = read_u32(data, 0) as usize;
+	if data.len() < 4 + count * 4 {
+		return Err(WadError::InvalidLump("TEXTURE1"));
+	}
+
+	let mut defs = Vec::<TextureDef>::with_capacity(count);
+	for i in 0..count {
+		let pos = read_u32(data, 4 + i * 4) as usize;
+		if pos + 22 > data.len() {
+			return Err(WadError::InvalidLump("TEXTURE1"));
+		}
+
+		// Name, masked flag, width, height, column directory, patch count
+		let num_patches = read_u16(data, pos + 20) as usize;
+		if pos + 22 + num_patches * 10 > data.len() {
+			return Err(WadError::InvalidLump("TEXTURE1"));
+		}
+
+		let patches = (0..num_patches).map(|j| {
+			let patch_pos = pos + 22 + j * 10;
+			TexturePatch {
+				x: read_u16(data, patch_pos) as i16 as i32,
+				y: read_u16(data, patch_pos + 2) as i16 as i32,
+				patch: read_u16(data, patch_pos + 4) as usize
+			}
+		}).collect();
+
+		defs.push(TextureDef {
+			name: read_name(&data[pos..pos + 8]),
+			width: read_u16(data, pos + 12) as i16 as i32,
+			height: read_u16(data, pos + 14) as i16 as i32,
+			patches: patches
+		});
+	}
+
+	Ok(defs)
+}
+
+fn read_name(data : &[u8]) -> String {
+	data.iter()
+		.take_while(|&&c| c != 0)
+		.map(|&c| (c as char).to_ascii_uppercase())
+		.collect()
+}
+
+fn read_u16(data : &[u8], pos : usize) -> u16 {
+	data[pos] as u16 | (data[pos + 1] as u16) << 8
+}
+
+fn read_u32(data : &[u8], pos : usize) -> u32 {
+	read_u16(data, pos) as u32 | (read_u16(data, pos + 2) as u32) << 16
+}
@@ -0,0 +1,149 @@
+/// How a thing placed in a map looks when it is spawned: the sprite and
+/// frame of its first state, like the spawn states of mobjinfo.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ThingInfo {
+	pub sprite : &'static str,
+	pub frame : u8,
+	pub full_bright : bool
+}
+
+fn info(sprite : &'static str, frame : u8, full_bright : bool) -> ThingInfo {
+	ThingInfo {
+		sprite: sprite,
+		frame: frame,
+		full_bright: full_bright
+	}
+}
+
+/// Finds the spawn look of a thing type. Returns None for types that are
+/// not drawn, like player starts and teleport destinations, and for unknown
+/// types.
+pub fn thing_info(thing_type : u32) -> Option<ThingInfo> {
+	let value = match thing_type {
+		// Monsters
+		3004 => info("POSS", b'A', false),
+		9 => info("SPOS", b'A', false),
+		65 => info("CPOS", b'A', false),
+		3001 => info("TROO", b'A', false),
+		3002 | 58 => info("SARG", b'A', false),
+		3006 => info("SKUL", b'A', true),
+		3005 => info("HEAD", b'A', false),
+		69 => info("BOS2", b'A', false),
+		3003 => info("BOSS", b'A', false),
+		68 => info("BSPI", b'A', false),
+		71 => info("PAIN", b'A', false),
+		66 => info("SKEL", b'A', false),
+		67 => info("FATT", b'A', false),
+		64 => info("VILE", b'A', false),
+		16 => info("CYBR", b'A', false),
+		7 => info("SPID", b'A', false),
+		84 => info("SSWV", b'A', false),
+		72 => info("KEEN", b'A', false),
+		88 => info("BBRN", b'A', false),
+
+		// Weapons
+		2005 => info("CSAW", b'A', false),
+		2001 => info("SHOT", b'A', false),
+		82 => info("SGN2", b'A', false),
+		2002 => info("MGUN", b'A', false),
+		2003 => info("LAUN", b'A', false),
+		2004 => info("PLAS", b'A', false),
+		2006 => info("BFUG", b'A', false),
+
+		// Ammunition
+		2007 => info("CLIP", b'A', false),
+		2048 => info("AMMO", b'A', false),
+		2008 => info("SHEL", b'A', false),
+		2049 => info("SBOX", b'A', false),
+		2010 => info("ROCK", b'A', false),
+		2046 => info("BROK", b'A', false),
+		2047 => info("CELL", b'A', false),
+		17 => info("CELP", b'A', false),
+		8 => info("BPAK", b'A', false),
+
+		// Health, armor and powerups
+		2011 => info("STIM", b'A', false),
+		2012 => info("MEDI", b'A', false),
+		2014 => info("BON1", b'A', false),
+		2015 => info("BON2", b'A', false),
+		2018 => info("ARM1", b'A', false),
+		2019 => info("ARM2", b'A', false),
+		2013 => info("SOUL", b'A', true),
+		83 => info("MEGA", b'A', true),
+		2022 => info("PINV", b'A', true),
+		2023 => info("PSTR", b'A', true),
+		2024 => info("PINS", b'A', true),
+		2025 => info("SUIT", b'A', true),
+		2026 => info("PMAP", b'A', true),
+		2045 => info("PVIS", b'A', true),
+
+		// Keys
+		5 => info("BKEY", b'A', false),
+		6 => info("YKEY", b'A', false),
+		13 => info("RKEY", b'A', false),
+		40 => info("BSKU", b'A', false),
+		39 => info("YSKU", b'A', false),
+		38 => info("RSKU", b'A', false),
+
+		// Obstacles and lights
+		2035 => info("BAR1", b'A', false),
+		70 => info("FCAN", b'A', true),
+		2028 => info("COLU", b'A', true),
+		85 => info("TLMP", b'A', true),
+		86 => info("TLP2", b'A', true),
+		34 => info("CAND", b'A', true),
+		35 => info("CBRA", b'A', true),
+		44 => info("TBLU", b'A', true),
+		45 => info("TGRN", b'A', true),
+		46 => info("TRED", b'A', true),
+		55 => info("SMBT", b'A', true),
+		56 => info("SMGT", b'A', true),
+		57 => info("SMRT", b'A', true),
+		48 => info("ELEC", b'A', false),
+		30 => info("COL1", b'A', false),
+		31 => info("COL2", b'A', false),
+		32 => info("COL3", b'A', false),
+		33 => info("COL4", b'A', false),
+		36 => info("COL5", b'A', false),
+		37 => info("COL6", b'A', false),
+		41 => info("CEYE", b'A', true),
+		42 => info("FSKU", b'A', true),
+		43 => info("TRE1", b'A', false),
+		54 => info("TRE2", b'A', false),
+		47 => info("SMIT", b'A', false),
+		25 => info("POL1", b'A', false),
+		26 => info("POL6", b'A', false),
+		27 => info("POL4", b'A', false),
+		28 => info("POL2", b'A', false),
+		29 => info("POL3", b'A', false),
+
+		// Hanging decorations
+		49 | 63 => info("GOR1", b'A', false),
+		50 | 59 => info("GOR2", b'A', false),
+		51 | 61 => info("GOR3", b'A', false),
+		52 | 60 => info("GOR4", b'A', false),
+		53 | 62 => info("GOR5", b'A', false),
+		73 => info("HDB1", b'A', false),
+		74 => info("HDB2", b'A', false),
+		75 => info("HDB3", b'A', false),
+		76 => info("HDB4", b'A', false),
+		77 => info("HDB5", b'A', false),
+		78 => info("HDB6", b'A', false),
+
+		// Corpses and gore
+		15 => info("PLAY", b'N', false),
+		10 | 12 => info("PLAY", b'W', false),
+		18 => info("POSS", b'L', false),
+		19 => info("SPOS", b'L', false),
+		20 => info("TROO", b'M', false),
+		21 => info("SARG", b'N', false),
+		22 => info("HEAD", b'L', false),
+		24 => info("POL5", b'A', false),
+		79 => info("POB1", b'A', false),
+		80 => info("POB2", b'A', false),
+		81 => info("BRS1", b'A', false),
+
+		_ => return None
+	};
+	Some(value)
+}
@@ -4,6 +4,8 @@ use std::io::BufReader;
 use std::io::SeekFrom;
 use std::io::Seek;
 use std::io::Read;
+use std::sync::Mutex;
+use std::time::SystemTime;
 use byteorder::ReadBytesExt;
 use byteorder::LittleEndian;
 use byteorder::Error as ByteOrderError;
@@ -23,11 +25,18 @@ macro_rules! try_bo {
 		match $e {
 			Ok(value) => value,
 			Err(ByteOrderError::Io(error)) => return Err(WadError::IoFailure(error)),
-			_ => panic!("UnexpectedEOF")
+			// Truncated files show up while an editor is still saving them.
+			_ => return Err(WadError::IoFailure(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected end of file")))
 		}
 	}
 }
 
+// Lumps that belong to the map before them.
+const MAP_LUMPS : [&'static str; 11] = [
+	"THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS",
+	"NODES", "SECTORS", "REJECT", "BLOCKMAP", "BEHAVIOR"
+];
+
 pub type WadResult<T> = Result<T, WadError>;
 
 #[derive(Debug)]
@@ -85,365 +94,471 @@ impl LumpReader {
 	}
 }
 
+/// The section of the WAD a lump is in, given by the marker lumps.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Namespace {
+	Global,
+	Map,
+	Sprites,
+	Flats,
+	Patches
+}
+
+/// A directory entry of a WAD. The data is read when it is needed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Lump {
+	pub name : String,
+	pub pos : u64,
+	pub size : u64,
+	pub namespace : Namespace
+}
+
+/// A WAD file. Only the directory is read when it is opened, lumps and maps
+/// are read from the file on request. If the file is rewritten after that,
+/// the directory no longer matches it and reads fail until it is opened
+/// again.
 pub struct WadPackage {
-	maps : Vec<bsp::Map>,
+	file : Mutex<File>,
+	// Size and modification time of the file the directory was read from
+	stamp : (u64, Option<SystemTime>),
+	// Names of the maps, with the index of their marker lump
+	maps : Vec<(String, usize)>,
+	lumps : Vec<Lump>
 }
 
 impl WadPackage {
-	pub fn new(file : &mut File) -> WadResult<WadPackage> {
-		try_io!(file.seek(SeekFrom::Start(0)));
-		let mut reader = BufReader::new(file);
+	pub fn new(mut file : File) -> WadResult<WadPackage> {
+		let mut maps = Vec::<(String, usize)>::new();
+		let mut lumps = Vec::<Lump>::new();
+		let stamp = try_io!(file_stamp(&file));
+
+		{
+			try_io!(file.seek(SeekFrom::Start(0)));
+			let mut reader = BufReader::new(&mut file);
 
-		// Lets trust that this is validated before.
-		let mut signature = [0u8; 4];
-		try_io!(reader.read_exact(&mut signature[..]));
+			// Lets trust that this is validated before.
+			let mut signature = [0u8; 4];
+			try_io!(reader.read_exact(&mut signature[..]));
 
-		// Create the lump reader
-		let mut lump_reader = {
 			let num_lumps = try_bo!(reader.read_u32::<LittleEndian>());
 			let lump_pos = try_bo!(reader.read_u32::<LittleEndian>()) as u64;
-			LumpReader::new(lump_pos, num_lumps)
-		};
-
-		let mut package = WadPackage {
-			maps: Vec::<bsp::Map>::new()
-		};
+			lumps.reserve(num_lumps as usize);
+
+			let mut lump_reader = LumpReader::new(lump_pos, num_lumps);
+			let mut namespace = Namespace::Global;
+			let mut in_map = false;
+			while let Some(lump) = try!(lump_reader.get(&mut reader)) {
+				let name = lump_name(&lump.name);
+
+				let lump_namespace = if in_map && MAP_LUMPS.contains(&&name[..]) {
+					Namespace::Map
+				} else {
+					in_map = false;
+					match &name[..] {
+						"S_START" | "SS_START" => namespace = Namespace::Sprites,
+						"F_START" | "FF_START" => namespace = Namespace::Flats,
+						"P_START" | "PP_START" => namespace = Namespace::Patches,
+						"S_END" | "SS_END" | "F_END" | "FF_END" | "P_END" | "PP_END" => namespace = Namespace::Global,
+						_ => ()
+					}
+					if name.ends_with("_START") || name.ends_with("_END") { Namespace::Global } else { namespace }
+				};
+
+				if is_map_lump(&lump) {
+					in_map = true;
+					maps.push((name.clone(), lumps.len()));
+				}
 
-		while let Some(lump) = try!(lump_reader.get(&mut reader)) {
-			if is_map_lump(&lump) {
-				package.maps.push(try!(read_map(lump.name, &mut reader, &mut lump_reader)));
-			} else {
+				lumps.push(Lump {
+					name: name,
+					pos: lump.pos,
+					size: lump.size,
+					namespace: lump_namespace
+				});
 				lump_reader.next();
 			}
 		}
 
-		Ok(package)
+		Ok(WadPackage {
+			file: Mutex::new(file),
+			stamp: stamp,
+			maps: maps,
+			lumps: lumps
+		})
 	}
 
-	pub fn get_maps(&self) -> &[bsp::Map] {
-		&self.maps[..]
+	pub fn get_map_names(&self) -> Vec<&str> {
+		self.maps.iter().map(|&(ref name, _)| &name[..]).collect()
 	}
-}
 
-fn is_map_lump(lump : &LumpHeader) -> bool {
-	if lump.size != 0 {
-		return false;
+	pub fn has_map(&self, name : &str) -> bool {
+		self.maps.iter().any(|&(ref map_name, _)| map_name.eq_ignore_ascii_case(name))
 	}
 
-	// Doom 2 level format.
-	if lump.name[0] == 'M' as u8 && lump.name[1] == 'A' as u8 && lump.name[2] == 'P' as u8 {
-		if lump.name[3] < '0' as u8 || lump.name[3] > '9' as u8 {
-			return false;
+	/// Reads and decodes a map. Returns None if the package doesn't have it.
+	pub fn read_map(&self, name : &str) -> WadResult<Option<bsp::Map>> {
+		match self.maps.iter().find(|&&(ref map_name, _)| map_name.eq_ignore_ascii_case(name)) {
+			Some(&(_, index)) => self.read_map_at(index).map(Some),
+			None => Ok(None)
 		}
+	}
 
-		let mut map_num = lump.name[3] - '0' as u8;
+	pub fn get_lumps(&self) -> &[Lump] {
+		&self.lumps[..]
+	}
 
-		if lump.name[4] >= '0' as u8 || lump.name[4] <= '9' as u8 {
-			map_num *= 10u8;
-			map_num += lump.name[4] - '0' as u8
-		} else if lump.name[4] != 0 {
-			return false;
-		}
+	/// Index of the lump with the given name. Like in the game, the last one
+	/// wins.
+	pub fn find_lump(&self, name : &str) -> Option<usize> {
+		self.lumps.iter().rposition(|lump| lump.name.eq_ignore_ascii_case(name))
+	}
 
-		if map_num > 32u8 {
-			return false;
+	pub fn read_lump(&self, index : usize) -> WadResult<Vec<u8>> {
+		let lump = &self.lumps[index];
+		// A panic while reading leaves the file usable, since every read seeks.
+		let mut file = match self.file.lock() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner()
+		};
+		if try_io!(file_stamp(&file)) != self.stamp {
+			return Err(WadError::IoFailure(io::Error::new(io::ErrorKind::Other, "file changed since its directory was read")));
 		}
-
-		return true;
+		let mut data = vec![0u8; lump.size as usize];
+		try_io!(file.seek(SeekFrom::Start(lump.pos)));
+		try_io!(file.read_exact(&mut data[..]));
+		Ok(data)
 	}
 
-	if lump.name[0] == 'E' as u8 && lump.name[2] == 'M' as u8 {
-		if lump.name[1] < '0' as u8 || lump.name[1] > '4' as u8 {
-			return false;
-		}
-		if lump.name[3] < '0' as u8 || lump.name[3] > '9' as u8 {
-			return false;
+	/// Reads a map lump if it comes next after the marker. `next` is moved
+	/// past it when it is found.
+	fn read_map_lump<F>(&self, mandatory : bool, next : &mut usize, name : &'static str, f : F) -> WadResult<()>
+		where F : FnOnce(&Vec<u8>) -> WadResult<()> {
+
+		if *next < self.lumps.len() && self.lumps[*next].name == name {
+			let data = try!(self.read_lump(*next));
+			try!(f(&data));
+			*next += 1;
+			Ok(())
+		} else if mandatory {
+			Err(WadError::LumpMissing(name))
+		} else {
+			Ok(())
 		}
-
-		return true;
 	}
 
-	return false;
-}
+	fn read_map_at(&self, index : usize) -> WadResult<bsp::Map> {
+		let mut next = index + 1;
+
+		let mut level = bsp::Map {
+			name: self.lumps[index].name.clone(),
+			things: Vec::<bsp::Thing>::new(),
+			lines: Vec::<bsp::LineDef>::new(),
+			sides: Vec::<bsp::SideDef>::new(),
+			sectors: Vec::<bsp::Sector>::new(),
+			subsectors: Vec::<bsp::Subsector>::new(),
+			segs: Vec::<bsp::LineSegment>::new(),
+			nodes: Vec::<bsp::Node>::new(),
+			vertices: Vec::<bsp::Vertex>::new()
+		};
 
-fn read_map(name_bytes : [u8; 8], reader : &mut BufReader<&mut File>, lump_reader : &mut LumpReader) -> WadResult<bsp::Map> {
-	lump_reader.next();
+		try!(self.read_map_lump(false, &mut next, "THINGS", |data| {
+			let num = data.len() / 10;
+			if data.len() % 10 != 0 {
+				return Err(WadError::InvalidLump("THINGS"));
+			}
 
-	let mut name = String::new();
-	for c in &name_bytes {
-		if *c == 0 {
-			break;
-		}
-		name.push(*c as char);
-	}
+			level.things.reserve(num);
+			let mut reader = BufReader::new(&data[..]);
+
+			for _ in 0..num {
+				let x = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let y = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let angle = try_bo!(reader.read_i16::<LittleEndian>()) as i32;
+				let thing_type = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let flags = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				level.things.push(bsp::Thing {
+					x: x,
+					y: y,
+					angle: ((angle * 0x10000) / 360) as u16,
+					thing_type: thing_type,
+					flags: flags
+				});
+			}
+			Ok(())
+		}));
 
-	let mut level = bsp::Map {
-		name: name,
-		lines: Vec::<bsp::LineDef>::new(),
-		sides: Vec::<bsp::SideDef>::new(),
-		sectors: Vec::<bsp::Sector>::new(),
-		subsectors: Vec::<bsp::Subsector>::new(),
-		segs: Vec::<bsp::LineSegment>::new(),
-		nodes: Vec::<bsp::Node>::new(),
-		vertices: Vec::<bsp::Vertex>::new()
-	};
-
-	let mut tmp_buf = Vec::<u8>::new();
-
-	try!(read_lump(false, reader, lump_reader, &mut tmp_buf, "THINGS", |_| {
-		// TODO
-		Ok(())
-	}));
-
-	try!(read_lump(true, reader, lump_reader, &mut tmp_buf, "LINEDEFS", |data| {
-		let num = data.len() / 14;
-		if data.len() % 14 != 0 {
-			return Err(WadError::InvalidLump("LINEDEFS"));
-		}
+		try!(self.read_map_lump(true, &mut next, "LINEDEFS", |data| {
+			let num = data.len() / 14;
+			if data.len() % 14 != 0 {
+				return Err(WadError::InvalidLump("LINEDEFS"));
+			}
 
-		level.lines.reserve(num);
-		let mut reader = BufReader::new(&data[..]);
-
-		for _ in 0..num {
-			let v0 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let v1 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let flags = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let special_type = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let sector_tag = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let s0 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let s1 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			level.lines.push(bsp::LineDef {
-				v: [v0, v1],
-				flags: flags,
-				special_type: special_type,
-				sector_tag: sector_tag,
-				side: [s0, s1]
-			});
-		}
-		Ok(())
-	}));
+			level.lines.reserve(num);
+			let mut reader = BufReader::new(&data[..]);
+
+			for _ in 0..num {
+				let v0 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let v1 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let flags = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let special_type = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let sector_tag = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let s0 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let s1 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				level.lines.push(bsp::LineDef {
+					v: [v0, v1],
+					flags: flags,
+					special_type: special_type,
+					sector_tag: sector_tag,
+					side: [s0, s1]
+				});
+			}
+			Ok(())
+		}));
 
-	try!(read_lump(true, reader, lump_reader, &mut tmp_buf, "SIDEDEFS", |data| {
-		let num = data.len() / 30;
-		if data.len() % 30 != 0 {
-			return Err(WadError::InvalidLump("SIDEDEFS"));
-		}
+		try!(self.read_map_lump(true, &mut next, "SIDEDEFS", |data| {
+			let num = data.len() / 30;
+			if data.len() % 30 != 0 {
+				return Err(WadError::InvalidLump("SIDEDEFS"));
+			}
 
-		level.sides.reserve(num);
-		let mut reader = BufReader::new(&data[..]);
-
-		for _ in 0..num {
-			let x_offset = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let y_offset = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let mut upper_tex = [0u8; 8];
-			try_io!(reader.read_exact(&mut upper_tex));
-			let mut lower_tex = [0u8; 8];
-			try_io!(reader.read_exact(&mut lower_tex));
-			let mut mid_tex = [0u8; 8];
-			try_io!(reader.read_exact(&mut mid_tex));
-			let sector = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			level.sides.push(bsp::SideDef {
-				x_offset: x_offset,
-				y_offset: y_offset,
-				sector: sector,
-			});
-		}
-		Ok(())
-	}));
+			level.sides.reserve(num);
+			let mut reader = BufReader::new(&data[..]);
+
+			for _ in 0..num {
+				let x_offset = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let y_offset = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let mut upper_tex = [0u8; 8];
+				try_io!(reader.read_exact(&mut upper_tex));
+				let mut lower_tex = [0u8; 8];
+				try_io!(reader.read_exact(&mut lower_tex));
+				let mut mid_tex = [0u8; 8];
+				try_io!(reader.read_exact(&mut mid_tex));
+				let sector = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				level.sides.push(bsp::SideDef {
+					x_offset: x_offset,
+					y_offset: y_offset,
+					upper_texture: lump_name(&upper_tex),
+					lower_texture: lump_name(&lower_tex),
+					middle_texture: lump_name(&mid_tex),
+					sector: sector,
+				});
+			}
+			Ok(())
+		}));
 
-	try!(read_lump(true, reader, lump_reader, &mut tmp_buf, "VERTEXES", |data| {
-		let num = data.len() / 4;
-		if data.len() % 4 != 0 {
-			return Err(WadError::InvalidLump("VERTEXES"));
-		}
+		try!(self.read_map_lump(true, &mut next, "VERTEXES", |data| {
+			let num = data.len() / 4;
+			if data.len() % 4 != 0 {
+				return Err(WadError::InvalidLump("VERTEXES"));
+			}
 
-		level.lines.reserve(num);
-		let mut reader = BufReader::new(&data[..]);
+			level.lines.reserve(num);
+			let mut reader = BufReader::new(&data[..]);
 
-		for _ in 0..num {
-			let x = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let y = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			level.vertices.push(bsp::Vertex {
-				x: x,
-				y: y
-			});
-		}
-		Ok(())
-	}));
+			for _ in 0..num {
+				let x = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let y = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				level.vertices.push(bsp::Vertex {
+					x: x,
+					y: y
+				});
+			}
+			Ok(())
+		}));
 
-	try!(read_lump(true, reader, lump_reader, &mut tmp_buf, "SEGS", |data| {
-		let num = data.len() / 12;
-		if data.len() % 12 != 0 {
-			return Err(WadError::InvalidLump("SEGS"));
-		}
+		try!(self.read_map_lump(true, &mut next, "SEGS", |data| {
+			let num = data.len() / 12;
+			if data.len() % 12 != 0 {
+				return Err(WadError::InvalidLump("SEGS"));
+			}
 
-		level.lines.reserve(num);
-		let mut reader = BufReader::new(&data[..]);
-
-		for _ in 0..num {
-			let v0 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let v1 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let angle = try_bo!(reader.read_u16::<LittleEndian>());
-			let line = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let side = try_bo!(reader.read_u16::<LittleEndian>());
-			let offset = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			level.segs.push(bsp::LineSegment {
-				v: [v0, v1],
-				angle: angle,
-				line: line,
-				side: side,
-				offset: offset
-			});
-		}
-		Ok(())
-	}));
+			level.lines.reserve(num);
+			let mut reader = BufReader::new(&data[..]);
+
+			for _ in 0..num {
+				let v0 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let v1 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let angle = try_bo!(reader.read_u16::<LittleEndian>());
+				let line = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let side = try_bo!(reader.read_u16::<LittleEndian>());
+				let offset = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				level.segs.push(bsp::LineSegment {
+					v: [v0, v1],
+					angle: angle,
+					line: line,
+					side: side,
+					offset: offset
+				});
+			}
+			Ok(())
+		}));
 
-	try!(read_lump(true, reader, lump_reader, &mut tmp_buf, "SSECTORS", |data| {
-		let num = data.len() / 4;
-		if data.len() % 4 != 0 {
-			return Err(WadError::InvalidLump("SSECTORS"));
-		}
+		try!(self.read_map_lump(true, &mut next, "SSECTORS", |data| {
+			let num = data.len() / 4;
+			if data.len() % 4 != 0 {
+				return Err(WadError::InvalidLump("SSECTORS"));
+			}
 
-		level.lines.reserve(num);
-		let mut reader = BufReader::new(&data[..]);
+			level.lines.reserve(num);
+			let mut reader = BufReader::new(&data[..]);
 
-		for _ in 0..num {
-			let num_segs = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let first_seg = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			level.subsectors.push(bsp::Subsector {
-				num_segs: num_segs,
-				first_seg: first_seg
-			});
-		}
-		Ok(())
-	}));
+			for _ in 0..num {
+				let num_segs = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let first_seg = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				level.subsectors.push(bsp::Subsector {
+					num_segs: num_segs,
+					first_seg: first_seg
+				});
+			}
+			Ok(())
+		}));
 
-	try!(read_lump(true, reader, lump_reader, &mut tmp_buf, "NODES", |data| {
-		let num = data.len() / 28;
-		if data.len() % 28 != 0 {
-			return Err(WadError::InvalidLump("NODES"));
-		}
+		try!(self.read_map_lump(true, &mut next, "NODES", |data| {
+			let num = data.len() / 28;
+			if data.len() % 28 != 0 {
+				return Err(WadError::InvalidLump("NODES"));
+			}
+
+			level.lines.reserve(num);
+			let mut reader = BufReader::new(&data[..]);
+
+			for _ in 0..num {
+				let x = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let y = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let dx = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let dy = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let bounds0_top = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let bounds0_bottom = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let bounds0_left = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let bounds0_right = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let bounds1_top = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let bounds1_bottom = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let bounds1_left = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let bounds1_right = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let mut child0 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let mut child1 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+
+				if child0 & 0x8000u32 != 0u32 {
+					child0 = (child0 & 0x7FFFu32) | 0x80000000u32;
+				}
+
+				if child1 & 0x8000u32 != 0u32 {
+					child1 = (child1 & 0x7FFFu32) | 0x80000000u32;
+				}
 
-		level.lines.reserve(num);
-		let mut reader = BufReader::new(&data[..]);
-
-		for _ in 0..num {
-			let x = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let y = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let dx = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let dy = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let bounds0_top = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let bounds0_bottom = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let bounds0_left = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let bounds0_right = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let bounds1_top = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let bounds1_bottom = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let bounds1_left = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let bounds1_right = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let mut child0 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let mut child1 = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-
-			if child0 & 0x8000u32 != 0u32 {
-				child0 = (child0 & 0x7FFFu32) | 0x80000000u32;
+				level.nodes.push(bsp::Node {
+					x: x,
+					y: y,
+					dx: dx,
+					dy: dy,
+					bounds: [bsp::Bounds {
+						top: bounds0_top,
+						bottom: bounds0_bottom,
+						left: bounds0_left,
+						right: bounds0_right,
+					},
+					bsp::Bounds {
+						top: bounds1_top,
+						bottom: bounds1_bottom,
+						left: bounds1_left,
+						right: bounds1_right,
+					}],
+					child: [child0, child1]
+				});
 			}
+			Ok(())
+		}));
 
-			if child1 & 0x8000u32 != 0u32 {
-				child1 = (child1 & 0x7FFFu32) | 0x80000000u32;
+		try!(self.read_map_lump(true, &mut next, "SECTORS", |data| {
+			let num = data.len() / 26;
+			if data.len() % 26 != 0 {
+				return Err(WadError::InvalidLump("SECTORS"));
 			}
 
-			level.nodes.push(bsp::Node {
-				x: x,
-				y: y,
-				dx: dx,
-				dy: dy,
-				bounds: [bsp::Bounds {
-					top: bounds0_top,
-					bottom: bounds0_bottom,
-					left: bounds0_left,
-					right: bounds0_right,
-				},
-				bsp::Bounds {
-					top: bounds1_top,
-					bottom: bounds1_bottom,
-					left: bounds1_left,
-					right: bounds1_right,
-				}],
-				child: [child0, child1]
-			});
-		}
-		Ok(())
-	}));
+			level.lines.reserve(num);
+			let mut reader = BufReader::new(&data[..]);
+
+			for _ in 0..num {
+				let floor_height = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let ceiling_height = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
+				let mut floor_tex = [0u8; 8];
+				try_io!(reader.read_exact(&mut floor_tex));
+				let mut ceiling_tex = [0u8; 8];
+				try_io!(reader.read_exact(&mut ceiling_tex));
+				let light_level = (try_bo!(reader.read_u16::<LittleEndian>()) as u32) << 16;
+				let sector_type = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+				let tag = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
+
+				level.sectors.push(bsp::Sector {
+					floor_height: floor_height,
+					ceiling_height: ceiling_height,
+					floor_texture: lump_name(&floor_tex),
+					ceiling_texture: lump_name(&ceiling_tex),
+					light_level: light_level,
+					sector_type: sector_type,
+					tag: tag
+				});
+			}
+			Ok(())
+		}));
 
-	try!(read_lump(true, reader, lump_reader, &mut tmp_buf, "SECTORS", |data| {
-		let num = data.len() / 26;
-		if data.len() % 26 != 0 {
-			return Err(WadError::InvalidLump("SECTORS"));
-		}
+		Ok(level)
+	}
+}
 
-		level.lines.reserve(num);
-		let mut reader = BufReader::new(&data[..]);
-
-		for _ in 0..num {
-			let floor_height = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let ceiling_height = (try_bo!(reader.read_i16::<LittleEndian>()) as i32) << 16;
-			let mut floor_tex = [0u8; 8];
-			try_io!(reader.read_exact(&mut floor_tex));
-			let mut ceiling_tex = [0u8; 8];
-			try_io!(reader.read_exact(&mut ceiling_tex));
-			let light_level = (try_bo!(reader.read_u16::<LittleEndian>()) as u32) << 16;
-			let sector_type = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-			let tag = try_bo!(reader.read_u16::<LittleEndian>()) as u32;
-
-			level.sectors.push(bsp::Sector {
-				floor_height: floor_height,
-				ceiling_height: ceiling_height,
-				light_level: light_level,
-				sector_type: sector_type,
-				tag: tag
-			});
+fn lump_name(name_bytes : &[u8; 8]) -> String {
+	let mut name = String::new();
+	for c in name_bytes {
+		if *c == 0 {
+			break;
 		}
-		Ok(())
-	}));
+		name.push(*c as char);
+	}
+	name
+}
 
-	Ok(level)
+fn file_stamp(file : &File) -> io::Result<(u64, Option<SystemTime>)> {
+	let metadata = try!(file.metadata());
+	Ok((metadata.len(), metadata.modified().ok()))
 }
 
-fn read_lump<F>(mandatory : bool, reader : &mut BufReader<&mut File>, lump_reader : &mut LumpReader,
-	tmp_buf : &mut Vec<u8>, name : &'static str, f : F) -> WadResult<()>
-	where F : FnOnce(&Vec<u8>) -> WadResult<()> {
-
-	assert!(name.len() <= 8);
-	if let Some(lump) = try!(lump_reader.get(reader)) {
-		let mut b_iter = lump.name.iter();
-
-		let mut lump_found = true;
-		for a in name.bytes() {
-			if let Some(&b) = b_iter.next() {
-				if b == 0 {
-					break;
-				} else if a != b {
-					lump_found = false;
-					break;
-				}
-			} else {
-				break;
-			}
+fn is_map_lump(lump : &LumpHeader) -> bool {
+	if lump.size != 0 {
+		return false;
+	}
+
+	// Doom 2 level format.
+	if lump.name[0] == 'M' as u8 && lump.name[1] == 'A' as u8 && lump.name[2] == 'P' as u8 {
+		if lump.name[3] < '0' as u8 || lump.name[3] > '9' as u8 {
+			return false;
 		}
 
-		if lump_found {
-			try_io!(reader.seek(SeekFrom::Start(lump.pos)));
-			tmp_buf.resize(lump.size as usize, 0u8);
-			try_io!(reader.read_exact(&mut tmp_buf[..]));
+		let mut map_num = lump.name[3] - '0' as u8;
+
+		if lump.name[4] >= '0' as u8 || lump.name[4] <= '9' as u8 {
+			map_num *= 10u8;
+			map_num += lump.name[4] - '0' as u8
+		} else if lump.name[4] != 0 {
+			return false;
+		}
 
-			try!(f(tmp_buf));
-			lump_reader.next();
-			return Ok(());
+		if map_num > 32u8 {
+			return false;
 		}
+
+		return true;
 	}
 
-	if mandatory {
-		Err(WadError::LumpMissing(name))
-	} else {
-		Ok(())
+	if lump.name[0] == 'E' as u8 && lump.name[2] == 'M' as u8 {
+		if lump.name[1] < '0' as u8 || lump.name[1] > '4' as u8 {
+			return false;
+		}
+		if lump.name[3] < '0' as u8 || lump.name[3] > '9' as u8 {
+			return false;
+		}
+
+		return true;
 	}
+
+	return false;
 }
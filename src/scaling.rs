@@ -0,0 +1,193 @@
+/// How the screen is stretched to the window.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScaleFilter {
+	/// The nearest pixel, at any size
+	Nearest,
+	/// The nearest pixel, at whole multiples of the screen size only, so all
+	/// pixels have the same size. With aspect correction they are the whole
+	/// number of rows closest to 1.2 times their width tall.
+	Integer,
+	/// Blends the four nearest pixels
+	Smooth
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ScaleOptions {
+	pub filter : ScaleFilter,
+
+	/// Shows the pixels 1.2 times taller than wide, like a 320x200 screen
+	/// filled a 4:3 monitor.
+	pub aspect_correction : bool
+}
+
+impl Default for ScaleOptions {
+	fn default() -> ScaleOptions {
+		ScaleOptions {
+			filter: ScaleFilter::Nearest,
+			aspect_correction: true
+		}
+	}
+}
+
+// How much taller than wide the pixels of a 320x200 screen are on 4:3.
+const PIXEL_ASPECT : f32 = 1.2;
+
+/// The area of a window the screen is shown in, centered with black bars
+/// around it, as x, y, width and height.
+pub fn scaled_rect(width : usize, height : usize, window_width : usize, window_height : usize, options : &ScaleOptions)
+	-> (usize, usize, usize, usize) {
+
+	let aspect = if options.aspect_correction { PIXEL_ASPECT } else { 1.0 };
+	let full_height = height as f32 * aspect;
+
+	let (scaled_width, scaled_height) = match options.filter {
+		ScaleFilter::Integer => {
+			// The largest scale whose rows fit too.
+			let mut scale = (window_width / width).max(1);
+			while scale > 1 && height * integer_rows(scale, aspect) > window_height {
+				scale -= 1;
+			}
+			(width * scale, height * integer_rows(scale, aspect))
+		}
+		_ => {
+			let scale = (window_width as f32 / width as f32).min(window_height as f32 / full_height);
+			((width as f32 * scale).round() as usize, (full_height * scale).round() as usize)
+		}
+	};
+
+	let scaled_width = scaled_width.min(window_width);
+	let scaled_height = scaled_height.min(window_height);
+	((window_width - scaled_width) / 2, (window_height - scaled_height) / 2, scaled_width, scaled_height)
+}
+
+// The rows each pixel covers at an integer scale.
+fn integer_rows(scale : usize, aspect : f32) -> usize {
+	((scale as f32 * aspect).round() as usize).max(1)
+}
+
+/// Stretches `source`, `width` by `height` pixels of 4 bytes, into `rect` of
+/// `target`, and clears the rest of `target` to black.
+pub fn scale(source : &[[u8; 4]], width : usize, height : usize, target : &mut [u8], pitch : usize,
+	rect : (usize, usize, usize, usize), filter : ScaleFilter) {
+
+	let (left, top, scaled_width, scaled_height) = rect;
+	for (y, row) in target.chunks_mut(pitch).enumerate() {
+		if y < top || y >= top + scaled_height {
+			clear(row);
+			continue;
+		}
+
+		clear(&mut row[..left * 4]);
+		clear(&mut row[(left + scaled_width) * 4..]);
+		let row = &mut row[left * 4..(left + scaled_width) * 4];
+
+		// The position of the row in the screen, at the pixel centers
+		let sy = ((y - top) as f32 + 0.5) * height as f32 / scaled_height as f32 - 0.5;
+
+		match filter {
+			ScaleFilter::Nearest | ScaleFilter::Integer => {
+				let source_row = &source[((sy + 0.5) as usize).min(height - 1) * width..];
+				for (x, pixel) in row.chunks_mut(4).enumerate() {
+					let sx = x * width / scaled_width;
+					pixel.copy_from_slice(&source_row[sx]);
+				}
+			}
+			ScaleFilter::Smooth => {
+				let sy = sy.max(0.0);
+				let y0 = (sy as usize).min(height - 1);
+				let y1 = (y0 + 1).min(height - 1);
+				let fy = sy - y0 as f32;
+
+				for (x, pixel) in row.chunks_mut(4).enumerate() {
+					let sx = ((x as f32 + 0.5) * width as f32 / scaled_width as f32 - 0.5).max(0.0);
+					let x0 = (sx as usize).min(width - 1);
+					let x1 = (x0 + 1).min(width - 1);
+					let fx = sx - x0 as f32;
+
+					for i in 0..4 {
+						let top = source[y0 * width + x0][i] as f32 * (1.0 - fx) + source[y0 * width + x1][i] as f32 * fx;
+						let bottom = source[y1 * width + x0][i] as f32 * (1.0 - fx) + source[y1 * width + x1][i] as f32 * fx;
+						pixel[i] = (top * (1.0 - fy) + bottom * fy) as u8;
+					}
+				}
+			}
+		}
+	}
+}
+
+fn clear(pixels : &mut [u8]) {
+	for value in pixels.iter_mut() {
+		*value = 0;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn options(filter : ScaleFilter, aspect_correction : bool) -> ScaleOptions {
+		ScaleOptions { filter: filter, aspect_correction: aspect_correction }
+	}
+
+	#[test]
+	fn screens_are_letterboxed() {
+		let table = [
+			// 4:3 windows fit a corrected 320x200 screen exactly.
+			(640, 480, ScaleFilter::Nearest, true, (0, 0, 640, 480)),
+			(640, 480, ScaleFilter::Smooth, true, (0, 0, 640, 480)),
+			// Square pixels leave bars above and below.
+			(640, 480, ScaleFilter::Nearest, false, (0, 40, 640, 400)),
+			// Wide windows leave bars left and right.
+			(1920, 1080, ScaleFilter::Nearest, true, (240, 0, 1440, 1080)),
+			(1920, 1080, ScaleFilter::Smooth, false, (96, 0, 1728, 1080)),
+			// Integer scaling leaves bars on every side, and each pixel is 4
+			// columns wide and 5 rows tall.
+			(1920, 1080, ScaleFilter::Integer, true, (320, 40, 1280, 1000)),
+			(1920, 1080, ScaleFilter::Integer, false, (160, 40, 1600, 1000)),
+			(640, 480, ScaleFilter::Integer, true, (0, 40, 640, 400)),
+			// Windows smaller than the screen show part of it at 1.
+			(200, 100, ScaleFilter::Integer, true, (0, 0, 200, 100))
+		];
+		for &(window_width, window_height, filter, aspect_correction, rect) in table.iter() {
+			assert_eq!(scaled_rect(320, 200, window_width, window_height, &options(filter, aspect_correction)), rect,
+				"{:?} in {}x{}", filter, window_width, window_height);
+		}
+	}
+
+	// Scales a screen of the pixels given, one byte per pixel repeated four
+	// times, and returns the first byte of each target pixel.
+	fn scaled(source : &[u8], width : usize, rect : (usize, usize, usize, usize), window_width : usize, window_height : usize,
+		filter : ScaleFilter) -> Vec<u8> {
+
+		let source : Vec<[u8; 4]> = source.iter().map(|&value| [value; 4]).collect();
+		let mut target = vec![255u8; window_width * window_height * 4];
+		scale(&source, width, source.len() / width, &mut target, window_width * 4, rect, filter);
+		target.chunks(4).map(|pixel| pixel[0]).collect()
+	}
+
+	#[test]
+	fn nearest_pixels_are_repeated() {
+		// A 2x2 screen in the middle of a 6x4 window, 2 pixels per pixel.
+		let target = scaled(&[1, 2, 3, 4], 2, (1, 0, 4, 4), 6, 4, ScaleFilter::Nearest);
+		assert_eq!(target, vec![
+			0, 1, 1, 2, 2, 0,
+			0, 1, 1, 2, 2, 0,
+			0, 3, 3, 4, 4, 0,
+			0, 3, 3, 4, 4, 0
+		]);
+
+		// Integer scaling with aspect correction, 1 column and 2 rows per
+		// pixel, below a black row.
+		let target = scaled(&[1, 2, 3, 4], 2, (0, 1, 2, 4), 2, 5, ScaleFilter::Integer);
+		assert_eq!(target, vec![0, 0, 1, 2, 1, 2, 3, 4, 3, 4]);
+	}
+
+	#[test]
+	fn smooth_pixels_are_blended() {
+		let target = scaled(&[0, 200], 2, (0, 0, 4, 1), 4, 1, ScaleFilter::Smooth);
+		assert_eq!(target, vec![0, 50, 150, 200]);
+
+		let target = scaled(&[0, 200], 1, (0, 0, 1, 4), 1, 4, ScaleFilter::Smooth);
+		assert_eq!(target, vec![0, 50, 150, 200]);
+	}
+}
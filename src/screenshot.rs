@@ -0,0 +1,107 @@
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+use framebuffer::Framebuffer;
+use image::write_png_rgb;
+use image::write_pcx;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScreenshotFormat {
+	/// The colors as shown, with the palette and gamma applied
+	Png,
+	/// The palette indices with the shown palette, like the screenshots of
+	/// vanilla
+	Pcx
+}
+
+impl ScreenshotFormat {
+	fn extension(&self) -> &'static str {
+		match *self {
+			ScreenshotFormat::Png => "PNG",
+			ScreenshotFormat::Pcx => "PCX"
+		}
+	}
+}
+
+/// Saves the screen to the first free file of DOOM00, DOOM01 and so on in
+/// `directory`, like M_ScreenShot, and returns the path. Past DOOM99 the
+/// numbers get more digits instead of failing. The directory is created if
+/// it does not exist.
+pub fn save_screenshot(framebuffer : &Framebuffer, directory : &Path, format : ScreenshotFormat) -> io::Result<PathBuf> {
+	try!(fs::create_dir_all(directory));
+
+	let mut number = 0;
+	let (path, file) = loop {
+		let path = directory.join(format!("DOOM{:02}.{}", number, format.extension()));
+
+		// Only new files, so two screenshots never overwrite each other.
+		match OpenOptions::new().write(true).create_new(true).open(&path) {
+			Ok(file) => break (path, file),
+			Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => number += 1,
+			Err(err) => return Err(err)
+		}
+	};
+
+	let mut writer = BufWriter::new(file);
+	let width = framebuffer.width() as u32;
+	let height = framebuffer.height() as u32;
+	let palette = framebuffer.shown_palette();
+
+	match format {
+		ScreenshotFormat::Png => {
+			let mut rgb = Vec::<u8>::with_capacity(framebuffer.pixels().len() * 3);
+			for &index in framebuffer.pixels() {
+				let color = palette.colors[index as usize];
+				rgb.extend_from_slice(&[color.r, color.g, color.b]);
+			}
+			try!(write_png_rgb(&mut writer, width, height, &rgb));
+		}
+		ScreenshotFormat::Pcx => try!(write_pcx(&mut writer, width, height, framebuffer.pixels(), &palette))
+	}
+
+	Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env;
+	use std::process;
+	use super::*;
+
+	#[test]
+	fn screenshots_take_the_first_free_number() {
+		let directory = env::temp_dir().join(format!("doom-screenshots-{}", process::id())).join("shots");
+		let _ = fs::remove_dir_all(&directory);
+		let mut framebuffer = Framebuffer::headless(4, 2);
+		framebuffer.clear(9);
+
+		let first = save_screenshot(&framebuffer, &directory, ScreenshotFormat::Pcx).unwrap();
+		assert_eq!(first, directory.join("DOOM00.PCX"));
+
+		// Files that are there already are kept, whatever they hold.
+		fs::write(directory.join("DOOM02.PCX"), b"keep").unwrap();
+		let second = save_screenshot(&framebuffer, &directory, ScreenshotFormat::Pcx).unwrap();
+		let third = save_screenshot(&framebuffer, &directory, ScreenshotFormat::Pcx).unwrap();
+		assert_eq!(second, directory.join("DOOM01.PCX"));
+		assert_eq!(third, directory.join("DOOM03.PCX"));
+		assert_eq!(fs::read(directory.join("DOOM02.PCX")).unwrap(), b"keep");
+
+		// Each format counts on its own.
+		let png = save_screenshot(&framebuffer, &directory, ScreenshotFormat::Png).unwrap();
+		assert_eq!(png, directory.join("DOOM00.PNG"));
+
+		// Past 99 the numbers get longer.
+		for number in 4..100 {
+			fs::write(directory.join(format!("DOOM{:02}.PCX", number)), b"").unwrap();
+		}
+		let last = save_screenshot(&framebuffer, &directory, ScreenshotFormat::Pcx).unwrap();
+		assert_eq!(last, directory.join("DOOM100.PCX"));
+
+		let data = fs::read(&first).unwrap();
+		assert_eq!((data[0], data[8], data[10]), (0x0a, 3, 1));
+		let _ = fs::remove_dir_all(directory.parent().unwrap());
+	}
+}
@@ -0,0 +1,354 @@
+use std::f32;
+use std::sync::Arc;
+use audio::AudioBackend;
+use audio::OplMusic;
+use audio::Sound;
+
+/// Number of sound effect channels vanilla Doom mixes by default.
+pub const DEFAULT_SOUND_CHANNELS : usize = 8;
+
+// Sounds further away than this are not heard at all.
+const S_CLIPPING_DIST : i32 = 1200 << 16;
+// Sounds closer than this are played at full volume.
+const S_CLOSE_DIST : i32 = 160 << 16;
+const S_ATTENUATOR : i32 = (S_CLIPPING_DIST - S_CLOSE_DIST) >> 16;
+const S_STEREO_SWING : f32 = 96.0;
+
+/// Identifies the thing that plays a sound, and where it is. A new sound from
+/// the same origin replaces the old one, like vanilla does.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SoundOrigin {
+	pub id : u32,
+	pub pos : (i32, i32)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Listener {
+	pub pos : (i32, i32),
+	pub angle : u16
+}
+
+struct SoundChannel {
+	sound : Arc<Sound>,
+	priority : u32,
+	origin : Option<SoundOrigin>,
+
+	// Position in the sound in 16.16 fixed point
+	pos : u64,
+	step : u64,
+
+	volume : i32,
+	separation : i32
+}
+
+/// Mixes the sound effects and the music, and feeds the result to an
+/// `AudioBackend`.
+pub struct SoundSystem {
+	backend : Box<dyn AudioBackend>,
+	channels : Vec<Option<SoundChannel>>,
+	listener : Listener,
+	music : Option<OplMusic>,
+
+	// Volumes in the 0-15 range of the vanilla menu.
+	sfx_volume : i32,
+	music_volume : u32,
+
+	mix_buffer : Vec<i32>,
+	music_buffer : Vec<i16>,
+	out_buffer : Vec<i16>
+}
+
+impl SoundSystem {
+	pub fn new(backend : Box<dyn AudioBackend>, num_channels : usize) -> SoundSystem {
+		let mut channels = Vec::<Option<SoundChannel>>::with_capacity(num_channels);
+		for _ in 0..num_channels {
+			channels.push(None);
+		}
+
+		SoundSystem {
+			backend: backend,
+			channels: channels,
+			listener: Listener {
+				pos: (0, 0),
+				angle: 0
+			},
+			music: None,
+			sfx_volume: 8,
+			music_volume: 8,
+			mix_buffer: Vec::<i32>::new(),
+			music_buffer: Vec::<i16>::new(),
+			out_buffer: Vec::<i16>::new()
+		}
+	}
+
+	pub fn sample_rate(&self) -> u32 {
+		self.backend.sample_rate()
+	}
+
+	pub fn num_channels(&self) -> usize {
+		self.channels.len()
+	}
+
+	pub fn set_sfx_volume(&mut self, volume : u32) {
+		self.sfx_volume = if volume > 15 { 15 } else { volume as i32 };
+	}
+
+	pub fn set_music_volume(&mut self, volume : u32) {
+		self.music_volume = if volume > 15 { 15 } else { volume };
+		if let Some(ref mut music) = self.music {
+			music.set_volume(self.music_volume * 8);
+		}
+	}
+
+	pub fn set_listener(&mut self, pos : (i32, i32), angle : u16) {
+		self.listener = Listener {
+			pos: pos,
+			angle: angle
+		};
+	}
+
+	/// Starts a sound. Lower priority values are more important. Sounds
+	/// without an origin are played at full volume in the center. Returns
+	/// false if the sound was not audible or there was no channel for it.
+	pub fn start_sound(&mut self, sound : Arc<Sound>, priority : u32, origin : Option<SoundOrigin>) -> bool {
+		let (volume, separation) = match origin {
+			Some(origin) => match self.adjust_sound_params(origin.pos) {
+				Some(value) => value,
+				None => return false
+			},
+			None => (self.sfx_volume * 8, 128)
+		};
+
+		// Only one sound per origin
+		if let Some(origin) = origin {
+			self.stop_sound(origin.id);
+		}
+
+		let channel = match self.find_channel(priority) {
+			Some(value) => value,
+			None => return false
+		};
+
+		let step = ((sound.sample_rate as u64) << 16) / self.backend.sample_rate() as u64;
+		self.channels[channel] = Some(SoundChannel {
+			sound: sound,
+			priority: priority,
+			origin: origin,
+			pos: 0,
+			step: step,
+			volume: volume,
+			separation: separation
+		});
+
+		true
+	}
+
+	pub fn stop_sound(&mut self, origin_id : u32) {
+		for channel in self.channels.iter_mut() {
+			let matches = match *channel {
+				Some(SoundChannel { origin: Some(origin), .. }) => origin.id == origin_id,
+				_ => false
+			};
+			if matches {
+				*channel = None;
+			}
+		}
+	}
+
+	/// Moves the origin of the sounds it is playing.
+	pub fn move_origin(&mut self, origin_id : u32, pos : (i32, i32)) {
+		for channel in self.channels.iter_mut() {
+			if let Some(ref mut channel) = *channel {
+				if let Some(ref mut origin) = channel.origin {
+					if origin.id == origin_id {
+						origin.pos = pos;
+					}
+				}
+			}
+		}
+	}
+
+	pub fn is_playing(&self, origin_id : u32) -> bool {
+		self.channels.iter().any(|channel| match *channel {
+			Some(SoundChannel { origin: Some(origin), .. }) => origin.id == origin_id,
+			_ => false
+		})
+	}
+
+	pub fn play_music(&mut self, mut music : OplMusic, looping : bool) {
+		music.set_looping(looping);
+		music.set_volume(self.music_volume * 8);
+		self.music = Some(music);
+	}
+
+	pub fn stop_music(&mut self) {
+		self.music = None;
+	}
+
+	/// Mixes as much sound as the backend wants. Call this once per frame.
+	pub fn update(&mut self) {
+		// Positional sounds follow the listener.
+		for i in 0..self.channels.len() {
+			let origin = match self.channels[i] {
+				Some(ref channel) => channel.origin,
+				None => continue
+			};
+			if let Some(origin) = origin {
+				match self.adjust_sound_params(origin.pos) {
+					Some((volume, separation)) => {
+						let channel = self.channels[i].as_mut().unwrap();
+						channel.volume = volume;
+						channel.separation = separation;
+					}
+					None => self.channels[i] = None
+				}
+			}
+		}
+
+		let frames = self.backend.frames_needed();
+		if frames == 0 {
+			return;
+		}
+		self.mix(frames);
+		self.backend.write(&self.out_buffer[..frames * 2]);
+	}
+
+	fn mix(&mut self, frames : usize) {
+		self.mix_buffer.clear();
+		self.mix_buffer.resize(frames * 2, 0);
+
+		for channel in self.channels.iter_mut() {
+			let finished = match *channel {
+				Some(ref mut channel) => {
+					let left_volume = ((254 - channel.separation) * channel.volume) / 127;
+					let right_volume = (channel.separation * channel.volume) / 127;
+					let samples = &channel.sound.samples;
+
+					let mut finished = false;
+					for frame in self.mix_buffer.chunks_mut(2) {
+						let index = (channel.pos >> 16) as usize;
+						if index >= samples.len() {
+							finished = true;
+							break;
+						}
+
+						// Unsigned 8-bit to signed 16-bit, scaled by the 0-254 channel volume.
+						let sample = (samples[index] as i32 - 128) << 8;
+						frame[0] += (sample * left_volume) >> 8;
+						frame[1] += (sample * right_volume) >> 8;
+						channel.pos += channel.step;
+					}
+					finished || (channel.pos >> 16) as usize >= samples.len()
+				}
+				None => false
+			};
+			if finished {
+				*channel = None;
+			}
+		}
+
+		self.music_buffer.clear();
+		self.music_buffer.resize(frames * 2, 0);
+		if let Some(ref mut music) = self.music {
+			music.render(&mut self.music_buffer[..]);
+		}
+
+		self.out_buffer.clear();
+		for (i, sample) in self.mix_buffer.iter().enumerate() {
+			let value = sample + self.music_buffer[i] as i32;
+			self.out_buffer.push(if value > 32767 { 32767 } else if value < -32768 { -32768 } else { value as i16 });
+		}
+	}
+
+	/// Picks the channel for a new sound. Free channels are used first, then
+	/// the ones playing less important sounds.
+	fn find_channel(&self, priority : u32) -> Option<usize> {
+		for (i, channel) in self.channels.iter().enumerate() {
+			if channel.is_none() {
+				return Some(i);
+			}
+		}
+
+		for (i, channel) in self.channels.iter().enumerate() {
+			if let Some(ref channel) = *channel {
+				if channel.priority >= priority {
+					return Some(i);
+				}
+			}
+		}
+
+		None
+	}
+
+	/// Computes the volume (0-127) and stereo separation (0-255, 128 is the
+	/// center) of a sound like S_AdjustSoundParams does. Returns None if the
+	/// sound is too far away to be heard.
+	fn adjust_sound_params(&self, pos : (i32, i32)) -> Option<(i32, i32)> {
+		// In i64, as the distance between far apart points overflows i32.
+		let dx = pos.0 as i64 - self.listener.pos.0 as i64;
+		let dy = pos.1 as i64 - self.listener.pos.1 as i64;
+		let adx = dx.abs();
+		let ady = dy.abs();
+
+		let approx_dist = adx + ady - ((if adx < ady { adx } else { ady }) >> 1);
+		if approx_dist > S_CLIPPING_DIST as i64 {
+			return None;
+		}
+		let approx_dist = approx_dist as i32;
+
+		let sfx_volume = self.sfx_volume * 8;
+
+		// Angle of the sound relative to the view direction
+		let source_angle = ((dy as f32).atan2(dx as f32) / (f32::consts::PI * 2f32) * (0x10000 as f32)) as i32 as u16;
+		let angle = source_angle.wrapping_sub(self.listener.angle);
+		let fangle = (angle as f32) / (0x10000 as f32) * f32::consts::PI * 2f32;
+		let separation = 128 - (S_STEREO_SWING * fangle.sin()) as i32;
+
+		let volume = if approx_dist < S_CLOSE_DIST {
+			sfx_volume
+		} else {
+			(sfx_volume * ((S_CLIPPING_DIST - approx_dist) >> 16)) / S_ATTENUATOR
+		};
+
+		if volume > 0 {
+			Some((volume, separation))
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct NullBackend;
+
+	impl AudioBackend for NullBackend {
+		fn sample_rate(&self) -> u32 {
+			11025
+		}
+
+		fn frames_needed(&mut self) -> usize {
+			0
+		}
+
+		fn write(&mut self, _samples : &[i16]) {
+		}
+	}
+
+	#[test]
+	fn far_sounds_are_not_heard() {
+		let mut sound = SoundSystem::new(Box::new(NullBackend), DEFAULT_SOUND_CHANNELS);
+		sound.set_listener((::std::i32::MIN, ::std::i32::MAX), 0);
+		assert_eq!(sound.adjust_sound_params((::std::i32::MAX, ::std::i32::MIN)), None);
+		assert_eq!(sound.adjust_sound_params((0, 0)), None);
+	}
+
+	#[test]
+	fn close_sounds_are_at_full_volume() {
+		let mut sound = SoundSystem::new(Box::new(NullBackend), DEFAULT_SOUND_CHANNELS);
+		sound.set_listener((64 << 16, 0), 0);
+		assert_eq!(sound.adjust_sound_params((128 << 16, 0)), Some((64, 128)));
+	}
+}
@@ -1,7 +1,9 @@
+use std::collections::VecDeque;
 use std::slice::Iter;
 use sdl2;
 use sdl2::SdlResult;
 use framebuffer::Framebuffer;
+use audio::SdlAudioBackend;
 
 pub use sdl2::keyboard::Keycode;
 
@@ -11,9 +13,21 @@ pub enum KeyEvent {
 	Up(Keycode),
 }
 
+enum Backend {
+	Sdl {
+		sdl_ctx : sdl2::Sdl,
+		video_ctx : sdl2::VideoSubsystem,
+		event_pump : sdl2::EventPump
+	},
+
+	// The key events of each frame still to come
+	Headless {
+		script : VecDeque<Vec<KeyEvent>>
+	}
+}
+
 pub struct System {
-	video_ctx : sdl2::VideoSubsystem,
-	event_pump : sdl2::EventPump,
+	backend : Backend,
 	key_events : Vec<KeyEvent>
 }
 
@@ -24,14 +38,55 @@ impl System {
 		let event_pump = try!(sdl_ctx.event_pump());
 
 		Ok(System {
-			video_ctx: video_ctx,
-			event_pump: event_pump,
+			backend: Backend::Sdl {
+				sdl_ctx: sdl_ctx,
+				video_ctx: video_ctx,
+				event_pump: event_pump
+			},
 			key_events: Vec::<KeyEvent>::new()
 		})
 	}
 
-	pub fn create_framebuffer<'a>(&self, width : u32, height : u32) -> SdlResult<Framebuffer<'a>> {
-		return Framebuffer::new(&self.video_ctx, width, height);
+	/// A system without SDL, for tests and servers. Each call to
+	/// `handle_events` takes the key events of the next frame from the
+	/// script, and quits when the script runs out. Framebuffers are off
+	/// screen, and there is no audio.
+	pub fn headless(script : Vec<Vec<KeyEvent>>) -> System {
+		System {
+			backend: Backend::Headless {
+				script: script.into_iter().collect()
+			},
+			key_events: Vec::<KeyEvent>::new()
+		}
+	}
+
+	pub fn is_headless(&self) -> bool {
+		match self.backend {
+			Backend::Sdl { .. } => false,
+			Backend::Headless { .. } => true
+		}
+	}
+
+	/// Opens the window, showing a screen of `width` by `height` pixels
+	/// scaled to it, and waiting for the display refresh when presenting with
+	/// `vsync`. Headless systems only make the screen.
+	pub fn create_framebuffer<'a>(&self, window_width : u32, window_height : u32, width : u32, height : u32, vsync : bool)
+		-> SdlResult<Framebuffer<'a>> {
+
+		match self.backend {
+			Backend::Sdl { ref video_ctx, .. } => Framebuffer::new(video_ctx, window_width, window_height, width, height, vsync),
+			Backend::Headless { .. } => Ok(Framebuffer::headless(width, height))
+		}
+	}
+
+	pub fn create_audio_backend(&self, sample_rate : u32) -> SdlResult<SdlAudioBackend> {
+		match self.backend {
+			Backend::Sdl { ref sdl_ctx, .. } => {
+				let audio_ctx = try!(sdl_ctx.audio());
+				SdlAudioBackend::new(&audio_ctx, sample_rate)
+			}
+			Backend::Headless { .. } => Err(sdl2::ErrorMessage("No audio without SDL".to_string()))
+		}
 	}
 
 	pub fn handle_events(&mut self) -> bool {
@@ -39,22 +94,38 @@ impl System {
 
 		self.key_events.clear();
 
-		for event in self.event_pump.poll_iter() {
-			match event {
-				Event::Quit {..} => return false,
-				Event::KeyDown { repeat: false, keycode: Some(code), .. } => self.key_events.push(KeyEvent::Down(code)),
-				Event::KeyUp { repeat: false, keycode: Some(code), .. } => self.key_events.push(KeyEvent::Up(code)),
-				_ => ()
+		match self.backend {
+			Backend::Sdl { ref mut event_pump, .. } => {
+				for event in event_pump.poll_iter() {
+					match event {
+						Event::Quit {..} => return false,
+						Event::KeyDown { repeat: false, keycode: Some(code), .. } => self.key_events.push(KeyEvent::Down(code)),
+						Event::KeyUp { repeat: false, keycode: Some(code), .. } => self.key_events.push(KeyEvent::Up(code)),
+						_ => ()
+					}
+				}
+				true
+			}
+			Backend::Headless { ref mut script } => {
+				match script.pop_front() {
+					Some(events) => {
+						self.key_events = events;
+						true
+					}
+					None => false
+				}
 			}
 		}
-		true
 	}
 
 	pub fn key_events(&self) -> Iter<KeyEvent> {
 		self.key_events.iter()
 	}
 
-	pub fn present(&self, fb : &Framebuffer) -> bool {
-		fb.present(&self.event_pump)
+	pub fn present(&self, fb : &mut Framebuffer) -> bool {
+		match self.backend {
+			Backend::Sdl { ref event_pump, .. } => fb.present(Some(event_pump)),
+			Backend::Headless { .. } => fb.present(None)
+		}
 	}
 }
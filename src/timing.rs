@@ -0,0 +1,127 @@
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Game tics per second, like TICRATE.
+pub const TIC_RATE : u32 = 35;
+
+// The most tics run in one frame. After a longer stall the clock skips the
+// time instead of running the game fast to catch up.
+const MAX_CATCH_UP_TICS : u32 = 8;
+
+/// Runs the game at 35 tics per second however often frames are drawn. Each
+/// frame runs the tics that are due, then draws the game between the last
+/// two tics, so the game stays tic exact while motion is smooth at any frame
+/// rate.
+pub struct TicClock {
+	// When tic 0 was due, or None to run one tic per frame
+	start : Option<Instant>,
+	tics : u64
+}
+
+impl TicClock {
+	/// A clock that follows the real time.
+	pub fn new() -> TicClock {
+		TicClock {
+			start: Some(Instant::now()),
+			tics: 0
+		}
+	}
+
+	/// A clock that runs one tic per frame, whatever the time, for headless
+	/// runs that have to give the same result every time.
+	pub fn fixed() -> TicClock {
+		TicClock {
+			start: None,
+			tics: 0
+		}
+	}
+
+	/// The number of tics to run before drawing the next frame.
+	pub fn tics_due(&mut self) -> u32 {
+		let start = match self.start {
+			Some(value) => value,
+			None => {
+				self.tics += 1;
+				return 1;
+			}
+		};
+
+		let due = tic_at(start.elapsed());
+		let mut tics = due.saturating_sub(self.tics);
+		if tics > MAX_CATCH_UP_TICS as u64 {
+			tics = MAX_CATCH_UP_TICS as u64;
+			let skipped = due - self.tics - tics;
+			self.start = Some(start + tic_duration(skipped));
+		}
+
+		self.tics += tics;
+		tics as u32
+	}
+
+	/// How far the time is from the last tic run to the next one, from 0.0
+	/// to 1.0. Frames show the game this far from the state before the last
+	/// tic to the state after it.
+	pub fn fraction(&self) -> f32 {
+		let start = match self.start {
+			Some(value) => value,
+			None => return 1.0
+		};
+
+		let since_tic = start.elapsed().checked_sub(tic_duration(self.tics)).unwrap_or(Duration::from_secs(0));
+		(duration_secs(since_tic) * TIC_RATE as f64).min(1.0) as f32
+	}
+
+	/// The number of tics run so far.
+	pub fn tics(&self) -> u64 {
+		self.tics
+	}
+}
+
+/// Waits between frames so they are not drawn more often than a cap.
+pub struct FrameLimiter {
+	frame_time : Option<Duration>,
+	next_frame : Instant
+}
+
+impl FrameLimiter {
+	/// A limiter to `fps` frames per second, or no limit for None.
+	pub fn new(fps : Option<u32>) -> FrameLimiter {
+		FrameLimiter {
+			frame_time: fps.map(|fps| Duration::from_secs(1) / fps.max(1)),
+			next_frame: Instant::now()
+		}
+	}
+
+	/// Sleeps until the next frame is due.
+	pub fn wait(&mut self) {
+		let frame_time = match self.frame_time {
+			Some(value) => value,
+			None => return
+		};
+
+		let now = Instant::now();
+		if self.next_frame > now {
+			thread::sleep(self.next_frame - now);
+			self.next_frame += frame_time;
+		} else {
+			// Late frames don't make the next ones come sooner.
+			self.next_frame = now + frame_time;
+		}
+	}
+}
+
+// The last tic that is due after `elapsed`.
+fn tic_at(elapsed : Duration) -> u64 {
+	let nanos = elapsed.as_secs() as u128 * 1000000000 + elapsed.subsec_nanos() as u128;
+	(nanos * TIC_RATE as u128 / 1000000000) as u64
+}
+
+fn tic_duration(tics : u64) -> Duration {
+	let nanos = tics as u128 * 1000000000 / TIC_RATE as u128;
+	Duration::new((nanos / 1000000000) as u64, (nanos % 1000000000) as u32)
+}
+
+fn duration_secs(duration : Duration) -> f64 {
+	duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1000000000.0
+}